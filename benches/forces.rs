@@ -0,0 +1,29 @@
+//! Serial against parallel direct-sum force evaluation
+//!
+//! Run with `cargo bench --bench forces`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use std::hint::black_box;
+use threebody_sim::forces::{direct_accelerations, parallel_direct_accelerations};
+use threebody_sim::generators::random_cluster;
+use threebody_sim::units::{ASTRONOMICAL_UNIT, SOLAR_MASS};
+use threebody_sim::Softening;
+
+const G: f64 = 6.67430e-11;
+
+fn direct_sum(c: &mut Criterion) {
+    let mut group = c.benchmark_group("direct_sum");
+    for n in [100, 500, 1000, 4000] {
+        let mut bodies = random_cluster(n, 1.0, 17, SOLAR_MASS, ASTRONOMICAL_UNIT).bodies;
+        group.bench_with_input(BenchmarkId::new("serial", n), &n, |b, _| {
+            b.iter(|| direct_accelerations(black_box(&mut bodies), G, Softening::None))
+        });
+        group.bench_with_input(BenchmarkId::new("parallel", n), &n, |b, _| {
+            b.iter(|| parallel_direct_accelerations(black_box(&mut bodies), G, Softening::None, 0))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, direct_sum);
+criterion_main!(benches);
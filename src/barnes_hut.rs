@@ -0,0 +1,304 @@
+//! Octree for the Barnes-Hut force approximation
+//!
+//! The tree is rebuilt from scratch for every force evaluation. Each cell
+//! stores the total mass and centre of mass of the bodies inside it, and
+//! the bodies themselves are kept grouped by cell in one index array so a
+//! cell refers to them as a contiguous range.
+
+use crate::body::Body;
+use crate::softening::Softening;
+
+/// Deepest level a cell may be split to; bodies still sharing a cell
+/// there (e.g. at identical positions) are summed directly
+const MAX_DEPTH: usize = 64;
+
+/// Marks a missing child cell
+const NO_CHILD: u32 = u32::MAX;
+
+/// One cubic cell of the octree
+struct Node {
+    half_size: f64,
+    mass: f64,
+    center_of_mass: [f64; 3],
+    /// Range of `Octree::order` holding the bodies in this cell
+    start: usize,
+    end: usize,
+    /// Child cells by octant; all `NO_CHILD` for a leaf
+    children: [u32; 8],
+}
+
+impl Node {
+    fn is_leaf(&self) -> bool {
+        self.children.iter().all(|&child| child == NO_CHILD)
+    }
+}
+
+/// A Barnes-Hut octree over a set of bodies
+pub struct Octree {
+    nodes: Vec<Node>,
+    /// Body indices grouped so that every cell covers a contiguous range
+    order: Vec<usize>,
+    /// Position of each body within `order`
+    rank: Vec<usize>,
+}
+
+/// Octant of a position relative to a cell centre, one bit per axis
+fn octant(position: [f64; 3], center: [f64; 3]) -> usize {
+    (position[0] >= center[0]) as usize
+        | ((position[1] >= center[1]) as usize) << 1
+        | ((position[2] >= center[2]) as usize) << 2
+}
+
+impl Octree {
+    /// Build the tree for the current body positions
+    pub fn build(bodies: &[Body]) -> Self {
+        let n = bodies.len();
+        let mut tree = Octree {
+            nodes: Vec::with_capacity(2 * n),
+            order: (0..n).collect(),
+            rank: vec![0; n],
+        };
+        if n == 0 {
+            return tree;
+        }
+
+        // Smallest cube around all bodies
+        let mut min = [f64::INFINITY; 3];
+        let mut max = [f64::NEG_INFINITY; 3];
+        for body in bodies {
+            for k in 0..3 {
+                min[k] = min[k].min(body.position[k]);
+                max[k] = max[k].max(body.position[k]);
+            }
+        }
+        let center = [
+            0.5 * (min[0] + max[0]),
+            0.5 * (min[1] + max[1]),
+            0.5 * (min[2] + max[2]),
+        ];
+        let half_size = (0..3).map(|k| 0.5 * (max[k] - min[k])).fold(0.0, f64::max);
+
+        let mut order = std::mem::take(&mut tree.order);
+        tree.build_node(bodies, &mut order, 0, n, center, half_size, 0);
+        for (position, &body) in order.iter().enumerate() {
+            tree.rank[body] = position;
+        }
+        tree.order = order;
+        tree
+    }
+
+    /// Add the cell covering `order[start..end]` and its subtree
+    #[allow(clippy::too_many_arguments)]
+    fn build_node(
+        &mut self,
+        bodies: &[Body],
+        order: &mut [usize],
+        start: usize,
+        end: usize,
+        center: [f64; 3],
+        half_size: f64,
+        depth: usize,
+    ) -> u32 {
+        let mut mass = 0.0;
+        let mut weighted = [0.0; 3];
+        for &i in &order[start..end] {
+            mass += bodies[i].mass;
+            for k in 0..3 {
+                weighted[k] += bodies[i].mass * bodies[i].position[k];
+            }
+        }
+        let center_of_mass = if mass > 0.0 {
+            [weighted[0] / mass, weighted[1] / mass, weighted[2] / mass]
+        } else {
+            center
+        };
+
+        let idx = self.nodes.len();
+        self.nodes.push(Node {
+            half_size,
+            mass,
+            center_of_mass,
+            start,
+            end,
+            children: [NO_CHILD; 8],
+        });
+
+        if end - start > 1 && depth < MAX_DEPTH && half_size > 0.0 {
+            order[start..end].sort_unstable_by_key(|&i| octant(bodies[i].position, center));
+
+            let child_half = 0.5 * half_size;
+            let mut children = [NO_CHILD; 8];
+            let mut child_start = start;
+            for (oct, child) in children.iter_mut().enumerate() {
+                let mut child_end = child_start;
+                while child_end < end && octant(bodies[order[child_end]].position, center) == oct {
+                    child_end += 1;
+                }
+                if child_end > child_start {
+                    let mut child_center = center;
+                    for (k, c) in child_center.iter_mut().enumerate() {
+                        *c += if oct & (1 << k) != 0 { child_half } else { -child_half };
+                    }
+                    *child = self.build_node(
+                        bodies,
+                        order,
+                        child_start,
+                        child_end,
+                        child_center,
+                        child_half,
+                        depth + 1,
+                    );
+                }
+                child_start = child_end;
+            }
+            self.nodes[idx].children = children;
+        }
+
+        idx as u32
+    }
+
+    /// Replace every body's acceleration with the tree approximation
+    ///
+    /// `bodies` must be the slice the tree was built from.
+    pub fn accelerations(&self, bodies: &mut [Body], g: f64, softening: Softening, opening_angle: f64) {
+        let mut stack = Vec::with_capacity(8 * MAX_DEPTH);
+        let accelerations: Vec<[f64; 3]> = (0..bodies.len())
+            .map(|i| self.acceleration_on(i, bodies, g, softening, opening_angle, &mut stack))
+            .collect();
+        for (body, acceleration) in bodies.iter_mut().zip(accelerations) {
+            body.acceleration = acceleration;
+        }
+    }
+
+    /// Acceleration of body `i`, opening cells that look too large from it
+    pub(crate) fn acceleration_on(
+        &self,
+        i: usize,
+        bodies: &[Body],
+        g: f64,
+        softening: Softening,
+        opening_angle: f64,
+        stack: &mut Vec<u32>,
+    ) -> [f64; 3] {
+        let position = bodies[i].position;
+        let rank = self.rank[i];
+        let theta_squared = opening_angle * opening_angle;
+        let mut acceleration = [0.0; 3];
+        let mut pull = |source: [f64; 3], mass: f64| {
+            let d = [
+                source[0] - position[0],
+                source[1] - position[1],
+                source[2] - position[2],
+            ];
+            let factor = g * mass * softening.force_factor(d[0] * d[0] + d[1] * d[1] + d[2] * d[2]);
+            for k in 0..3 {
+                acceleration[k] += factor * d[k];
+            }
+        };
+
+        if self.nodes.is_empty() {
+            return acceleration;
+        }
+        stack.clear();
+        stack.push(0);
+        while let Some(idx) = stack.pop() {
+            let node = &self.nodes[idx as usize];
+            if node.is_leaf() {
+                for &j in &self.order[node.start..node.end] {
+                    if j != i {
+                        pull(bodies[j].position, bodies[j].mass);
+                    }
+                }
+                continue;
+            }
+
+            // A cell far enough away acts as a point mass, but never the
+            // cell holding the body itself
+            let d = [
+                node.center_of_mass[0] - position[0],
+                node.center_of_mass[1] - position[1],
+                node.center_of_mass[2] - position[2],
+            ];
+            let distance_squared = d[0] * d[0] + d[1] * d[1] + d[2] * d[2];
+            let size = 2.0 * node.half_size;
+            let contains_body = node.start <= rank && rank < node.end;
+            if !contains_body && size * size < theta_squared * distance_squared {
+                pull(node.center_of_mass, node.mass);
+            } else {
+                stack.extend(node.children.iter().filter(|&&child| child != NO_CHILD));
+            }
+        }
+        acceleration
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::forces::direct_accelerations;
+    use crate::generators::random_cluster;
+    use crate::units::{ASTRONOMICAL_UNIT, SOLAR_MASS};
+
+    const G: f64 = 6.67430e-11;
+
+    fn cluster(n: usize) -> Vec<Body> {
+        random_cluster(n, 1.0, 17, SOLAR_MASS, ASTRONOMICAL_UNIT).bodies
+    }
+
+    /// Relative acceleration errors of Barnes-Hut against the direct sum
+    fn relative_errors(bodies: &[Body], opening_angle: f64) -> Vec<f64> {
+        let mut direct = bodies.to_vec();
+        direct_accelerations(&mut direct, G, Softening::None);
+        let mut tree = bodies.to_vec();
+        Octree::build(&tree).accelerations(&mut tree, G, Softening::None, opening_angle);
+
+        direct
+            .iter()
+            .zip(&tree)
+            .map(|(exact, approx)| {
+                let diff: f64 = (0..3)
+                    .map(|k| (exact.acceleration[k] - approx.acceleration[k]).powi(2))
+                    .sum();
+                let norm: f64 = (0..3).map(|k| exact.acceleration[k].powi(2)).sum();
+                (diff / norm).sqrt()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_zero_opening_angle_matches_direct_sum() {
+        let errors = relative_errors(&cluster(300), 0.0);
+        let worst = errors.iter().cloned().fold(0.0, f64::max);
+        assert!(worst < 1e-12, "worst relative error {}", worst);
+    }
+
+    #[test]
+    fn test_barnes_hut_against_direct_sum() {
+        let bodies = cluster(2000);
+
+        let mut errors = relative_errors(&bodies, 0.5);
+        errors.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        // The largest relative errors belong to bodies whose pulls nearly
+        // cancel, so bound the bulk of the distribution rather than the tail
+        let median = errors[errors.len() / 2];
+        let percentile_99 = errors[errors.len() * 99 / 100];
+        assert!(median < 1e-2, "median relative error {}", median);
+        assert!(percentile_99 < 5e-2, "99th percentile relative error {}", percentile_99);
+
+        // A smaller opening angle is more accurate
+        let mean = |errors: &[f64]| errors.iter().sum::<f64>() / errors.len() as f64;
+        assert!(mean(&relative_errors(&bodies, 0.3)) < mean(&errors));
+        assert!(mean(&errors) < mean(&relative_errors(&bodies, 0.8)));
+    }
+
+    #[test]
+    fn test_coincident_bodies() {
+        let mut bodies = vec![Body::new(1e30, [1e11, 0.0, 0.0], [0.0; 3]); 5];
+        bodies.push(Body::new(1e30, [0.0; 3], [0.0; 3]));
+        Octree::build(&bodies).accelerations(&mut bodies, G, Softening::None, 0.5);
+
+        let expected = G * 1e30 / 1e22;
+        assert!(((bodies[0].acceleration[0] + expected) / expected).abs() < 1e-12);
+        assert!(((bodies[5].acceleration[0] - 5.0 * expected) / expected).abs() < 1e-12);
+    }
+}
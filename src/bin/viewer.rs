@@ -1,6 +1,6 @@
 use bevy::prelude::*;
 use bevy::input::mouse::{MouseMotion, MouseWheel};
-use threebody_sim::TrajectoryData;
+use threebody_sim::{Interpolation, Position, TrajectoryData};
 use std::env;
 
 /// Calculate the centroid and maximum distance of bodies at frame 0
@@ -11,7 +11,7 @@ fn calculate_camera_target(trajectory: &TrajectoryData) -> (Vec3, f32) {
     // Calculate centroid of all bodies at frame 0
     for body_traj in &trajectory.bodies {
         if let Some(pos) = body_traj.get_position(0) {
-            centroid += Vec3::new(pos.x, pos.y, pos.z);
+            centroid += Vec3::from(pos.to_f32());
             count += 1;
         }
     }
@@ -24,7 +24,7 @@ fn calculate_camera_target(trajectory: &TrajectoryData) -> (Vec3, f32) {
     let mut max_distance: f32 = 0.0;
     for body_traj in &trajectory.bodies {
         if let Some(pos) = body_traj.get_position(0) {
-            let pos_vec = Vec3::new(pos.x, pos.y, pos.z);
+            let pos_vec = Vec3::from(pos.to_f32());
             let distance = (pos_vec - centroid).length();
             max_distance = max_distance.max(distance);
         }
@@ -50,9 +50,10 @@ fn calculate_camera_position(target: Vec3, distance: f32, yaw: f32, pitch: f32)
 #[derive(Resource)]
 struct ViewerState {
     trajectory: TrajectoryData,
-    current_frame: usize,
+    current_time: f64,
+    frame_interval: f64, // Mean time between frames
     is_playing: bool,
-    speed: f32, // Frames per update
+    speed: f32, // Frames per update, fractions play in slow motion
     centroid: Vec3,
     camera_distance: f32,
 }
@@ -79,6 +80,21 @@ impl Default for CameraState {
     }
 }
 
+impl ViewerState {
+    fn start_time(&self) -> f64 {
+        self.trajectory.times.first().copied().unwrap_or(0.0)
+    }
+
+    fn end_time(&self) -> f64 {
+        self.trajectory.times.last().copied().unwrap_or(0.0)
+    }
+
+    /// Position of a body at the current time, between frames if need be
+    fn position(&self, body_index: usize) -> Option<Position> {
+        self.trajectory.position_at(body_index, self.current_time, Interpolation::Hermite)
+    }
+}
+
 /// Component for bodies in the 3D view
 #[derive(Component)]
 struct BodyVisual {
@@ -93,6 +109,12 @@ struct BodyTrail {
     max_trail_length: usize,
 }
 
+/// Component for the on-screen name label that follows a body
+#[derive(Component)]
+struct BodyLabel {
+    body_index: usize,
+}
+
 /// Marker component for trail mesh entities
 #[derive(Component)]
 struct TrailMesh;
@@ -108,7 +130,7 @@ fn main() {
 
     println!("Loading trajectory from: {}", trajectory_file);
     
-    let trajectory = match TrajectoryData::load_csv(&trajectory_file) {
+    let trajectory = match TrajectoryData::load(&trajectory_file) {
         Ok(traj) => {
             println!("Loaded {} bodies with {} frames", traj.bodies.len(), traj.num_frames);
             traj
@@ -122,9 +144,14 @@ fn main() {
     let (centroid, max_distance) = calculate_camera_target(&trajectory);
     let camera_distance = max_distance * 2.5;
 
+    let start_time = trajectory.times.first().copied().unwrap_or(0.0);
+    let end_time = trajectory.times.last().copied().unwrap_or(0.0);
+    let frame_interval = (end_time - start_time) / trajectory.num_frames.saturating_sub(1).max(1) as f64;
+
     let viewer_state = ViewerState {
         trajectory,
-        current_frame: 0,
+        current_time: start_time,
+        frame_interval,
         is_playing: true,
         speed: 1.0,
         centroid,
@@ -147,6 +174,7 @@ fn main() {
             handle_mouse_input,
             update_camera,
             update_positions,
+            update_labels,
             update_trails,
             render_trails,
             handle_input,
@@ -224,7 +252,7 @@ fn setup(
         Color::rgb(0.8, 0.3, 0.5),  // Magenta
     ];
 
-    for (idx, _body) in state.trajectory.bodies.iter().enumerate() {
+    for (idx, body) in state.trajectory.bodies.iter().enumerate() {
         let color = colors[idx % colors.len()];
         let material = materials.add(StandardMaterial {
             base_color: color,
@@ -234,7 +262,7 @@ fn setup(
         // Initialize trail with the first position
         let mut initial_positions = Vec::new();
         if let Some(pos) = state.trajectory.bodies[idx].get_position(0) {
-            initial_positions.push(Vec3::new(pos.x, pos.y, pos.z));
+            initial_positions.push(Vec3::from(pos.to_f32()));
         }
 
         commands.spawn((
@@ -251,6 +279,24 @@ fn setup(
                 max_trail_length: 500, // Keep last 500 positions
             },
         ));
+
+        // Name label, positioned over the body each frame by update_labels
+        let name = if body.name.is_empty() { format!("body{}", idx) } else { body.name.clone() };
+        commands.spawn((
+            TextBundle::from_section(
+                name,
+                TextStyle {
+                    font_size: 16.0,
+                    color,
+                    ..default()
+                },
+            )
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                ..default()
+            }),
+            BodyLabel { body_index: idx },
+        ));
     }
 
     println!("Controls:");
@@ -264,17 +310,49 @@ fn setup(
 
 fn update_positions(
     mut state: ResMut<ViewerState>,
-    mut body_query: Query<(&BodyVisual, &mut Transform)>,
+    mut body_query: Query<(&BodyVisual, &mut Transform, &mut Visibility)>,
 ) {
-    if state.is_playing && state.current_frame < state.trajectory.num_frames - 1 {
-        state.current_frame = (state.current_frame as f32 + state.speed).min(state.trajectory.num_frames as f32 - 1.0) as usize;
+    if state.is_playing && state.current_time < state.end_time() {
+        state.current_time = (state.current_time + state.speed as f64 * state.frame_interval).min(state.end_time());
+    }
+
+    for (body_visual, mut transform, mut visibility) in body_query.iter_mut() {
+        if let Some(pos) = state.position(body_visual.body_index) {
+            // Bodies merged into another one have no position any more
+            if pos.is_finite() {
+                transform.translation = Vec3::from(pos.to_f32());
+                *visibility = Visibility::Inherited;
+            } else {
+                *visibility = Visibility::Hidden;
+            }
+        }
     }
+}
 
-    for (body_visual, mut transform) in body_query.iter_mut() {
-        if let Some(pos) = state.trajectory.bodies[body_visual.body_index]
-            .get_position(state.current_frame)
-        {
-            transform.translation = Vec3::new(pos.x, pos.y, pos.z);
+/// Keep each name label next to its body on screen
+fn update_labels(
+    state: Res<ViewerState>,
+    camera_query: Query<(&Camera, &GlobalTransform), With<Camera3d>>,
+    mut label_query: Query<(&BodyLabel, &mut Style, &mut Visibility)>,
+) {
+    let Ok((camera, camera_transform)) = camera_query.get_single() else {
+        return;
+    };
+
+    for (label, mut style, mut visibility) in label_query.iter_mut() {
+        let screen_pos = state
+            .position(label.body_index)
+            .filter(|pos| pos.is_finite())
+            .and_then(|pos| camera.world_to_viewport(camera_transform, Vec3::from(pos.to_f32())));
+
+        match screen_pos {
+            Some(screen_pos) => {
+                // Offset slightly so the label sits beside the sphere
+                style.left = Val::Px(screen_pos.x + 8.0);
+                style.top = Val::Px(screen_pos.y - 8.0);
+                *visibility = Visibility::Inherited;
+            }
+            None => *visibility = Visibility::Hidden,
         }
     }
 }
@@ -481,7 +559,7 @@ fn handle_input(
     }
 
     if keyboard_input.just_pressed(KeyCode::KeyR) {
-        state.current_frame = 0;
+        state.current_time = state.start_time();
         
         // Clear all trail meshes from the scene
         for entity in trail_mesh_query.iter() {
@@ -504,14 +582,18 @@ fn handle_input(
 
 fn update_ui(
     state: Res<ViewerState>,
+    mut last_printed: Local<Option<usize>>,
 ) {
-    // Print status occasionally (every 60 frames)
-    if state.current_frame % 60 == 0 {
-        let percent = (state.current_frame as f32 / state.trajectory.num_frames as f32) * 100.0;
+    // Print status occasionally (every 60 frames), once per frame even in slow motion
+    let current_frame = state.trajectory.frame_at(state.current_time).unwrap_or(0);
+    if current_frame % 60 == 0 && *last_printed != Some(current_frame) {
+        *last_printed = Some(current_frame);
+        let percent = (current_frame as f32 / state.trajectory.num_frames as f32) * 100.0;
         println!(
-            "Frame: {}/{} ({:.1}%) | Speed: {:.1}x | Status: {}",
-            state.current_frame,
+            "Frame: {}/{} (t = {:.4e}, {:.1}%) | Speed: {:.1}x | Status: {}",
+            current_frame,
             state.trajectory.num_frames,
+            state.current_time,
             percent,
             state.speed,
             if state.is_playing { "Playing" } else { "Paused" }
@@ -0,0 +1,356 @@
+//! Binary trajectory files
+//!
+//! A compact alternative to the CSV output: the same columns, at full
+//! precision, as raw little-endian `f64` records after a self-describing
+//! header. Records have a fixed size, so any frame can be read with a
+//! single seek (see `trajectory::TrajectoryReader`), and a file that is
+//! still being written, or was cut short, can be read up to its last
+//! complete frame.
+//!
+//! ```text
+//! magic        8 bytes   "NBODYTRJ"
+//! version      u32       1
+//! data offset  u64       byte offset of the first record
+//! units        string    si, au or nbody
+//!              3 x f64   mass (kg), length (m) and time (s) units
+//! bodies       u32       count, then per body:
+//!              string    name
+//!              f64       mass in the file's units when it was started
+//! columns      u32       count, then per column:
+//!              string    name, as in the CSV header (time, Sun_x, ...)
+//! records      f64 x columns, one record per frame
+//! ```
+//!
+//! Strings are a `u32` byte length followed by UTF-8. Every integer and
+//! float is little-endian. Values are in the file's units, like the CSV
+//! output in `output_units`, and `NaN` marks bodies lost in a merger.
+
+use crate::body::Body;
+use crate::frame::OutputFrame;
+use crate::output::{labels, OutputColumns};
+use crate::softening::Softening;
+use crate::units::UnitSystem;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+
+/// First bytes of every binary trajectory file
+pub const MAGIC: &[u8; 8] = b"NBODYTRJ";
+
+/// Format version written by this library
+pub const VERSION: u32 = 1;
+
+/// Size of the fixed part of the header: magic, version and data offset
+const PREAMBLE_SIZE: u64 = 20;
+
+/// Headers larger than this are taken as a corrupt data offset
+const MAX_HEADER_SIZE: u64 = 1 << 26;
+
+/// The self-describing header of a binary trajectory file
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrajectoryHeader {
+    /// Units of every value in the file
+    pub units: UnitSystem,
+    /// Body names, as used in the column names
+    pub names: Vec<String>,
+    /// Body masses in `units`; `NaN` for a body missing when the file was started
+    pub masses: Vec<f64>,
+    /// Column names of each record, starting with `time`
+    pub columns: Vec<String>,
+}
+
+impl TrajectoryHeader {
+    /// Header for the columns written by `OutputColumns` for the named bodies
+    ///
+    /// Masses are taken from the bodies, in SI, and converted to `units`.
+    pub fn for_output(columns: &OutputColumns, names: &[String], bodies: &[Body], units: UnitSystem) -> Self {
+        let body_names = labels(bodies);
+        let masses = names
+            .iter()
+            .map(|name| match body_names.iter().position(|body_name| body_name == name) {
+                Some(idx) => bodies[idx].mass / units.mass(),
+                None => f64::NAN,
+            })
+            .collect();
+        TrajectoryHeader {
+            units,
+            names: names.to_vec(),
+            masses,
+            columns: columns.header_for(names).split(',').map(String::from).collect(),
+        }
+    }
+
+    /// Index of the named column
+    pub fn column(&self, name: &str) -> Option<usize> {
+        self.columns.iter().position(|column| column == name)
+    }
+
+    /// Size of one record in bytes
+    pub fn record_size(&self) -> u64 {
+        8 * self.columns.len() as u64
+    }
+
+    /// The complete header as written to a file
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        push_string(&mut body, self.units.name());
+        for unit in [self.units.mass(), self.units.length(), self.units.time()] {
+            body.extend(unit.to_le_bytes());
+        }
+        body.extend((self.names.len() as u32).to_le_bytes());
+        for (name, mass) in self.names.iter().zip(&self.masses) {
+            push_string(&mut body, name);
+            body.extend(mass.to_le_bytes());
+        }
+        body.extend((self.columns.len() as u32).to_le_bytes());
+        for column in &self.columns {
+            push_string(&mut body, column);
+        }
+
+        let mut bytes = Vec::with_capacity(PREAMBLE_SIZE as usize + body.len());
+        bytes.extend(MAGIC);
+        bytes.extend(VERSION.to_le_bytes());
+        bytes.extend((PREAMBLE_SIZE + body.len() as u64).to_le_bytes());
+        bytes.extend(body);
+        bytes
+    }
+
+    /// Read a header from the start of a file, returning it with the byte
+    /// offset of the first record
+    pub fn read<R: Read>(reader: &mut R) -> std::io::Result<(Self, u64)> {
+        let mut preamble = [0u8; PREAMBLE_SIZE as usize];
+        reader.read_exact(&mut preamble)?;
+        if &preamble[..8] != MAGIC {
+            return Err(invalid_data(String::from("not a binary trajectory file")));
+        }
+        let version = u32::from_le_bytes(preamble[8..12].try_into().unwrap());
+        if version != VERSION {
+            return Err(invalid_data(format!("unsupported trajectory format version {}", version)));
+        }
+        let data_offset = u64::from_le_bytes(preamble[12..20].try_into().unwrap());
+        if !(PREAMBLE_SIZE..=MAX_HEADER_SIZE).contains(&data_offset) {
+            return Err(invalid_data(format!("invalid data offset {}", data_offset)));
+        }
+        let mut bytes = vec![0u8; (data_offset - PREAMBLE_SIZE) as usize];
+        reader.read_exact(&mut bytes)?;
+
+        let mut cursor = Cursor { bytes: &bytes, position: 0 };
+        let unit_name = cursor.string()?;
+        let (mass, length, time) = (cursor.f64()?, cursor.f64()?, cursor.f64()?);
+        let units = match unit_name.as_str() {
+            "si" => UnitSystem::Si,
+            "au" => UnitSystem::Astronomical,
+            "nbody" => UnitSystem::nbody(mass, length),
+            other => return Err(invalid_data(format!("unknown unit system '{}'", other))),
+        };
+        if (units.time() - time).abs() > 1e-9 * time.abs() {
+            return Err(invalid_data(format!("time unit {} s does not match {}", time, units)));
+        }
+        let mut names = Vec::new();
+        let mut masses = Vec::new();
+        for _ in 0..cursor.u32()? {
+            names.push(cursor.string()?);
+            masses.push(cursor.f64()?);
+        }
+        let mut columns = Vec::new();
+        for _ in 0..cursor.u32()? {
+            columns.push(cursor.string()?);
+        }
+        if columns.first().map(String::as_str) != Some("time") {
+            return Err(invalid_data(String::from("the first column is not time")));
+        }
+        Ok((TrajectoryHeader { units, names, masses, columns }, data_offset))
+    }
+}
+
+/// Whether a file starts like a binary trajectory file
+pub fn is_trajectory_file<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<bool> {
+    let mut magic = [0u8; 8];
+    let mut file = File::open(path)?;
+    let mut read = 0;
+    while read < magic.len() {
+        match file.read(&mut magic[read..])? {
+            0 => return Ok(false),
+            n => read += n,
+        }
+    }
+    Ok(&magic == MAGIC)
+}
+
+fn push_string(bytes: &mut Vec<u8>, text: &str) {
+    bytes.extend((text.len() as u32).to_le_bytes());
+    bytes.extend(text.as_bytes());
+}
+
+fn invalid_data(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+/// Reads the fields of a header already in memory
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, len: usize) -> std::io::Result<&'a [u8]> {
+        let end = self.position.checked_add(len).filter(|&end| end <= self.bytes.len());
+        let end = end.ok_or_else(|| invalid_data(String::from("truncated trajectory header")))?;
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> std::io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn f64(&mut self) -> std::io::Result<f64> {
+        Ok(f64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> std::io::Result<String> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec())
+            .map_err(|_| invalid_data(String::from("invalid UTF-8 in trajectory header")))
+    }
+}
+
+/// Time of a record, stored in its first eight bytes
+fn read_time<R: Read + Seek>(reader: &mut R, offset: u64) -> std::io::Result<f64> {
+    let mut bytes = [0u8; 8];
+    reader.seek(SeekFrom::Start(offset))?;
+    reader.read_exact(&mut bytes)?;
+    Ok(f64::from_le_bytes(bytes))
+}
+
+/// A binary trajectory file whose header is written with the first record
+///
+/// Like `CsvOutput`, deferring the header lets the columns be chosen after
+/// the file is opened.
+pub(crate) struct BinaryOutput {
+    file: File,
+    header_written: bool,
+}
+
+impl BinaryOutput {
+    pub(crate) fn new(file: File, header_written: bool) -> Self {
+        BinaryOutput { file, header_written }
+    }
+
+    /// Whether the header, and so the record layout, has been written
+    pub(crate) fn header_written(&self) -> bool {
+        self.header_written
+    }
+
+    /// Write a record laid out for the named bodies, as in `OutputColumns::values_for`
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn write_row(
+        &mut self,
+        columns: &OutputColumns,
+        time: f64,
+        names: &[String],
+        bodies: &[Body],
+        frame: &OutputFrame,
+        softening: Softening,
+        units: UnitSystem,
+    ) -> std::io::Result<()> {
+        if !self.header_written {
+            let header = TrajectoryHeader::for_output(columns, names, bodies, units);
+            self.file.write_all(&header.encode())?;
+            self.header_written = true;
+        }
+        let values = columns.values_for(time, names, bodies, frame, softening, units);
+        let mut bytes = Vec::with_capacity(8 * values.len());
+        for value in values {
+            bytes.extend(value.to_le_bytes());
+        }
+        self.file.write_all(&bytes)
+    }
+}
+
+/// Open a binary trajectory file to continue it after `time`, returning the
+/// file positioned at its end and whether it already has its header
+///
+/// Records later than `time`, in the file's units, are removed together
+/// with any partly written final record. A header with other columns or
+/// units is an error; a missing or empty file is started afresh.
+pub(crate) fn open_for_append(
+    path: &str,
+    units: UnitSystem,
+    columns: &[String],
+    time: f64,
+) -> std::io::Result<(File, bool)> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+    let len = file.metadata()?.len();
+    if len == 0 {
+        return Ok((file, false));
+    }
+
+    let (header, data_offset) = TrajectoryHeader::read(&mut file)?;
+    if header.columns != columns || header.units != units {
+        return Err(invalid_data(format!(
+            "{} has different columns than the resumed simulation",
+            path
+        )));
+    }
+
+    // Times increase from record to record, so search for the first one
+    // after `time`
+    let epsilon = time.abs() * 1e-12;
+    let record_size = header.record_size();
+    let (mut low, mut high) = (0, len.saturating_sub(data_offset) / record_size);
+    while low < high {
+        let mid = (low + high) / 2;
+        if read_time(&mut file, data_offset + mid * record_size)? <= time + epsilon {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+
+    file.set_len(data_offset + low * record_size)?;
+    file.seek(SeekFrom::End(0))?;
+    Ok((file, true))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_round_trip() {
+        let bodies = vec![
+            Body::new(2.0 * crate::units::SOLAR_MASS, [0.0; 3], [0.0; 3]).with_name("Sun"),
+            Body::new(1e24, [1e11, 0.0, 0.0], [0.0; 3]),
+        ];
+        let names = vec![String::from("Sun"), String::from("Lost"), String::from("body1")];
+        let columns = OutputColumns {
+            velocity: true,
+            total_energy: true,
+            ..OutputColumns::default()
+        };
+        let header = TrajectoryHeader::for_output(&columns, &names, &bodies, UnitSystem::Astronomical);
+        assert_eq!(header.masses[0], 2.0);
+        assert!(header.masses[1].is_nan());
+        assert_eq!(header.columns.len(), 1 + 3 * 6 + 1);
+        assert_eq!(header.column("body1_vx"), Some(16));
+        assert_eq!(header.record_size(), 160);
+
+        let bytes = header.encode();
+        assert_eq!(&bytes[..8], MAGIC);
+        let (read, data_offset) = TrajectoryHeader::read(&mut bytes.as_slice()).unwrap();
+        assert_eq!(data_offset, bytes.len() as u64);
+        assert_eq!(read.units, header.units);
+        assert_eq!(read.names, header.names);
+        assert_eq!(read.columns, header.columns);
+        assert_eq!(read.masses[2], header.masses[2]);
+
+        assert!(TrajectoryHeader::read(&mut &bytes[..bytes.len() - 1]).is_err());
+        assert!(TrajectoryHeader::read(&mut &b"time,Sun_x,Sun_y,Sun_z\n0,0,0,0\n"[..]).is_err());
+    }
+}
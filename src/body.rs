@@ -3,10 +3,16 @@
 use std::fmt;
 
 /// A physical body with mass, position, and velocity in 3D space
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Body {
+    /// Name used in output headers and viewer labels (may be empty)
+    pub name: String,
     /// Mass of the body (in kg)
     pub mass: f64,
+    /// Physical radius (in m); zero for a point mass
+    pub radius: f64,
+    /// Display colour as linear RGB in [0, 1], if one was chosen
+    pub color: Option<[f32; 3]>,
     /// Position vector [x, y, z]
     pub position: [f64; 3],
     /// Velocity vector [vx, vy, vz]
@@ -19,13 +25,43 @@ impl Body {
     /// Create a new body with the given properties
     pub fn new(mass: f64, position: [f64; 3], velocity: [f64; 3]) -> Self {
         Body {
+            name: String::new(),
             mass,
+            radius: 0.0,
+            color: None,
             position,
             velocity,
             acceleration: [0.0; 3],
         }
     }
 
+    /// Set the body's name
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    /// Set the body's physical radius
+    pub fn with_radius(mut self, radius: f64) -> Self {
+        self.radius = radius;
+        self
+    }
+
+    /// Set the body's display colour
+    pub fn with_color(mut self, color: [f32; 3]) -> Self {
+        self.color = Some(color);
+        self
+    }
+
+    /// Name to show for the body at `idx`, falling back to `body{idx}`
+    pub fn label(&self, idx: usize) -> String {
+        if self.name.is_empty() {
+            format!("body{}", idx)
+        } else {
+            self.name.clone()
+        }
+    }
+
     /// Calculate the distance to another body
     pub fn distance_to(&self, other: &Body) -> f64 {
         let dx = other.position[0] - self.position[0];
@@ -43,6 +79,25 @@ impl Body {
         ]
     }
 
+    /// Kinetic energy of the body, 1/2 m v^2
+    pub fn kinetic_energy(&self) -> f64 {
+        let v_squared = self.velocity[0] * self.velocity[0]
+            + self.velocity[1] * self.velocity[1]
+            + self.velocity[2] * self.velocity[2];
+        0.5 * self.mass * v_squared
+    }
+
+    /// Angular momentum about the origin, m (r x v)
+    pub fn angular_momentum(&self) -> [f64; 3] {
+        let r = self.position;
+        let v = self.velocity;
+        [
+            self.mass * (r[1] * v[2] - r[2] * v[1]),
+            self.mass * (r[2] * v[0] - r[0] * v[2]),
+            self.mass * (r[0] * v[1] - r[1] * v[0]),
+        ]
+    }
+
     /// Set acceleration to zero
     pub fn reset_acceleration(&mut self) {
         self.acceleration = [0.0; 3];
@@ -58,6 +113,9 @@ impl Body {
 
 impl fmt::Display for Body {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.name.is_empty() {
+            write!(f, "{} ", self.name)?;
+        }
         write!(
             f,
             "Body {{ mass: {:.2e}, pos: [{:.2e}, {:.2e}, {:.2e}], vel: [{:.2e}, {:.2e}, {:.2e}] }}",
@@ -95,4 +153,16 @@ mod tests {
         let vec = body1.vector_to(&body2);
         assert_eq!(vec, [3.0, 4.0, 5.0]);
     }
+
+    #[test]
+    fn test_body_metadata() {
+        let body = Body::new(5.972e24, [0.0; 3], [0.0; 3])
+            .with_name("Earth")
+            .with_radius(6.371e6)
+            .with_color([0.2, 0.4, 1.0]);
+        assert_eq!(body.label(3), "Earth");
+        assert_eq!(body.radius, 6.371e6);
+        assert_eq!(body.color, Some([0.2, 0.4, 1.0]));
+        assert_eq!(Body::new(1.0, [0.0; 3], [0.0; 3]).label(3), "body3");
+    }
 }
@@ -0,0 +1,526 @@
+//! Simulator checkpoints for stopping and resuming long runs
+//!
+//! A checkpoint records everything needed to continue a run exactly where
+//! it stopped: the bodies, the time, the step size, the integrator, the
+//! force model, the adaptive-stepping state and the output cadence. It is
+//! stored as plain text in an INI-like layout. Every floating-point value
+//! is written in Rust's shortest round-trip form, so reading a checkpoint
+//! back restores each value bit for bit.
+
+use crate::body::Body;
+use crate::collision::CollisionMode;
+use crate::diagnostics::{Drift, DriftTracker};
+use crate::forces::ForceSolver;
+use crate::frame::OutputFrame;
+use crate::output::OutputColumns;
+use crate::simulator::AdaptiveSettings;
+use crate::softening::Softening;
+use crate::units::UnitSystem;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Format version written to and expected in the `[Checkpoint]` section
+const VERSION: u32 = 1;
+
+/// The complete state of a simulator at one moment
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checkpoint {
+    pub bodies: Vec<Body>,
+    pub time: f64,
+    pub dt: f64,
+    /// Name of the integrator, as returned by `Integrator::name`
+    pub integrator: String,
+    pub force_solver: ForceSolver,
+    pub softening: Softening,
+    /// Whether the 1PN relativistic correction is applied
+    pub post_newtonian: bool,
+    pub collision_mode: CollisionMode,
+    /// Whether a collision has stopped the run
+    pub stopped: bool,
+    pub adaptive: Option<AdaptiveSettings>,
+    pub accepted_steps: usize,
+    pub rejected_steps: usize,
+    pub output_interval: Option<f64>,
+    /// Time from which output rows are counted when a cadence is set
+    pub output_origin: f64,
+    /// Number of rows written since `output_origin`
+    pub output_count: u64,
+    pub output_columns: OutputColumns,
+    pub output_units: UnitSystem,
+    pub output_frame: OutputFrame,
+    /// Reference values and largest errors of the conserved quantities
+    pub drift: Option<DriftTracker>,
+    /// Bodies named in the output columns, once mergers have removed some
+    pub output_layout: Option<Vec<String>>,
+}
+
+impl Checkpoint {
+    /// Render the checkpoint as text
+    ///
+    /// Example (as shown in this text example, not valid Rust):
+    /// ```text
+    /// [Checkpoint]
+    /// version = 1
+    /// time = 8.64e4
+    /// dt = 3.6e3
+    /// integrator = yoshida4
+    /// accepted_steps = 24
+    /// rejected_steps = 0
+    /// output_origin = 0e0
+    /// output_count = 0
+    /// output_columns = positions
+    ///
+    /// [Body]
+    /// name = Earth
+    /// mass = 5.972e24
+    /// radius = 6.371e6
+    /// color = 2e-1 4e-1 1e0
+    /// position = -1.2e3 4.5e1 0e0
+    /// velocity = 1.1e-2 -3e-1 0e0
+    /// acceleration = 0e0 0e0 0e0
+    /// ```
+    ///
+    /// `adaptive` (abs, abs velocity, rel, min, max, safety),
+    /// `output_interval` and `color` are omitted when unset, as are
+    /// `force_solver` for the default direct sum, `softening` for point
+    /// masses, `post_newtonian` when off, `collisions` when they are
+    /// ignored, `stopped` unless a collision stopped the run,
+    /// `output_units` for SI, `output_frame` for the inertial frame,
+    /// `drift_reference`
+    /// (time, energy, momentum, angular momentum and their two scales) and
+    /// `drift_max` until drift is tracked, and `output_bodies` until a
+    /// merger removes a body. `opening_angle` or `threads` follow
+    /// `force_solver` for the solvers that take them.
+    pub fn to_text(&self) -> String {
+        let mut text = String::from("# threebody-sim checkpoint\n[Checkpoint]\n");
+        // Writing to a String cannot fail
+        let _ = writeln!(text, "version = {}", VERSION);
+        let _ = writeln!(text, "time = {:e}", self.time);
+        let _ = writeln!(text, "dt = {:e}", self.dt);
+        let _ = writeln!(text, "integrator = {}", self.integrator);
+        match self.force_solver {
+            ForceSolver::Direct => {}
+            ForceSolver::ParallelDirect { threads } => {
+                let _ = writeln!(text, "force_solver = {}", self.force_solver.name());
+                let _ = writeln!(text, "threads = {}", threads);
+            }
+            ForceSolver::BarnesHut { opening_angle } => {
+                let _ = writeln!(text, "force_solver = {}", self.force_solver.name());
+                let _ = writeln!(text, "opening_angle = {:e}", opening_angle);
+            }
+        }
+        if self.softening != Softening::None {
+            let _ = writeln!(text, "softening = {} {:e}", self.softening.name(), self.softening.length());
+        }
+        if self.post_newtonian {
+            let _ = writeln!(text, "post_newtonian = true");
+        }
+        if self.collision_mode != CollisionMode::Ignore {
+            let _ = writeln!(text, "collisions = {}", self.collision_mode);
+        }
+        if self.stopped {
+            let _ = writeln!(text, "stopped = true");
+        }
+        let _ = writeln!(text, "accepted_steps = {}", self.accepted_steps);
+        let _ = writeln!(text, "rejected_steps = {}", self.rejected_steps);
+        if let Some(a) = self.adaptive {
+            let _ = writeln!(
+                text,
+                "adaptive = {:e} {:e} {:e} {:e} {:e} {:e}",
+                a.abs_tolerance, a.abs_velocity_tolerance, a.rel_tolerance, a.min_dt, a.max_dt, a.safety
+            );
+        }
+        if let Some(interval) = self.output_interval {
+            let _ = writeln!(text, "output_interval = {:e}", interval);
+        }
+        let _ = writeln!(text, "output_origin = {:e}", self.output_origin);
+        let _ = writeln!(text, "output_count = {}", self.output_count);
+        let _ = writeln!(text, "output_columns = {}", self.output_columns);
+        match self.output_units {
+            UnitSystem::Si => {}
+            UnitSystem::Astronomical => {
+                let _ = writeln!(text, "output_units = {}", self.output_units.name());
+            }
+            UnitSystem::NBody { mass, length } => {
+                let _ = writeln!(text, "output_units = {} {:e} {:e}", self.output_units.name(), mass, length);
+            }
+        }
+        if self.output_frame != OutputFrame::Inertial {
+            let _ = writeln!(text, "output_frame = {}", self.output_frame);
+        }
+        if let Some(ref drift) = self.drift {
+            let _ = writeln!(
+                text,
+                "drift_reference = {:e} {:e} {:e} {:e} {:e} {:e} {:e} {:e} {:e} {:e}",
+                drift.time,
+                drift.energy,
+                drift.momentum[0],
+                drift.momentum[1],
+                drift.momentum[2],
+                drift.angular_momentum[0],
+                drift.angular_momentum[1],
+                drift.angular_momentum[2],
+                drift.momentum_scale,
+                drift.angular_momentum_scale
+            );
+            let _ = writeln!(
+                text,
+                "drift_max = {:e} {:e} {:e}",
+                drift.max.energy, drift.max.momentum, drift.max.angular_momentum
+            );
+        }
+        if let Some(ref names) = self.output_layout {
+            let _ = writeln!(text, "output_bodies = {}", names.join(", "));
+        }
+
+        for body in &self.bodies {
+            let _ = writeln!(text, "\n[Body]");
+            let _ = writeln!(text, "name = {}", body.name);
+            let _ = writeln!(text, "mass = {:e}", body.mass);
+            let _ = writeln!(text, "radius = {:e}", body.radius);
+            if let Some([r, g, b]) = body.color {
+                let _ = writeln!(text, "color = {:e} {:e} {:e}", r, g, b);
+            }
+            for (key, v) in [
+                ("position", body.position),
+                ("velocity", body.velocity),
+                ("acceleration", body.acceleration),
+            ] {
+                let _ = writeln!(text, "{} = {:e} {:e} {:e}", key, v[0], v[1], v[2]);
+            }
+        }
+        text
+    }
+
+    /// Parse a checkpoint written by `to_text`
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let mut checkpoint = Checkpoint {
+            bodies: Vec::new(),
+            time: 0.0,
+            dt: 0.0,
+            integrator: String::new(),
+            force_solver: ForceSolver::Direct,
+            softening: Softening::None,
+            post_newtonian: false,
+            collision_mode: CollisionMode::Ignore,
+            stopped: false,
+            adaptive: None,
+            accepted_steps: 0,
+            rejected_steps: 0,
+            output_interval: None,
+            output_origin: 0.0,
+            output_count: 0,
+            output_columns: OutputColumns::default(),
+            output_units: UnitSystem::Si,
+            output_frame: OutputFrame::Inertial,
+            drift: None,
+            output_layout: None,
+        };
+        let mut in_header = false;
+        let mut version = None;
+        let mut dt = None;
+        let mut seen = Vec::new();
+
+        for (idx, line) in text.lines().enumerate() {
+            let error = |message: String| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("checkpoint line {}: {}", idx + 1, message))
+            };
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+            match trimmed {
+                "[Checkpoint]" => {
+                    in_header = true;
+                    seen.clear();
+                    continue;
+                }
+                "[Body]" => {
+                    in_header = false;
+                    seen.clear();
+                    // NaN until a `mass` line is read
+                    checkpoint.bodies.push(Body::new(f64::NAN, [0.0; 3], [0.0; 3]));
+                    continue;
+                }
+                _ => {}
+            }
+
+            let (key, value) = trimmed
+                .split_once('=')
+                .ok_or_else(|| error(format!("malformed line '{}'", trimmed)))?;
+            let (key, value) = (key.trim(), value.trim());
+            if seen.contains(&key) {
+                return Err(error(format!("duplicate key '{}'", key)));
+            }
+            seen.push(key);
+
+            let number = |text: &str| -> io::Result<f64> {
+                text.parse().map_err(|_| error(format!("{}: invalid number '{}'", key, text)))
+            };
+            let numbers = |count: usize| -> io::Result<Vec<f64>> {
+                let values = value.split_whitespace().map(number).collect::<io::Result<Vec<f64>>>()?;
+                if values.len() != count {
+                    return Err(error(format!("{}: expected {} numbers", key, count)));
+                }
+                Ok(values)
+            };
+            let integer = |text: &str| -> io::Result<u64> {
+                text.parse().map_err(|_| error(format!("{}: invalid count '{}'", key, text)))
+            };
+            let vector = || -> io::Result<[f64; 3]> {
+                let v = numbers(3)?;
+                Ok([v[0], v[1], v[2]])
+            };
+
+            if in_header {
+                match key {
+                    "version" => version = Some(integer(value)?),
+                    "time" => checkpoint.time = number(value)?,
+                    "dt" => {
+                        let step = number(value)?;
+                        if step == 0.0 || !step.is_finite() {
+                            return Err(error(format!("dt: expected a finite, non-zero time step, got '{}'", value)));
+                        }
+                        dt = Some(step);
+                    }
+                    "integrator" => checkpoint.integrator = value.to_string(),
+                    "force_solver" => checkpoint.force_solver = value.parse().map_err(error)?,
+                    // Always written after `force_solver`
+                    "opening_angle" => match checkpoint.force_solver {
+                        ForceSolver::BarnesHut { ref mut opening_angle } => *opening_angle = number(value)?,
+                        _ => return Err(error(String::from("opening_angle without force_solver"))),
+                    },
+                    "threads" => match checkpoint.force_solver {
+                        ForceSolver::ParallelDirect { ref mut threads } => *threads = integer(value)? as usize,
+                        _ => return Err(error(String::from("threads without force_solver"))),
+                    },
+                    "softening" => checkpoint.softening = value.parse().map_err(error)?,
+                    "post_newtonian" => match value {
+                        "true" => checkpoint.post_newtonian = true,
+                        "false" => checkpoint.post_newtonian = false,
+                        _ => return Err(error(format!("post_newtonian: expected true or false, got '{}'", value))),
+                    },
+                    "collisions" => checkpoint.collision_mode = value.parse().map_err(error)?,
+                    "stopped" => match value {
+                        "true" => checkpoint.stopped = true,
+                        "false" => checkpoint.stopped = false,
+                        _ => return Err(error(format!("stopped: expected true or false, got '{}'", value))),
+                    },
+                    "accepted_steps" => checkpoint.accepted_steps = integer(value)? as usize,
+                    "rejected_steps" => checkpoint.rejected_steps = integer(value)? as usize,
+                    "adaptive" => {
+                        let a = numbers(6)?;
+                        let settings = AdaptiveSettings {
+                            abs_tolerance: a[0],
+                            abs_velocity_tolerance: a[1],
+                            rel_tolerance: a[2],
+                            min_dt: a[3],
+                            max_dt: a[4],
+                            safety: a[5],
+                        };
+                        settings.validate().map_err(|message| error(format!("adaptive: {}", message)))?;
+                        checkpoint.adaptive = Some(settings);
+                    }
+                    "output_interval" => checkpoint.output_interval = Some(number(value)?),
+                    "output_origin" => checkpoint.output_origin = number(value)?,
+                    "output_count" => checkpoint.output_count = integer(value)?,
+                    "output_columns" => checkpoint.output_columns = value.parse().map_err(error)?,
+                    "output_units" => checkpoint.output_units = value.parse().map_err(error)?,
+                    "output_frame" => checkpoint.output_frame = value.parse().map_err(error)?,
+                    "drift_reference" => {
+                        let d = numbers(10)?;
+                        checkpoint.drift = Some(DriftTracker {
+                            time: d[0],
+                            energy: d[1],
+                            momentum: [d[2], d[3], d[4]],
+                            angular_momentum: [d[5], d[6], d[7]],
+                            momentum_scale: d[8],
+                            angular_momentum_scale: d[9],
+                            max: Drift::default(),
+                        });
+                    }
+                    // Always written after `drift_reference`
+                    "drift_max" => match checkpoint.drift {
+                        Some(ref mut drift) => {
+                            let m = numbers(3)?;
+                            drift.max = Drift {
+                                energy: m[0],
+                                momentum: m[1],
+                                angular_momentum: m[2],
+                            };
+                        }
+                        None => return Err(error(String::from("drift_max without drift_reference"))),
+                    },
+                    "output_bodies" => {
+                        checkpoint.output_layout = Some(value.split(',').map(|name| name.trim().to_string()).collect())
+                    }
+                    _ => return Err(error(format!("unknown key '{}'", key))),
+                }
+            } else if let Some(body) = checkpoint.bodies.last_mut() {
+                match key {
+                    "name" => body.name = value.to_string(),
+                    "mass" => {
+                        body.mass = number(value)?;
+                        if !(body.mass > 0.0 && body.mass.is_finite()) {
+                            return Err(error(format!("mass: expected a positive number, got '{}'", value)));
+                        }
+                    }
+                    "radius" => body.radius = number(value)?,
+                    "color" => {
+                        let c = value
+                            .split_whitespace()
+                            .map(|text| text.parse::<f32>().map_err(|_| error(format!("color: invalid number '{}'", text))))
+                            .collect::<io::Result<Vec<f32>>>()?;
+                        if c.len() != 3 {
+                            return Err(error(String::from("color: expected 3 numbers")));
+                        }
+                        body.color = Some([c[0], c[1], c[2]]);
+                    }
+                    "position" => body.position = vector()?,
+                    "velocity" => body.velocity = vector()?,
+                    "acceleration" => body.acceleration = vector()?,
+                    _ => return Err(error(format!("unknown key '{}'", key))),
+                }
+            } else {
+                return Err(error(String::from("key outside of any section")));
+            }
+        }
+
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+        match version {
+            Some(v) if v == VERSION as u64 => {}
+            Some(v) => return Err(invalid(&format!("unsupported checkpoint version {}", v))),
+            None => return Err(invalid("not a checkpoint: missing [Checkpoint] version")),
+        }
+        checkpoint.dt = dt.ok_or_else(|| invalid("checkpoint has no dt"))?;
+        if checkpoint.bodies.is_empty() {
+            return Err(invalid("checkpoint contains no bodies"));
+        }
+        if let Some(body) = checkpoint.bodies.iter().find(|body| body.mass.is_nan()) {
+            return Err(invalid(&format!("body '{}' has no mass", body.name)));
+        }
+        Ok(checkpoint)
+    }
+
+    /// Write the checkpoint to a file
+    ///
+    /// The text goes to a temporary file first and is then renamed over
+    /// the target, so an interrupted save never leaves a truncated
+    /// checkpoint behind.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+        fs::write(&temp, self.to_text())?;
+        fs::rename(&temp, path)
+    }
+
+    /// Read a checkpoint from a file
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Checkpoint {
+        let mut moon = Body::new(7.342e22, [3.844e8, 1.0 / 3.0, -0.0], [0.1 + 0.2, 1022.0, 1e-300])
+            .with_name("Moon of Earth")
+            .with_color([0.1, 0.7, 1.0 / 3.0]);
+        moon.acceleration = [-2.7e-3, f64::MIN_POSITIVE, 0.0];
+        Checkpoint {
+            bodies: vec![Body::new(5.972e24, [0.0; 3], [0.0; 3]).with_radius(6.371e6), moon],
+            time: 86400.0 * 7.0 + 1.0 / 7.0,
+            dt: 3599.999999999999,
+            integrator: String::from("rkf45"),
+            force_solver: ForceSolver::barnes_hut(0.35),
+            softening: Softening::spline(1.0 / 3.0),
+            post_newtonian: true,
+            collision_mode: CollisionMode::Merge,
+            stopped: true,
+            adaptive: Some(AdaptiveSettings::new(1e-3, 1e-10, 1.0, 864000.0).unwrap()),
+            accepted_steps: 168,
+            rejected_steps: 3,
+            output_interval: Some(86400.0),
+            output_origin: 0.0,
+            output_count: 7,
+            output_columns: OutputColumns {
+                velocity: true,
+                ..OutputColumns::default()
+            },
+            output_units: UnitSystem::nbody(1.98847e30, 1.0 / 3.0),
+            output_frame: OutputFrame::centred_on("Moon of Earth"),
+            drift: Some(DriftTracker {
+                time: 0.1,
+                energy: -1.0 / 3.0,
+                momentum: [0.0, -0.0, 1e-300],
+                angular_momentum: [1.0, 2.0, 3.0],
+                momentum_scale: 7.0,
+                angular_momentum_scale: 0.0,
+                max: Drift {
+                    energy: 1e-9,
+                    momentum: 0.0,
+                    angular_momentum: 2.0 / 3.0,
+                },
+            }),
+            output_layout: Some(vec![String::from("body0"), String::from("Moon of Earth"), String::from("Io")]),
+        }
+    }
+
+    #[test]
+    fn test_text_roundtrip_is_exact() {
+        let checkpoint = sample();
+        let parsed = Checkpoint::parse(&checkpoint.to_text()).unwrap();
+        assert_eq!(parsed, checkpoint);
+        assert_eq!(parsed.bodies[1].position[2].to_bits(), (-0.0f64).to_bits());
+    }
+
+    #[test]
+    fn test_parallel_solver_roundtrip() {
+        let checkpoint = Checkpoint {
+            force_solver: ForceSolver::ParallelDirect { threads: 6 },
+            ..sample()
+        };
+        assert_eq!(Checkpoint::parse(&checkpoint.to_text()).unwrap(), checkpoint);
+    }
+
+    #[test]
+    fn test_parse_rejects_bad_input() {
+        assert!(Checkpoint::parse("[Body1]\nmass = 1\n").is_err());
+
+        let text = sample().to_text().replace("version = 1", "version = 99");
+        assert!(Checkpoint::parse(&text).is_err());
+
+        let text = sample().to_text().replace("mass = 5.972e24", "mass = heavy");
+        let err = Checkpoint::parse(&text).unwrap_err();
+        assert!(err.to_string().contains("mass: invalid number 'heavy'"), "{}", err);
+
+        // A zero step would never advance a resumed run
+        for dt in ["0e0", "inf", "NaN"] {
+            let text = sample().to_text().replace("dt = 3.599999999999999e3", &format!("dt = {}", dt));
+            let err = Checkpoint::parse(&text).unwrap_err();
+            assert!(err.to_string().contains("dt: expected a finite, non-zero time step"), "{}", err);
+        }
+        let text = sample().to_text().replace("dt = 3.599999999999999e3\n", "");
+        let err = Checkpoint::parse(&text).unwrap_err();
+        assert!(err.to_string().contains("checkpoint has no dt"), "{}", err);
+
+        for mass in ["0e0", "-1e3"] {
+            let text = sample().to_text().replace("mass = 5.972e24", &format!("mass = {}", mass));
+            let err = Checkpoint::parse(&text).unwrap_err();
+            assert!(err.to_string().contains("mass: expected a positive number"), "{}", err);
+        }
+        let text = sample().to_text().replace("mass = 7.342e22\n", "");
+        let err = Checkpoint::parse(&text).unwrap_err();
+        assert!(err.to_string().contains("body 'Moon of Earth' has no mass"), "{}", err);
+
+        // Step bounds the controller could not clamp between
+        let text = sample().to_text().replace("1e-10 1e0 8.64e5", "1e-10 1e6 8.64e5");
+        let err = Checkpoint::parse(&text).unwrap_err();
+        assert!(err.to_string().contains("adaptive: maximum time step"), "{}", err);
+    }
+}
@@ -0,0 +1,650 @@
+//! Command-line interface of the `threebody-sim` binary
+//!
+//! ```text
+//! threebody-sim [run] [OPTIONS] [CONFIG] [OUTPUT]
+//! threebody-sim validate [OPTIONS] [CONFIG]
+//! threebody-sim info [OPTIONS] [CONFIG]
+//! threebody-sim export [OPTIONS] TRAJECTORY OUTPUT.npz
+//! threebody-sim help [COMMAND]
+//! ```
+//!
+//! `run` is the default, so `threebody-sim config.ini out.csv` still works.
+//! Options take their value as the next argument or after `=`. Parsing
+//! lives here rather than in the binary so that it can be tested; the
+//! binary maps a `Command` to its work and its outcome to one of the exit
+//! codes below. `--json` prints one JSON object on stdout, built with
+//! `JsonObject`, and `--quiet` prints nothing but warnings and errors.
+
+use crate::config::{ParseMode, SimulationConfig};
+use crate::integrator::IntegratorKind;
+use crate::simulator::AdaptiveSettings;
+use std::fmt;
+
+/// Everything went as asked; for `validate`, the configuration is valid
+pub const EXIT_SUCCESS: i32 = 0;
+/// The simulation or its files failed
+pub const EXIT_FAILURE: i32 = 1;
+/// The command line could not be understood
+pub const EXIT_USAGE: i32 = 2;
+/// The configuration file is missing or invalid
+pub const EXIT_INVALID_CONFIG: i32 = 3;
+
+/// Configuration read when none is given
+pub const DEFAULT_CONFIG: &str = "./data/ic.ini";
+
+/// How much the binary prints
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputMode {
+    /// Progress and results for a person to read
+    #[default]
+    Normal,
+    /// Only warnings and errors, on stderr
+    Quiet,
+    /// A single JSON object on stdout
+    Json,
+}
+
+/// A parsed command line
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Run(RunOptions),
+    Validate(CheckOptions),
+    Info(CheckOptions),
+    Export(ExportOptions),
+    /// Print the help, for one command or in general
+    Help(Option<String>),
+}
+
+/// Options shared by every command that reads a configuration
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheckOptions {
+    pub config: String,
+    pub parse_mode: ParseMode,
+    pub output_mode: OutputMode,
+}
+
+impl Default for CheckOptions {
+    fn default() -> Self {
+        CheckOptions {
+            config: String::from(DEFAULT_CONFIG),
+            parse_mode: ParseMode::Strict,
+            output_mode: OutputMode::Normal,
+        }
+    }
+}
+
+/// Options of the `export` command
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExportOptions {
+    /// Trajectory file, binary or CSV
+    pub input: String,
+    /// `.npz` archive to write
+    pub output: String,
+    pub output_mode: OutputMode,
+}
+
+/// Options of the `run` command
+///
+/// Times given on the command line are in the configuration's `units`,
+/// like the values in the file they override.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunOptions {
+    pub check: CheckOptions,
+    /// Output CSV, overriding the configuration's `output_file`
+    pub output: Option<String>,
+    pub steps: Option<usize>,
+    pub time_step: Option<f64>,
+    pub duration: Option<f64>,
+    pub integrator: Option<IntegratorKind>,
+    /// Relative tolerance; switches on adaptive stepping
+    pub tolerance: Option<f64>,
+    pub output_interval: Option<f64>,
+    pub barycentric: bool,
+    pub centre_on: Option<String>,
+    pub resume: Option<String>,
+    pub checkpoint: Option<String>,
+    /// Accepted steps between checkpoints; `usize::MAX` saves one at the end only
+    pub checkpoint_every: usize,
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        RunOptions {
+            check: CheckOptions::default(),
+            output: None,
+            steps: None,
+            time_step: None,
+            duration: None,
+            integrator: None,
+            tolerance: None,
+            output_interval: None,
+            barycentric: false,
+            centre_on: None,
+            resume: None,
+            checkpoint: None,
+            checkpoint_every: usize::MAX,
+        }
+    }
+}
+
+impl RunOptions {
+    /// Override the configuration with the stepping options given
+    pub fn apply(&self, config: &mut SimulationConfig) {
+        let time = config.units.time();
+        if let Some(steps) = self.steps {
+            config.num_steps = steps;
+            config.duration = None;
+        }
+        if let Some(duration) = self.duration {
+            config.duration = Some(duration * time);
+        }
+        if let Some(time_step) = self.time_step {
+            config.time_step = time_step * time;
+        }
+        if let Some(integrator) = self.integrator {
+            config.integrator = integrator;
+        }
+        if let Some(interval) = self.output_interval {
+            config.output_interval = Some(interval * time);
+        }
+        if let Some(tolerance) = self.tolerance {
+            let settings = config.adaptive.get_or_insert_with(AdaptiveSettings::default);
+            settings.rel_tolerance = tolerance;
+        }
+    }
+}
+
+/// A command line that could not be understood
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UsageError(pub String);
+
+impl fmt::Display for UsageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for UsageError {}
+
+/// Parse the arguments after the program name
+pub fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Command, UsageError> {
+    let mut args = args.into_iter().peekable();
+    let explicit = match args.peek().map(String::as_str) {
+        Some(name @ ("run" | "validate" | "info" | "export" | "help")) => Some(name.to_string()),
+        _ => None,
+    };
+    if explicit.is_some() {
+        args.next();
+    }
+    let name = explicit.clone().unwrap_or_else(|| String::from("run"));
+    if name == "help" {
+        return Ok(Command::Help(args.next()));
+    }
+
+    let mut run = RunOptions::default();
+    let mut positional: Vec<String> = Vec::new();
+    while let Some(arg) = args.next() {
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value.to_string())),
+            _ => (arg.clone(), None),
+        };
+        let mut value = || -> Result<String, UsageError> {
+            match inline.clone().or_else(|| args.next()) {
+                Some(value) => Ok(value),
+                None => Err(UsageError(format!("{} needs a value", flag))),
+            }
+        };
+        let run_only = || -> Result<(), UsageError> {
+            if name == "run" {
+                Ok(())
+            } else {
+                Err(UsageError(format!("{} is only accepted by run", flag)))
+            }
+        };
+        match flag.as_str() {
+            "-h" | "--help" => return Ok(Command::Help(explicit)),
+            "--lenient" if name == "export" => {
+                return Err(UsageError(String::from("--lenient is not accepted by export")));
+            }
+            "--lenient" => run.check.parse_mode = ParseMode::Lenient,
+            "-q" | "--quiet" => run.check.output_mode = OutputMode::Quiet,
+            "--json" => run.check.output_mode = OutputMode::Json,
+            "-o" | "--output" => {
+                run_only()?;
+                run.output = Some(value()?);
+            }
+            "--steps" => {
+                run_only()?;
+                run.steps = Some(parse_value(&flag, &value()?, "a number of steps")?);
+            }
+            "--dt" => {
+                run_only()?;
+                run.time_step = Some(parse_positive(&flag, &value()?)?);
+            }
+            "--duration" => {
+                run_only()?;
+                run.duration = Some(parse_positive(&flag, &value()?)?);
+            }
+            "--integrator" => {
+                run_only()?;
+                let expected = "rkf45, leapfrog, yoshida4 or wisdom-holman";
+                run.integrator = Some(parse_value(&flag, &value()?, expected)?);
+            }
+            "--tolerance" => {
+                run_only()?;
+                run.tolerance = Some(parse_positive(&flag, &value()?)?);
+            }
+            "--output-interval" => {
+                run_only()?;
+                run.output_interval = Some(parse_positive(&flag, &value()?)?);
+            }
+            "--barycentric" => {
+                run_only()?;
+                run.barycentric = true;
+            }
+            "--centre-on" => {
+                run_only()?;
+                run.centre_on = Some(value()?);
+            }
+            "--resume" => {
+                run_only()?;
+                run.resume = Some(value()?);
+            }
+            "--checkpoint" => {
+                run_only()?;
+                run.checkpoint = Some(value()?);
+            }
+            "--checkpoint-every" => {
+                run_only()?;
+                let steps: usize = parse_value(&flag, &value()?, "a positive number of steps")?;
+                if steps == 0 {
+                    return Err(UsageError(String::from("--checkpoint-every needs a positive number of steps")));
+                }
+                run.checkpoint_every = steps;
+            }
+            _ if arg.starts_with('-') && arg.len() > 1 => {
+                return Err(UsageError(format!("unknown option {}", arg)));
+            }
+            _ => positional.push(arg),
+        }
+    }
+    if run.steps.is_some() && run.duration.is_some() {
+        return Err(UsageError(String::from("--steps and --duration cannot be combined")));
+    }
+    if let Some(integrator) = run.integrator.filter(|integrator| !integrator.has_error_estimate()) {
+        if run.tolerance.is_some() {
+            return Err(UsageError(format!(
+                "--tolerance cannot be combined with --integrator {}, which has no error estimate",
+                integrator.name()
+            )));
+        }
+    }
+    // A checkpoint fixes the stepping and the frame; only the run length
+    // and the output files can change on resume
+    if run.resume.is_some() {
+        let overrides = [
+            ("--dt", run.time_step.is_some()),
+            ("--integrator", run.integrator.is_some()),
+            ("--tolerance", run.tolerance.is_some()),
+            ("--output-interval", run.output_interval.is_some()),
+            ("--barycentric", run.barycentric),
+            ("--centre-on", run.centre_on.is_some()),
+        ];
+        if let Some((flag, _)) = overrides.iter().find(|(_, given)| *given) {
+            return Err(UsageError(format!(
+                "{} cannot be combined with --resume, which continues with the checkpoint's settings",
+                flag
+            )));
+        }
+    }
+
+    if name == "export" {
+        let [input, output]: [String; 2] = positional
+            .try_into()
+            .map_err(|_| UsageError(String::from("export needs a trajectory file and an output file")))?;
+        return Ok(Command::Export(ExportOptions {
+            input,
+            output,
+            output_mode: run.check.output_mode,
+        }));
+    }
+    let most = if name == "run" { 2 } else { 1 };
+    if positional.len() > most {
+        return Err(UsageError(format!("unexpected argument {}", positional[most])));
+    }
+    let mut positional = positional.into_iter();
+    if let Some(config) = positional.next() {
+        run.check.config = config;
+    }
+    if let Some(output) = positional.next() {
+        if run.output.is_some() {
+            return Err(UsageError(String::from("the output file is given twice")));
+        }
+        run.output = Some(output);
+    }
+
+    Ok(match name.as_str() {
+        "validate" => Command::Validate(run.check),
+        "info" => Command::Info(run.check),
+        _ => Command::Run(run),
+    })
+}
+
+fn parse_value<T: std::str::FromStr>(flag: &str, value: &str, expected: &str) -> Result<T, UsageError> {
+    value
+        .parse()
+        .map_err(|_| UsageError(format!("{}: invalid value '{}' (expected {})", flag, value, expected)))
+}
+
+fn parse_positive(flag: &str, value: &str) -> Result<f64, UsageError> {
+    match parse_value::<f64>(flag, value, "a positive number")? {
+        number if number > 0.0 && number.is_finite() => Ok(number),
+        _ => Err(UsageError(format!("{}: invalid value '{}' (expected a positive number)", flag, value))),
+    }
+}
+
+/// Help text for a command, or the general help for `None` or an unknown
+/// command
+pub fn help(command: Option<&str>) -> &'static str {
+    match command {
+        Some("run") => RUN_HELP,
+        Some("validate") => VALIDATE_HELP,
+        Some("info") => INFO_HELP,
+        Some("export") => EXPORT_HELP,
+        _ => GENERAL_HELP,
+    }
+}
+
+const GENERAL_HELP: &str = "\
+N-body gravitational simulator
+
+Usage: threebody-sim [COMMAND] [OPTIONS] [CONFIG] [OUTPUT]
+
+Commands:
+  run       Run a simulation (the default)
+  validate  Check a configuration file without running it
+  info      Print the bodies and energies of a configuration
+  export    Convert a trajectory file to a NumPy .npz archive
+  help      Print this help, or the help of a command
+
+Options for every command:
+  --lenient     Skip invalid config entries with a warning (not export)
+  -q, --quiet   Print only warnings and errors
+  --json        Print one JSON object on stdout
+  -h, --help    Print help
+
+CONFIG defaults to ./data/ic.ini.
+
+Exit codes: 0 success, 1 simulation or file error, 2 bad command line,
+3 missing or invalid configuration.
+";
+
+const RUN_HELP: &str = "\
+Run a simulation
+
+Usage: threebody-sim [run] [OPTIONS] [CONFIG] [OUTPUT]
+
+OUTPUT overrides the configuration's output_file. Times are in the
+configuration's units.
+
+Options:
+  -o, --output FILE          Output CSV, like OUTPUT
+  --steps N                  Number of steps, instead of the duration
+  --duration T               Simulated time to run for
+  --dt T                     Time step (the first step when adaptive)
+  --integrator NAME          rkf45, leapfrog, yoshida4 or wisdom-holman
+  --tolerance X              Relative tolerance; enables adaptive stepping (rkf45)
+  --output-interval T        Time between output rows
+  --barycentric              Shift the bodies to the centre-of-mass frame
+  --centre-on NAME           Write the output centred on a body
+  --checkpoint FILE          Save a checkpoint when the run ends
+  --checkpoint-every N       Also save it every N accepted steps
+  --resume FILE              Continue from a checkpoint, appending to the output;
+                             not with --dt, --integrator, --tolerance,
+                             --output-interval, --barycentric or --centre-on
+  --lenient                  Skip invalid config entries with a warning
+  -q, --quiet                Print only warnings and errors
+  --json                     Print a summary of the run as JSON
+  -h, --help                 Print help
+";
+
+const VALIDATE_HELP: &str = "\
+Check a configuration file without running it
+
+Usage: threebody-sim validate [OPTIONS] [CONFIG]
+
+Exits with 0 if the configuration is valid and 3 if it is not. With
+--lenient, problems that can be skipped are reported as warnings.
+
+Options:
+  --lenient     Skip invalid config entries with a warning
+  -q, --quiet   Print only warnings and errors
+  --json        Print the result as JSON
+  -h, --help    Print help
+";
+
+const INFO_HELP: &str = "\
+Print the bodies and energies of a configuration
+
+Usage: threebody-sim info [OPTIONS] [CONFIG]
+
+Shows each body's mass, position and velocity with the system's kinetic,
+potential and total energy, momentum, angular momentum, centre of mass and
+virial ratio, all in SI.
+
+Options:
+  --lenient     Skip invalid config entries with a warning
+  -q, --quiet   Print only warnings and errors
+  --json        Print the information as JSON
+  -h, --help    Print help
+";
+
+const EXPORT_HELP: &str = "\
+Convert a trajectory file to a NumPy .npz archive
+
+Usage: threebody-sim export [OPTIONS] TRAJECTORY OUTPUT.npz
+
+TRAJECTORY is a binary trajectory file (binary_file in the config) or a
+CSV output file. Binary files give full precision, times, masses and
+units, and are converted one frame at a time.
+
+Options:
+  -q, --quiet   Print only errors
+  --json        Print the result as JSON
+  -h, --help    Print help
+";
+
+/// A JSON object written field by field
+///
+/// Non-finite numbers, which JSON cannot represent, are written as `null`.
+#[derive(Debug, Clone, Default)]
+pub struct JsonObject {
+    fields: Vec<String>,
+}
+
+impl JsonObject {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a field holding already formatted JSON
+    pub fn raw(mut self, key: &str, json: String) -> Self {
+        self.fields.push(format!("{}:{}", json_string(key), json));
+        self
+    }
+
+    pub fn string(self, key: &str, value: &str) -> Self {
+        self.raw(key, json_string(value))
+    }
+
+    pub fn number(self, key: &str, value: f64) -> Self {
+        self.raw(key, json_number(value))
+    }
+
+    pub fn integer(self, key: &str, value: u64) -> Self {
+        self.raw(key, value.to_string())
+    }
+
+    pub fn boolean(self, key: &str, value: bool) -> Self {
+        self.raw(key, value.to_string())
+    }
+
+    pub fn vector(self, key: &str, value: [f64; 3]) -> Self {
+        self.raw(key, json_array(value.iter().map(|&x| json_number(x))))
+    }
+
+    /// Add a string field, or `null` for `None`
+    pub fn optional_string(self, key: &str, value: Option<&str>) -> Self {
+        self.raw(key, value.map_or(String::from("null"), json_string))
+    }
+}
+
+impl fmt::Display for JsonObject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{{{}}}", self.fields.join(","))
+    }
+}
+
+/// A string as a quoted JSON string
+pub fn json_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for ch in value.chars() {
+        match ch {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            ch if (ch as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => quoted.push(ch),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// A number as JSON, in exact round-trip form, or `null` if not finite
+pub fn json_number(value: f64) -> String {
+    if value.is_finite() {
+        format!("{:e}", value)
+    } else {
+        String::from("null")
+    }
+}
+
+/// Already formatted JSON values as an array
+pub fn json_array<I: IntoIterator<Item = String>>(values: I) -> String {
+    format!("[{}]", values.into_iter().collect::<Vec<_>>().join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, UsageError> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_run_is_the_default() {
+        let Command::Run(run) = parse(&["./data/earth_moon.ini", "out.csv", "--lenient"]).unwrap() else {
+            panic!("expected run");
+        };
+        assert_eq!(run.check.config, "./data/earth_moon.ini");
+        assert_eq!(run.output.as_deref(), Some("out.csv"));
+        assert_eq!(run.check.parse_mode, ParseMode::Lenient);
+
+        let Command::Run(run) = parse(&[]).unwrap() else {
+            panic!("expected run");
+        };
+        assert_eq!(run, RunOptions::default());
+    }
+
+    #[test]
+    fn test_run_options() {
+        let args = [
+            "run", "--steps=500", "--dt", "60", "--integrator", "rkf45", "--tolerance", "1e-9",
+            "--output-interval", "3600", "--checkpoint-every", "10", "--json", "sim.ini",
+        ];
+        let Command::Run(run) = parse(&args).unwrap() else {
+            panic!("expected run");
+        };
+        assert_eq!(run.steps, Some(500));
+        assert_eq!(run.time_step, Some(60.0));
+        assert_eq!(run.integrator, Some(IntegratorKind::Rkf45));
+        assert_eq!(run.checkpoint_every, 10);
+        assert_eq!(run.check.output_mode, OutputMode::Json);
+        assert_eq!(run.check.config, "sim.ini");
+
+        let mut config = SimulationConfig {
+            duration: Some(1e6),
+            ..SimulationConfig::default()
+        };
+        run.apply(&mut config);
+        assert_eq!((config.num_steps, config.duration), (500, None));
+        assert_eq!(config.time_step, 60.0);
+        assert_eq!(config.output_interval, Some(3600.0));
+        assert_eq!(config.adaptive.unwrap().rel_tolerance, 1e-9);
+    }
+
+    #[test]
+    fn test_other_commands() {
+        let Command::Validate(check) = parse(&["validate", "-q", "a.ini"]).unwrap() else {
+            panic!("expected validate");
+        };
+        assert_eq!(check.config, "a.ini");
+        assert_eq!(check.output_mode, OutputMode::Quiet);
+        assert!(matches!(parse(&["info"]).unwrap(), Command::Info(_)));
+        let Command::Export(export) = parse(&["export", "run.traj", "run.npz", "--json"]).unwrap() else {
+            panic!("expected export");
+        };
+        assert_eq!((export.input.as_str(), export.output.as_str()), ("run.traj", "run.npz"));
+        assert_eq!(export.output_mode, OutputMode::Json);
+        assert_eq!(parse(&["info", "--help"]).unwrap(), Command::Help(Some(String::from("info"))));
+        assert_eq!(parse(&["help", "run"]).unwrap(), Command::Help(Some(String::from("run"))));
+        assert_eq!(parse(&["a.ini", "-h"]).unwrap(), Command::Help(None));
+        assert_eq!(help(Some("run")), RUN_HELP);
+    }
+
+    #[test]
+    fn test_usage_errors() {
+        let cases: [&[&str]; 17] = [
+            &["--frobnicate"],
+            &["--dt"],
+            &["--dt", "0"],
+            &["--dt", "-60"],
+            &["--duration", "-5"],
+            &["--duration=inf"],
+            &["--steps", "10", "--duration", "5"],
+            &["validate", "--steps", "10"],
+            &["info", "a.ini", "b.ini"],
+            &["a.ini", "b.csv", "c"],
+            &["export", "run.traj"],
+            &["export", "run.traj", "run.npz", "--lenient"],
+            &["export", "run.traj", "run.npz", "--steps", "5"],
+            &["--integrator", "leapfrog", "--tolerance", "1e-9"],
+            &["--resume", "run.ckpt", "--dt", "60"],
+            &["--resume=run.ckpt", "--integrator", "leapfrog"],
+            &["--centre-on", "Sun", "--resume", "run.ckpt"],
+        ];
+        for args in cases {
+            assert!(parse(args).is_err(), "for {:?}", args);
+        }
+    }
+
+    #[test]
+    fn test_json_object() {
+        let json = JsonObject::new()
+            .string("name", "a \"quoted\"\nname")
+            .number("energy", -1.5e30)
+            .number("lost", f64::NAN)
+            .vector("v", [1.0, 0.0, -2.0])
+            .optional_string("file", None)
+            .to_string();
+        assert_eq!(
+            json,
+            r#"{"name":"a \"quoted\"\nname","energy":-1.5e30,"lost":null,"v":[1e0,0e0,-2e0],"file":null}"#
+        );
+    }
+}
@@ -0,0 +1,381 @@
+//! Collision detection and response
+//!
+//! Bodies with a radius collide when their spheres overlap. Detection runs
+//! after every accepted step and follows each pair along a straight line
+//! from its start-of-step to its end-of-step separation, so fast bodies
+//! that pass through each other within one step are still caught. What
+//! happens next is chosen with `CollisionMode`:
+//!
+//! - `Merge`: the pair becomes one body (a perfectly inelastic collision)
+//!   that conserves mass, momentum and volume and keeps the name of the
+//!   heavier body
+//! - `Bounce`: the approaching pair swaps momentum along the line of
+//!   centres (an elastic collision); a pair that already passed through
+//!   each other by the end of the step is no longer approaching and is
+//!   left alone, so the step must resolve the approach
+//! - `Stop`: the run halts at the end of the step
+//!
+//! Every collision is reported as a `CollisionEvent`.
+
+use crate::body::Body;
+use crate::vector::{difference, dot};
+use std::fmt;
+use std::str::FromStr;
+
+/// What to do when two bodies overlap
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CollisionMode {
+    /// Bodies pass through each other as point masses
+    #[default]
+    Ignore,
+    /// Replace the pair by a single body
+    Merge,
+    /// Reflect the pair elastically
+    Bounce,
+    /// Halt the simulation
+    Stop,
+}
+
+impl CollisionMode {
+    /// Name used in configuration files and messages
+    pub fn name(&self) -> &'static str {
+        match self {
+            CollisionMode::Ignore => "ignore",
+            CollisionMode::Merge => "merge",
+            CollisionMode::Bounce => "bounce",
+            CollisionMode::Stop => "stop",
+        }
+    }
+}
+
+impl FromStr for CollisionMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "ignore" | "none" | "off" => Ok(CollisionMode::Ignore),
+            "merge" => Ok(CollisionMode::Merge),
+            "bounce" => Ok(CollisionMode::Bounce),
+            "stop" => Ok(CollisionMode::Stop),
+            other => Err(format!(
+                "unknown collision mode '{}' (expected ignore, merge, bounce or stop)",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for CollisionMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// How a detected collision was resolved
+#[derive(Debug, Clone, PartialEq)]
+pub enum CollisionOutcome {
+    /// The pair was replaced by one body with the given name
+    Merged { into: String },
+    /// The pair bounced apart
+    Bounced,
+    /// The simulation was halted
+    Stopped,
+}
+
+/// A collision between two bodies
+#[derive(Debug, Clone, PartialEq)]
+pub struct CollisionEvent {
+    /// Estimated time of first contact
+    pub time: f64,
+    /// Names of the colliding bodies
+    pub bodies: (String, String),
+    /// Relative speed of the pair at the end of the step
+    pub relative_speed: f64,
+    pub outcome: CollisionOutcome,
+}
+
+impl fmt::Display for CollisionEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "t = {:.2} s: {} and {} collided at {:.4e} m/s",
+            self.time, self.bodies.0, self.bodies.1, self.relative_speed
+        )?;
+        match &self.outcome {
+            CollisionOutcome::Merged { into } => write!(f, ", merged into {}", into),
+            CollisionOutcome::Bounced => f.write_str(", bounced"),
+            CollisionOutcome::Stopped => f.write_str(", simulation stopped"),
+        }
+    }
+}
+
+/// A pair of bodies found to be in contact during a step
+struct Contact {
+    i: usize,
+    j: usize,
+    /// Fraction of the step at which the spheres first touch
+    fraction: f64,
+}
+
+/// First fraction of the step at which two spheres touch, if they do
+///
+/// The separation is taken to change linearly from `start` to `end`.
+fn contact_fraction(start: [f64; 3], end: [f64; 3], reach: f64) -> Option<f64> {
+    let reach_squared = reach * reach;
+    if dot(start, start) < reach_squared {
+        return Some(0.0);
+    }
+    // Solve |start + s (end - start)|^2 = reach^2 for the smaller root
+    let motion = difference(end, start);
+    let a = dot(motion, motion);
+    let b = 2.0 * dot(start, motion);
+    let c = dot(start, start) - reach_squared;
+    let discriminant = b * b - 4.0 * a * c;
+    if a == 0.0 || discriminant < 0.0 {
+        return None;
+    }
+    let s = (-b - discriminant.sqrt()) / (2.0 * a);
+    (0.0..=1.0).contains(&s).then_some(s)
+}
+
+/// Pairs of bodies whose spheres touched during a step, earliest first
+///
+/// `before` holds the bodies at the start of the step and must line up
+/// with `after`. Bodies without a radius never collide.
+fn contacts(before: &[Body], after: &[Body]) -> Vec<Contact> {
+    let mut found = Vec::new();
+    for i in 0..after.len() {
+        for j in (i + 1)..after.len() {
+            let reach = after[i].radius + after[j].radius;
+            if reach <= 0.0 {
+                continue;
+            }
+            let start = difference(before[j].position, before[i].position);
+            let end = difference(after[j].position, after[i].position);
+            if let Some(fraction) = contact_fraction(start, end, reach) {
+                found.push(Contact { i, j, fraction });
+            }
+        }
+    }
+    found.sort_by(|a, b| a.fraction.total_cmp(&b.fraction));
+    found
+}
+
+fn relative_speed(a: &Body, b: &Body) -> f64 {
+    let v = difference(b.velocity, a.velocity);
+    dot(v, v).sqrt()
+}
+
+/// Combine two bodies into one, conserving mass, momentum and volume
+///
+/// The result takes the name and colour of the heavier body (the first on
+/// a tie) and sits at the pair's centre of mass.
+pub fn merge(a: &Body, b: &Body) -> Body {
+    let mass = a.mass + b.mass;
+    let weighted = |x: [f64; 3], y: [f64; 3]| {
+        [
+            (a.mass * x[0] + b.mass * y[0]) / mass,
+            (a.mass * x[1] + b.mass * y[1]) / mass,
+            (a.mass * x[2] + b.mass * y[2]) / mass,
+        ]
+    };
+    let heavier = if b.mass > a.mass { b } else { a };
+    Body {
+        name: heavier.name.clone(),
+        mass,
+        radius: (a.radius.powi(3) + b.radius.powi(3)).cbrt(),
+        color: heavier.color,
+        position: weighted(a.position, b.position),
+        velocity: weighted(a.velocity, b.velocity),
+        acceleration: weighted(a.acceleration, b.acceleration),
+    }
+}
+
+/// Reverse the approaching part of two bodies' relative velocity along
+/// the line between their centres; returns whether they were approaching
+pub fn bounce(a: &mut Body, b: &mut Body) -> bool {
+    let separation = difference(b.position, a.position);
+    let distance = dot(separation, separation).sqrt();
+    if distance == 0.0 {
+        return false;
+    }
+    let normal = separation.map(|x| x / distance);
+    let approach = dot(difference(b.velocity, a.velocity), normal);
+    if approach >= 0.0 {
+        return false;
+    }
+    // Elastic impulse along the normal, shared by inverse mass
+    let impulse = 2.0 * approach / (1.0 / a.mass + 1.0 / b.mass);
+    for (k, n) in normal.iter().enumerate() {
+        a.velocity[k] += impulse / a.mass * n;
+        b.velocity[k] -= impulse / b.mass * n;
+    }
+    true
+}
+
+/// Detect and resolve the collisions of one step
+///
+/// `before` and `after` are the bodies at the start and end of a step of
+/// length `dt` that ended at `time`. Bodies are merged, bounced or left
+/// in place according to `mode`, and the collisions are returned in the
+/// order they happened. Merged bodies are removed from `after`, so any
+/// unnamed body is first given its `Body::label` to keep names stable.
+pub fn resolve(
+    mode: CollisionMode,
+    before: &[Body],
+    after: &mut Vec<Body>,
+    time: f64,
+    dt: f64,
+) -> Vec<CollisionEvent> {
+    let mut events = Vec::new();
+    if mode == CollisionMode::Ignore {
+        return events;
+    }
+    let event_time = |fraction: f64| time - dt + fraction * dt;
+    let names = |bodies: &[Body], i: usize, j: usize| (bodies[i].label(i), bodies[j].label(j));
+
+    match mode {
+        CollisionMode::Ignore => {}
+        CollisionMode::Stop => {
+            for contact in contacts(before, after) {
+                events.push(CollisionEvent {
+                    time: event_time(contact.fraction),
+                    bodies: names(after, contact.i, contact.j),
+                    relative_speed: relative_speed(&after[contact.i], &after[contact.j]),
+                    outcome: CollisionOutcome::Stopped,
+                });
+            }
+        }
+        CollisionMode::Bounce => {
+            for contact in contacts(before, after) {
+                let (head, tail) = after.split_at_mut(contact.j);
+                let (a, b) = (&mut head[contact.i], &mut tail[0]);
+                let speed = relative_speed(a, b);
+                if bounce(a, b) {
+                    events.push(CollisionEvent {
+                        time: event_time(contact.fraction),
+                        bodies: names(after, contact.i, contact.j),
+                        relative_speed: speed,
+                        outcome: CollisionOutcome::Bounced,
+                    });
+                }
+            }
+        }
+        CollisionMode::Merge => {
+            // Merge the earliest pair and look again, since the merged body
+            // may now reach a third one
+            let mut before = before.to_vec();
+            while let Some(contact) = contacts(&before, after).into_iter().next() {
+                for (idx, body) in after.iter_mut().enumerate() {
+                    if body.name.is_empty() {
+                        body.name = body.label(idx);
+                    }
+                }
+                let (i, j) = (contact.i, contact.j);
+                let merged = merge(&after[i], &after[j]);
+                events.push(CollisionEvent {
+                    time: event_time(contact.fraction),
+                    bodies: names(after, i, j),
+                    relative_speed: relative_speed(&after[i], &after[j]),
+                    outcome: CollisionOutcome::Merged {
+                        into: merged.name.clone(),
+                    },
+                });
+                // The merged body has been in contact all step long
+                before[i] = merged.clone();
+                before.remove(j);
+                after[i] = merged;
+                after.remove(j);
+            }
+        }
+    }
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ball(name: &str, mass: f64, x: f64, vx: f64) -> Body {
+        Body::new(mass, [x, 0.0, 0.0], [vx, 0.0, 0.0])
+            .with_name(name)
+            .with_radius(1.0)
+    }
+
+    #[test]
+    fn test_merge_conserves_mass_momentum_and_volume() {
+        let a = ball("A", 3.0, 0.0, 2.0);
+        let b = ball("B", 1.0, 1.5, -2.0).with_radius(2.0);
+        let merged = merge(&a, &b);
+
+        assert_eq!(merged.name, "A");
+        assert_eq!(merged.mass, 4.0);
+        assert_eq!(merged.velocity[0], 1.0);
+        assert_eq!(merged.position[0], 0.375);
+        assert!((merged.radius.powi(3) - 9.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_bounce_is_elastic() {
+        let mut a = ball("A", 2.0, 0.0, 1.0);
+        let mut b = ball("B", 1.0, 1.9, -1.0);
+        let energy = |a: &Body, b: &Body| a.kinetic_energy() + b.kinetic_energy();
+        let before = energy(&a, &b);
+
+        assert!(bounce(&mut a, &mut b));
+        assert!((2.0 * a.velocity[0] + b.velocity[0] - 1.0).abs() < 1e-12);
+        assert!((energy(&a, &b) - before).abs() < 1e-12);
+        assert!(b.velocity[0] > a.velocity[0]);
+
+        // Separating bodies are left alone
+        assert!(!bounce(&mut a, &mut b));
+    }
+
+    #[test]
+    fn test_detects_pass_through_within_a_step() {
+        // The pair starts and ends 4 apart but crosses in between
+        let before = vec![ball("A", 1.0, -2.0, 4.0), ball("B", 1.0, 2.0, -4.0)];
+        let mut after = vec![ball("A", 1.0, 2.0, 4.0), ball("B", 1.0, -2.0, -4.0)];
+        let events = resolve(CollisionMode::Merge, &before, &mut after, 11.0, 1.0);
+
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].bodies, ("A".to_string(), "B".to_string()));
+        // Contact at separation 2 after a quarter of the step
+        assert!((events[0].time - 10.25).abs() < 1e-12);
+        assert_eq!(after.len(), 1);
+        assert_eq!(after[0].velocity[0], 0.0);
+    }
+
+    #[test]
+    fn test_chain_merge_and_stable_names() {
+        let before = vec![
+            Body::new(1.0, [0.0; 3], [0.0; 3]).with_radius(1.0),
+            Body::new(5.0, [1.5, 0.0, 0.0], [0.0; 3]).with_radius(1.0),
+            Body::new(1.0, [10.0, 0.0, 0.0], [0.0; 3]).with_radius(1.0),
+            Body::new(1.0, [3.0, 0.0, 0.0], [0.0; 3]).with_radius(1.0),
+        ];
+        let mut after = before.clone();
+        let events = resolve(CollisionMode::Merge, &before, &mut after, 1.0, 1.0);
+
+        // body0 and body3 both touch body1 and end up inside it, while
+        // body2 keeps the name it had by position
+        assert_eq!(events.len(), 2);
+        assert_eq!(after.len(), 2);
+        assert_eq!(after[0].name, "body1");
+        assert_eq!(after[0].mass, 7.0);
+        assert_eq!(after[1].name, "body2");
+    }
+
+    #[test]
+    fn test_parse_collision_mode() {
+        assert_eq!(
+            "Merge".parse::<CollisionMode>().unwrap(),
+            CollisionMode::Merge
+        );
+        assert_eq!(
+            "none".parse::<CollisionMode>().unwrap(),
+            CollisionMode::Ignore
+        );
+        assert!("explode".parse::<CollisionMode>().is_err());
+    }
+}
@@ -1,163 +1,1233 @@
 //! Configuration and initial conditions file parsing
 //!
 //! Handles parsing INI-format initial condition files for N-body simulations.
+//! Problems are reported as `ConfigError`s carrying the file, line, section
+//! and key they refer to. In strict mode (the default) the first problem
+//! fails the parse; in lenient mode problems are collected as warnings and
+//! the offending value or body is skipped.
+
+// Errors carry their full location; parsing is far from any hot path
+#![allow(clippy::result_large_err)]
 
 use crate::body::Body;
+use crate::collision::CollisionMode;
+use crate::forces::ForceSolver;
+use crate::frame::{self, OutputFrame};
+use crate::integrator::IntegratorKind;
+use crate::orbit::{Anomaly, OrbitalElements};
+use crate::output::OutputColumns;
+use crate::simulator::AdaptiveSettings;
+use crate::softening::Softening;
+use crate::units::UnitSystem;
+use std::fmt;
 use std::fs;
 
 /// Configuration for a simulation run
+///
+/// Every quantity is in SI, whatever units the file was written in.
 #[derive(Debug, Clone)]
 pub struct SimulationConfig {
     pub bodies: Vec<Body>,
     pub time_step: f64,
     pub num_steps: usize,
+    /// Total simulated time; when set it takes precedence over `num_steps`
+    pub duration: Option<f64>,
     pub output_file: String,
+    /// Time between output rows; `None` writes one row per step
+    pub output_interval: Option<f64>,
+    /// Optional columns written alongside the positions
+    pub output_columns: OutputColumns,
+    /// Units the values in the file were given in
+    pub units: UnitSystem,
+    /// Units of the output columns
+    pub output_units: UnitSystem,
+    /// Binary trajectory file written alongside the CSV output, if any
+    pub binary_file: Option<String>,
+    /// CSV file for conserved-quantity diagnostics, if any
+    pub diagnostics_file: Option<String>,
+    /// CSV file for osculating orbital elements, if any
+    pub elements_file: Option<String>,
+    /// Body the orbital elements are taken around; the most massive body
+    /// when not given
+    pub elements_primary: Option<String>,
+    /// Whether the bodies were shifted into the centre-of-mass frame
+    pub barycentric: bool,
+    /// Frame the output columns are written in
+    pub output_frame: OutputFrame,
+    pub integrator: IntegratorKind,
+    /// How gravitational accelerations are computed
+    pub force_solver: ForceSolver,
+    /// Short-range softening of forces and potential energy
+    pub softening: Softening,
+    /// Whether the 1PN relativistic correction is applied
+    pub post_newtonian: bool,
+    /// What happens when bodies overlap
+    pub collision_mode: CollisionMode,
+    /// Error-control settings when adaptive stepping is enabled
+    pub adaptive: Option<AdaptiveSettings>,
+    /// Problems skipped while parsing in lenient mode
+    pub warnings: Vec<ConfigError>,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        SimulationConfig {
+            bodies: Vec::new(),
+            time_step: 86400.0, // 1 day default
+            num_steps: 1000,    // 1000 steps default
+            duration: None,
+            output_file: String::from("./data/results.csv"),
+            output_interval: None,
+            output_columns: OutputColumns::default(),
+            units: UnitSystem::default(),
+            output_units: UnitSystem::default(),
+            binary_file: None,
+            diagnostics_file: None,
+            elements_file: None,
+            elements_primary: None,
+            barycentric: false,
+            output_frame: OutputFrame::default(),
+            integrator: IntegratorKind::default(),
+            force_solver: ForceSolver::default(),
+            softening: Softening::default(),
+            post_newtonian: false,
+            collision_mode: CollisionMode::default(),
+            adaptive: None,
+            warnings: Vec::new(),
+        }
+    }
+}
+
+/// How strictly configuration problems are treated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ParseMode {
+    /// Fail on the first problem
+    #[default]
+    Strict,
+    /// Skip problems, collecting them in `SimulationConfig::warnings`
+    Lenient,
+}
+
+/// What went wrong while reading a configuration
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigErrorKind {
+    /// The file could not be read
+    Io(std::io::ErrorKind, String),
+    /// A line that is neither a section header, a comment nor `key = value`
+    MalformedLine(String),
+    /// A `key = value` line before the first section header
+    KeyOutsideSection,
+    /// A section other than `[Simulation]` or `[BodyN]`
+    UnknownSection,
+    /// A section name that appears more than once
+    DuplicateSection,
+    /// A key not recognised in its section
+    UnknownKey,
+    /// A key that appears more than once in its section
+    DuplicateKey,
+    /// A value that could not be parsed or is out of range
+    InvalidValue { value: String, expected: &'static str },
+    /// A number that parsed as NaN or infinity
+    NonFiniteValue(String),
+    /// A body section without a `mass` key
+    MissingMass,
+    /// A key that other keys in the section require
+    MissingKey,
+    /// A key that cannot be combined with the named one
+    ConflictingKey(String),
+    /// A body name already used by an earlier body
+    DuplicateName(String),
+    /// A reference to a body that no section defines
+    UnknownBody(String),
+    /// No body sections at all
+    NoBodies,
+}
+
+/// A configuration problem with its location in the input
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigError {
+    pub kind: ConfigErrorKind,
+    /// File being parsed, when parsing from a file
+    pub file: Option<String>,
+    /// 1-based line number
+    pub line: Option<usize>,
+    /// Section name, without brackets
+    pub section: Option<String>,
+    /// Key within the section
+    pub key: Option<String>,
+}
+
+impl ConfigError {
+    fn new(kind: ConfigErrorKind) -> Self {
+        ConfigError {
+            kind,
+            file: None,
+            line: None,
+            section: None,
+            key: None,
+        }
+    }
+}
+
+impl fmt::Display for ConfigErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigErrorKind::Io(_, message) => write!(f, "{}", message),
+            ConfigErrorKind::MalformedLine(line) => write!(f, "malformed line '{}'", line),
+            ConfigErrorKind::KeyOutsideSection => write!(f, "key outside of any section"),
+            ConfigErrorKind::UnknownSection => {
+                write!(f, "unknown section (expected [Simulation] or [BodyN])")
+            }
+            ConfigErrorKind::DuplicateSection => write!(f, "duplicate section"),
+            ConfigErrorKind::UnknownKey => write!(f, "unknown key"),
+            ConfigErrorKind::DuplicateKey => write!(f, "duplicate key"),
+            ConfigErrorKind::InvalidValue { value, expected } => {
+                write!(f, "invalid value '{}' (expected {})", value, expected)
+            }
+            ConfigErrorKind::NonFiniteValue(value) => {
+                write!(f, "non-finite value '{}'", value)
+            }
+            ConfigErrorKind::MissingMass => write!(f, "body has no mass"),
+            ConfigErrorKind::MissingKey => write!(f, "missing key"),
+            ConfigErrorKind::ConflictingKey(other) => write!(f, "cannot be combined with '{}'", other),
+            ConfigErrorKind::DuplicateName(name) => write!(f, "duplicate body name '{}'", name),
+            ConfigErrorKind::UnknownBody(name) => write!(f, "no body named '{}'", name),
+            ConfigErrorKind::NoBodies => write!(f, "No bodies found in configuration file"),
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.file, self.line) {
+            (Some(file), Some(line)) => write!(f, "{}:{}: ", file, line)?,
+            (Some(file), None) => write!(f, "{}: ", file)?,
+            (None, Some(line)) => write!(f, "line {}: ", line)?,
+            (None, None) => {}
+        }
+        if let Some(ref section) = self.section {
+            write!(f, "[{}] ", section)?;
+        }
+        if let Some(ref key) = self.key {
+            write!(f, "{}: ", key)?;
+        }
+        write!(f, "{}", self.kind)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<ConfigError> for std::io::Error {
+    fn from(err: ConfigError) -> Self {
+        let kind = match err.kind {
+            ConfigErrorKind::Io(kind, _) => kind,
+            _ => std::io::ErrorKind::InvalidData,
+        };
+        std::io::Error::new(kind, err.to_string())
+    }
 }
 
 /// Parse an INI file and extract body initial conditions
 ///
 /// Expected format (as shown in this text example, not valid Rust):
 /// ```text
+/// [Simulation]
+/// units = si                 # au (AU, solar masses, days) or nbody [<mass> <length>]
+/// output_units = au          # defaults to units
+/// barycentric = true         # move the centre of mass to rest at the origin
+/// output_frame = body Sun    # output centred on a body, or inertial (the default)
+/// time_step = 86400
+/// duration = 8.64e7          # or num_steps = 1000
+/// output_file = ./data/results.csv
+/// output_interval = 86400
+/// output_columns = velocity, total_energy   # or all
+/// binary_file = ./data/results.traj  # the trajectory again, in the binary format
+/// diagnostics_file = ./data/diagnostics.csv  # momentum, centre of mass, drift, ...
+/// elements_file = ./data/elements.csv  # osculating orbital elements
+/// elements_primary = Sun     # body the orbits are around; the most massive by default
+/// integrator = rkf45         # leapfrog, yoshida4, wisdom-holman
+/// force_solver = barnes-hut  # parallel, or direct (the default)
+/// opening_angle = 0.5        # Barnes-Hut accuracy; implies barnes-hut
+/// softening = plummer 1e7    # or spline <length>, or none (the default)
+/// post_newtonian = true     # 1PN relativistic correction, off by default
+/// collisions = merge         # bounce, stop, or ignore (the default)
+/// adaptive = true
+/// abs_tolerance = 1e-3        # position error, a length
+/// abs_velocity_tolerance = 1e-9  # velocity error; abs_tolerance per time unit by default
+/// rel_tolerance = 1e-10
+/// min_time_step = 1
+/// max_time_step = 864000
+///
 /// [Body1]
+/// name = Alpha               # defaults to the section name
 /// mass = 4e29
+/// radius = 7e8               # optional, metres
+/// color = #ffcc44            # optional, or 1.0, 0.8, 0.27
 /// position_x = 0
 /// position_y = 1e11
 /// position_z = -1e11
 /// velocity_x = -600
 /// velocity_y = 0
 /// velocity_z = 2600
+///
+/// [Body2]
+/// name = Beta
+/// mass = 6e24
+/// parent = Alpha             # orbital elements relative to an earlier body
+/// semi_major_axis = 1.5e11
+/// eccentricity = 0.0167
+/// inclination = 7.2          # degrees, like the angles below
+/// ascending_node = 348.7
+/// argument_of_periapsis = 114.2
+/// mean_anomaly = 358.6       # or true_anomaly
 /// ```
-pub fn parse_ini_file(path: &str) -> std::io::Result<SimulationConfig> {
-    let content = fs::read_to_string(path)?;
-    parse_ini_content(&content)
+///
+/// Every `[Simulation]` key is optional; missing ones keep their defaults.
+/// Masses, lengths, velocities and times, including `abs_tolerance` (a
+/// length) and `abs_velocity_tolerance` (a velocity), are in `units` and
+/// converted to SI; the defaults are in SI whatever `units` says. `threads = 4` (0 for one per core) implies the parallel solver;
+/// it and `opening_angle` conflict with each other and with any other
+/// `force_solver`.
+/// Body sections must have a positive `mass`; positions and velocities
+/// default to zero. A body with a `parent` is placed by its orbital
+/// elements instead, on an elliptical orbit around that body's position
+/// and velocity: `semi_major_axis` is required, the other elements default
+/// to zero, and the parent must be defined in an earlier section. Body
+/// names must be unique and may not contain commas or quotes, since they
+/// become CSV column names. Any problem fails the parse.
+pub fn parse_ini_file(path: &str) -> Result<SimulationConfig, ConfigError> {
+    parse_ini_file_with_mode(path, ParseMode::Strict)
+}
+
+/// Parse an INI file, choosing how problems are handled
+///
+/// In `ParseMode::Lenient` the returned configuration lists skipped
+/// problems in `warnings`; only an unreadable file or a file without any
+/// valid body is still an error.
+pub fn parse_ini_file_with_mode(path: &str, mode: ParseMode) -> Result<SimulationConfig, ConfigError> {
+    let content = fs::read_to_string(path).map_err(|e| ConfigError {
+        file: Some(path.to_string()),
+        ..ConfigError::new(ConfigErrorKind::Io(e.kind(), e.to_string()))
+    })?;
+    Parser::new(Some(path), mode).parse(&content)
 }
 
 /// Parse INI content from a string
-fn parse_ini_content(content: &str) -> std::io::Result<SimulationConfig> {
-    let mut bodies = Vec::new();
-    let mut body_data: Option<BodyData> = None;
+pub fn parse_ini_str(content: &str, mode: ParseMode) -> Result<SimulationConfig, ConfigError> {
+    Parser::new(None, mode).parse(content)
+}
 
-    for line in content.lines() {
-        let trimmed = line.trim();
+/// The section currently being parsed
+enum Section {
+    Simulation,
+    Body(Box<BodyData>),
+    /// An unknown section whose keys are skipped
+    Skipped,
+}
+
+/// Parsing state: the current location and the problems found so far
+struct Parser {
+    file: Option<String>,
+    mode: ParseMode,
+    line: usize,
+    section_name: Option<String>,
+    warnings: Vec<ConfigError>,
+    /// Bodies placed by orbital elements, positioned once all are read
+    orbits: Vec<PendingOrbit>,
+}
+
+/// A body whose state is still to be worked out from its orbit
+struct PendingOrbit {
+    /// Index into the configuration's bodies
+    index: usize,
+    line: usize,
+    section: Option<String>,
+    parent: String,
+    elements: OrbitalElements,
+}
 
-        // Skip empty lines and comments
-        if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with(';') {
-            continue;
+impl Parser {
+    fn new(file: Option<&str>, mode: ParseMode) -> Self {
+        Parser {
+            file: file.map(String::from),
+            mode,
+            line: 0,
+            section_name: None,
+            warnings: Vec::new(),
+            orbits: Vec::new(),
         }
+    }
+
+    /// Build an error at the current location
+    fn error(&self, kind: ConfigErrorKind, key: Option<&str>) -> ConfigError {
+        ConfigError {
+            kind,
+            file: self.file.clone(),
+            line: Some(self.line).filter(|&l| l > 0),
+            section: self.section_name.clone(),
+            key: key.map(String::from),
+        }
+    }
+
+    /// Fail in strict mode, or record a warning and carry on in lenient mode
+    fn report(&mut self, err: ConfigError) -> Result<(), ConfigError> {
+        match self.mode {
+            ParseMode::Strict => Err(err),
+            ParseMode::Lenient => {
+                self.warnings.push(err);
+                Ok(())
+            }
+        }
+    }
+
+    fn parse(mut self, content: &str) -> Result<SimulationConfig, ConfigError> {
+        let mut config = SimulationConfig::default();
+        let mut simulation = SimulationData::new();
+        let mut section: Option<Section> = None;
+        let mut seen_sections: Vec<String> = Vec::new();
+        let mut seen_keys: Vec<String> = Vec::new();
+
+        for (idx, line) in content.lines().enumerate() {
+            self.line = idx + 1;
+            let trimmed = line.trim();
+
+            // Skip empty lines and comments
+            if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with(';') {
+                continue;
+            }
+
+            // Check for section headers like [Simulation], [Body1], [Body2], etc.
+            if trimmed.starts_with('[') && trimmed.ends_with(']') {
+                // Save previous body if exists
+                if let Some(Section::Body(body)) = section.take() {
+                    self.finish_body(body, &mut config)?;
+                }
 
-        // Check for section headers like [Body1], [Body2], etc.
-        if trimmed.starts_with('[') && trimmed.ends_with(']') {
-            // Save previous body if exists
-            if let Some(body) = body_data.take() {
-                if let Ok(b) = body.to_body() {
-                    bodies.push(b);
+                let section_name = trimmed[1..trimmed.len() - 1].trim().to_string();
+                let lower = section_name.to_lowercase();
+                self.section_name = Some(section_name);
+                seen_keys.clear();
+
+                if seen_sections.contains(&lower) {
+                    let err = self.error(ConfigErrorKind::DuplicateSection, None);
+                    self.report(err)?;
+                    section = Some(Section::Skipped);
+                    continue;
                 }
+                seen_sections.push(lower.clone());
+
+                section = if lower == "simulation" {
+                    Some(Section::Simulation)
+                } else if lower.starts_with("body") {
+                    let name = self.section_name.clone().unwrap_or_default();
+                    Some(Section::Body(Box::new(BodyData::new(self.line, name))))
+                } else {
+                    let err = self.error(ConfigErrorKind::UnknownSection, None);
+                    self.report(err)?;
+                    Some(Section::Skipped)
+                };
+                continue;
             }
 
-            let section_name = &trimmed[1..trimmed.len() - 1];
-            if section_name.to_lowercase().starts_with("body") {
-                body_data = Some(BodyData::new());
+            // Parse key=value pairs
+            let eq_pos = match trimmed.find('=') {
+                Some(eq_pos) => eq_pos,
+                None => {
+                    let err = self.error(ConfigErrorKind::MalformedLine(trimmed.to_string()), None);
+                    self.report(err)?;
+                    continue;
+                }
+            };
+            let key = trimmed[..eq_pos].trim().to_lowercase();
+            let value_str = strip_inline_comment(trimmed[eq_pos + 1..].trim());
+            // Aliases of one key count as the same key
+            let canonical = match section {
+                Some(Section::Simulation) => SimulationData::canonical_key(&key).to_string(),
+                _ => key.clone(),
+            };
+
+            let result = match section {
+                None => Err(ConfigErrorKind::KeyOutsideSection),
+                Some(Section::Skipped) => continue,
+                Some(_) if seen_keys.contains(&canonical) => Err(ConfigErrorKind::DuplicateKey),
+                Some(Section::Simulation) => {
+                    simulation.lines.push((key.clone(), self.line));
+                    simulation.set(&key, value_str)
+                }
+                Some(Section::Body(ref mut body)) => body.set(&key, value_str),
+            };
+            seen_keys.push(canonical);
+            if let Err(kind) = result {
+                let err = self.error(kind, Some(&key));
+                self.report(err)?;
             }
-            continue;
         }
 
-        // Parse key=value pairs
-        if let Some(ref mut body) = body_data {
-            if let Some(eq_pos) = trimmed.find('=') {
-                let key = trimmed[..eq_pos].trim().to_lowercase();
-                let mut value_str = trimmed[eq_pos + 1..].trim();
-                
-                // Strip inline comments
-                if let Some(hash_pos) = value_str.find('#') {
-                    value_str = &value_str[..hash_pos].trim();
-                }
-                if let Some(semi_pos) = value_str.find(';') {
-                    value_str = &value_str[..semi_pos].trim();
+        // Don't forget the last body
+        if let Some(Section::Body(body)) = section {
+            self.finish_body(body, &mut config)?;
+        }
+
+        // Orbital elements are in the file's units, like the parent's state
+        let g = simulation.units.unwrap_or_default().gravitational_constant();
+        self.place_orbiting_bodies(&mut config, g)?;
+
+        if config.bodies.is_empty() {
+            self.line = 0;
+            self.section_name = None;
+            return Err(self.error(ConfigErrorKind::NoBodies, None));
+        }
+
+        for (names, kind) in simulation.check() {
+            let (key, line) = simulation.key_line(names).unwrap_or_default();
+            self.section_name = Some(String::from("Simulation"));
+            self.report_at(line, kind, Some(&key))?;
+        }
+        simulation.apply(&mut config);
+        if let OutputFrame::Body(ref name) = config.output_frame {
+            if !config.bodies.iter().any(|body| body.name == *name) {
+                self.line = 0;
+                self.section_name = Some(String::from("Simulation"));
+                let err = self.error(ConfigErrorKind::UnknownBody(name.clone()), Some("output_frame"));
+                self.report(err)?;
+                config.output_frame = OutputFrame::Inertial;
+            }
+        }
+        if let Some(ref name) = config.elements_primary {
+            if !config.bodies.iter().any(|body| body.name == *name) {
+                self.line = 0;
+                self.section_name = Some(String::from("Simulation"));
+                let err = self.error(ConfigErrorKind::UnknownBody(name.clone()), Some("elements_primary"));
+                self.report(err)?;
+                config.elements_primary = None;
+            }
+        }
+        config.warnings = self.warnings;
+        Ok(config)
+    }
+
+    /// Validate a completed body section and add it to the configuration
+    fn finish_body(&mut self, body: Box<BodyData>, config: &mut SimulationConfig) -> Result<(), ConfigError> {
+        match body.to_body().and_then(|b| Ok((b, body.orbit()?))) {
+            Ok((b, _)) if config.bodies.iter().any(|other| other.name == b.name) => {
+                self.report_at(body.line, ConfigErrorKind::DuplicateName(b.name), Some("name"))
+            }
+            Ok((b, orbit)) => {
+                if let Some((parent, elements)) = orbit {
+                    self.orbits.push(PendingOrbit {
+                        index: config.bodies.len(),
+                        line: body.line,
+                        section: self.section_name.clone(),
+                        parent,
+                        elements,
+                    });
                 }
+                config.bodies.push(b);
+                Ok(())
+            }
+            Err((kind, key)) => self.report_at(body.line, kind, key),
+        }
+    }
+
+    /// Set the state of bodies given by orbital elements from their
+    /// parents, with `g` the gravitational constant in the file's units
+    fn place_orbiting_bodies(&mut self, config: &mut SimulationConfig, g: f64) -> Result<(), ConfigError> {
+        let mut skipped: Vec<usize> = Vec::new();
+        for orbit in std::mem::take(&mut self.orbits) {
+            let parent = config.bodies[..orbit.index]
+                .iter()
+                .enumerate()
+                .find(|(idx, body)| body.name == orbit.parent && !skipped.contains(idx))
+                .map(|(_, body)| body.clone());
+            let Some(parent) = parent else {
+                self.section_name = orbit.section;
+                let kind = ConfigErrorKind::InvalidValue {
+                    value: orbit.parent,
+                    expected: "the name of a body in an earlier section",
+                };
+                self.report_at(orbit.line, kind, Some("parent"))?;
+                skipped.push(orbit.index);
+                continue;
+            };
+            let body = &mut config.bodies[orbit.index];
+            let (position, velocity) = orbit.elements.to_state_vectors(g * (parent.mass + body.mass));
+            body.position = std::array::from_fn(|k| parent.position[k] + position[k]);
+            body.velocity = std::array::from_fn(|k| parent.velocity[k] + velocity[k]);
+        }
+        for idx in skipped.into_iter().rev() {
+            config.bodies.remove(idx);
+        }
+        Ok(())
+    }
+
+    /// Report a problem against an earlier line of the current section
+    fn report_at(&mut self, line: usize, kind: ConfigErrorKind, key: Option<&str>) -> Result<(), ConfigError> {
+        let current = self.line;
+        self.line = line;
+        let err = self.error(kind, key);
+        self.line = current;
+        self.report(err)
+    }
+}
+
+/// Remove a trailing `# ...` or `; ...` comment from a value
+///
+/// A comment marker only counts when it follows whitespace, so values
+/// such as `#ff8800` survive.
+fn strip_inline_comment(value: &str) -> &str {
+    for (idx, ch) in value.char_indices() {
+        if (ch == '#' || ch == ';') && value[..idx].ends_with(char::is_whitespace) {
+            return value[..idx].trim();
+        }
+    }
+    value
+}
+
+/// Parse a finite floating-point value
+fn parse_number(value: &str) -> Result<f64, ConfigErrorKind> {
+    let number: f64 = value.parse().map_err(|_| ConfigErrorKind::InvalidValue {
+        value: value.to_string(),
+        expected: "a number",
+    })?;
+    if !number.is_finite() {
+        return Err(ConfigErrorKind::NonFiniteValue(value.to_string()));
+    }
+    Ok(number)
+}
+
+/// Parse a finite value that must be strictly positive
+fn parse_positive(value: &str) -> Result<f64, ConfigErrorKind> {
+    let number = parse_number(value)?;
+    if number <= 0.0 {
+        return Err(ConfigErrorKind::InvalidValue {
+            value: value.to_string(),
+            expected: "a positive number",
+        });
+    }
+    Ok(number)
+}
+
+/// Temporary structure to hold `[Simulation]` settings while parsing
+#[derive(Debug, Clone, Default)]
+struct SimulationData {
+    time_step: Option<f64>,
+    num_steps: Option<usize>,
+    duration: Option<f64>,
+    output_file: Option<String>,
+    output_interval: Option<f64>,
+    output_columns: Option<OutputColumns>,
+    binary_file: Option<String>,
+    diagnostics_file: Option<String>,
+    elements_file: Option<String>,
+    elements_primary: Option<String>,
+    barycentric: Option<bool>,
+    output_frame: Option<OutputFrame>,
+    units: Option<UnitSystem>,
+    output_units: Option<UnitSystem>,
+    integrator: Option<IntegratorKind>,
+    force_solver: Option<ForceSolver>,
+    opening_angle: Option<f64>,
+    threads: Option<usize>,
+    softening: Option<Softening>,
+    post_newtonian: Option<bool>,
+    collisions: Option<CollisionMode>,
+    adaptive: Option<bool>,
+    abs_tolerance: Option<f64>,
+    abs_velocity_tolerance: Option<f64>,
+    rel_tolerance: Option<f64>,
+    min_time_step: Option<f64>,
+    max_time_step: Option<f64>,
+    /// Line of each key that was set, with the key as written
+    lines: Vec<(String, usize)>,
+}
 
-                if let Ok(value) = value_str.parse::<f64>() {
-                    match key.as_str() {
-                        "mass" => body.mass = value,
-                        "position_x" => body.position_x = value,
-                        "position_y" => body.position_y = value,
-                        "position_z" => body.position_z = value,
-                        "velocity_x" => body.velocity_x = value,
-                        "velocity_y" => body.velocity_y = value,
-                        "velocity_z" => body.velocity_z = value,
-                        _ => {} // Ignore unknown keys
+/// Keys that switch on adaptive stepping, or set it up
+const ADAPTIVE_KEYS: &[&str] = &[
+    "adaptive",
+    "abs_tolerance",
+    "abs_velocity_tolerance",
+    "rel_tolerance",
+    "min_time_step",
+    "max_time_step",
+];
+
+impl SimulationData {
+    fn new() -> Self {
+        Self::default()
+    }
+
+    /// The name a key is known by, for keys that have an alias
+    fn canonical_key(key: &str) -> &str {
+        match key {
+            "dt" => "time_step",
+            other => other,
+        }
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigErrorKind> {
+        match key {
+            "time_step" | "dt" => {
+                let time_step = parse_number(value)?;
+                if time_step == 0.0 {
+                    return Err(ConfigErrorKind::InvalidValue {
+                        value: value.to_string(),
+                        expected: "a nonzero time step",
+                    });
+                }
+                self.time_step = Some(time_step);
+            }
+            "num_steps" => {
+                self.num_steps = Some(value.parse().map_err(|_| ConfigErrorKind::InvalidValue {
+                    value: value.to_string(),
+                    expected: "a non-negative integer",
+                })?)
+            }
+            "duration" => {
+                let duration = parse_number(value)?;
+                if duration < 0.0 {
+                    return Err(ConfigErrorKind::InvalidValue {
+                        value: value.to_string(),
+                        expected: "a non-negative duration",
+                    });
+                }
+                self.duration = Some(duration);
+            }
+            "output_file" => {
+                if value.is_empty() {
+                    return Err(ConfigErrorKind::InvalidValue {
+                        value: value.to_string(),
+                        expected: "a file path",
+                    });
+                }
+                self.output_file = Some(value.to_string())
+            }
+            "output_interval" => self.output_interval = Some(parse_positive(value)?),
+            "barycentric" => {
+                self.barycentric = Some(parse_bool(value).ok_or_else(|| {
+                    ConfigErrorKind::InvalidValue {
+                        value: value.to_string(),
+                        expected: "true or false",
                     }
+                })?)
+            }
+            "output_frame" => {
+                self.output_frame = Some(value.parse().map_err(|_| ConfigErrorKind::InvalidValue {
+                    value: value.to_string(),
+                    expected: "inertial, or body followed by a body name",
+                })?)
+            }
+            "binary_file" | "diagnostics_file" | "elements_file" => {
+                if value.is_empty() {
+                    return Err(ConfigErrorKind::InvalidValue {
+                        value: value.to_string(),
+                        expected: "a file path",
+                    });
+                }
+                match key {
+                    "binary_file" => self.binary_file = Some(value.to_string()),
+                    "diagnostics_file" => self.diagnostics_file = Some(value.to_string()),
+                    _ => self.elements_file = Some(value.to_string()),
+                }
+            }
+            "elements_primary" => self.elements_primary = Some(parse_name(value)?),
+            "output_columns" => {
+                self.output_columns = Some(value.parse().map_err(|_| ConfigErrorKind::InvalidValue {
+                    value: value.to_string(),
+                    expected: "a list of velocity, acceleration, kinetic_energy, total_energy, angular_momentum or all",
+                })?)
+            }
+            "units" | "output_units" => {
+                let units = Some(value.parse().map_err(|_| ConfigErrorKind::InvalidValue {
+                    value: value.to_string(),
+                    expected: "si, au, or nbody optionally followed by mass and length units",
+                })?);
+                if key == "units" {
+                    self.units = units;
+                } else {
+                    self.output_units = units;
+                }
+            }
+            "integrator" => {
+                self.integrator = Some(value.parse().map_err(|_| ConfigErrorKind::InvalidValue {
+                    value: value.to_string(),
+                    expected: "rkf45, leapfrog, yoshida4 or wisdom-holman",
+                })?)
+            }
+            "force_solver" => {
+                self.force_solver = Some(value.parse().map_err(|_| ConfigErrorKind::InvalidValue {
+                    value: value.to_string(),
+                    expected: "direct, parallel or barnes-hut",
+                })?)
+            }
+            "opening_angle" => {
+                let angle = parse_number(value)?;
+                if angle < 0.0 {
+                    return Err(ConfigErrorKind::InvalidValue {
+                        value: value.to_string(),
+                        expected: "a non-negative opening angle",
+                    });
                 }
+                self.opening_angle = Some(angle);
+            }
+            "threads" => {
+                self.threads = Some(value.parse().map_err(|_| ConfigErrorKind::InvalidValue {
+                    value: value.to_string(),
+                    expected: "a non-negative number of threads",
+                })?)
+            }
+            "softening" => {
+                self.softening = Some(value.parse().map_err(|_| ConfigErrorKind::InvalidValue {
+                    value: value.to_string(),
+                    expected: "none, or plummer or spline followed by a positive length",
+                })?)
+            }
+            "post_newtonian" => {
+                self.post_newtonian = Some(parse_bool(value).ok_or_else(|| {
+                    ConfigErrorKind::InvalidValue {
+                        value: value.to_string(),
+                        expected: "true or false",
+                    }
+                })?)
             }
+            "collisions" => {
+                self.collisions = Some(value.parse().map_err(|_| ConfigErrorKind::InvalidValue {
+                    value: value.to_string(),
+                    expected: "ignore, merge, bounce or stop",
+                })?)
+            }
+            "adaptive" => {
+                self.adaptive = Some(parse_bool(value).ok_or_else(|| {
+                    ConfigErrorKind::InvalidValue {
+                        value: value.to_string(),
+                        expected: "true or false",
+                    }
+                })?)
+            }
+            "abs_tolerance" => self.abs_tolerance = Some(parse_positive(value)?),
+            "abs_velocity_tolerance" => self.abs_velocity_tolerance = Some(parse_positive(value)?),
+            "rel_tolerance" => self.rel_tolerance = Some(parse_positive(value)?),
+            "min_time_step" => self.min_time_step = Some(parse_positive(value)?),
+            "max_time_step" => self.max_time_step = Some(parse_positive(value)?),
+            _ => return Err(ConfigErrorKind::UnknownKey),
         }
+        Ok(())
+    }
+
+    /// The line a key was set on, and the key as written, for any of the
+    /// given spellings
+    fn key_line(&self, names: &[&str]) -> Option<(String, usize)> {
+        self.lines.iter().find(|(key, _)| names.contains(&key.as_str())).cloned()
     }
 
-    // Don't forget the last body
-    if let Some(body) = body_data {
-        if let Ok(b) = body.to_body() {
-            bodies.push(b);
+    /// Problems with combinations of keys, found once the whole section is
+    /// read, each with the spellings of the key at fault
+    ///
+    /// The offending keys are cleared, so that in lenient mode the rest of
+    /// the section still applies with defaults in their place.
+    fn check(&mut self) -> Vec<(&'static [&'static str], ConfigErrorKind)> {
+        let mut problems = Vec::new();
+        // A run of fixed duration has to step forwards to reach its end
+        if self.duration.is_some() && self.time_step.is_some_and(|dt| dt < 0.0) {
+            let value = self.time_step.take().unwrap_or_default().to_string();
+            problems.push((
+                &["time_step", "dt"][..],
+                ConfigErrorKind::InvalidValue { value, expected: "a positive time step when duration is set" },
+            ));
+        }
+        // Only an integrator with an error estimate can adapt its steps
+        let fixed_steps = self.integrator.is_some_and(|integrator| !integrator.has_error_estimate());
+        if fixed_steps && self.adaptive_settings().is_some() {
+            problems.push((ADAPTIVE_KEYS, ConfigErrorKind::ConflictingKey(String::from("integrator"))));
+            self.adaptive = Some(false);
+        }
+        // Step bounds are checked against each other or the defaults
+        if self.adaptive_settings().is_some_and(|settings| settings.min_dt > settings.max_dt) {
+            let problem = match self.max_time_step {
+                Some(max_dt) => (
+                    &["max_time_step"][..],
+                    ConfigErrorKind::InvalidValue {
+                        value: max_dt.to_string(),
+                        expected: "a maximum time step no smaller than min_time_step",
+                    },
+                ),
+                None => (
+                    &["min_time_step"][..],
+                    ConfigErrorKind::InvalidValue {
+                        value: self.min_time_step.unwrap_or_default().to_string(),
+                        expected: "a minimum time step no larger than the maximum time step",
+                    },
+                ),
+            };
+            problems.push(problem);
+            self.adaptive.get_or_insert(true);
+            self.min_time_step = None;
+            self.max_time_step = None;
+        }
+        // Solver settings only apply to the solver that uses them
+        let (tree, parallel) = match self.force_solver {
+            Some(ForceSolver::BarnesHut { .. }) => (true, false),
+            Some(ForceSolver::ParallelDirect { .. }) => (false, true),
+            Some(ForceSolver::Direct) => (false, false),
+            None => (true, true),
+        };
+        if !tree && self.opening_angle.take().is_some() {
+            problems.push((&["opening_angle"][..], ConfigErrorKind::ConflictingKey(String::from("force_solver"))));
+        }
+        if !parallel && self.threads.take().is_some() {
+            problems.push((&["threads"][..], ConfigErrorKind::ConflictingKey(String::from("force_solver"))));
+        }
+        if self.force_solver.is_none() && self.opening_angle.is_some() && self.threads.take().is_some() {
+            problems.push((&["threads"][..], ConfigErrorKind::ConflictingKey(String::from("opening_angle"))));
+        }
+        problems
+    }
+
+    /// Fill in the configuration, keeping defaults for anything unset, and
+    /// convert everything read from the file to SI
+    fn apply(self, config: &mut SimulationConfig) {
+        config.adaptive = self.adaptive_settings();
+        let units = self.units.unwrap_or_default();
+        let (length, time) = (units.length(), units.time());
+        config.units = units;
+        config.output_units = self.output_units.unwrap_or(units);
+        for body in config.bodies.iter_mut() {
+            *body = units.body_to_si(body);
+        }
+        config.barycentric = self.barycentric.unwrap_or(false);
+        if config.barycentric {
+            frame::to_barycentric(&mut config.bodies);
+        }
+        if let Some(output_frame) = self.output_frame {
+            config.output_frame = output_frame;
         }
+
+        if let Some(time_step) = self.time_step {
+            config.time_step = time_step * time;
+        }
+        if let Some(num_steps) = self.num_steps {
+            config.num_steps = num_steps;
+        }
+        if let Some(output_file) = self.output_file {
+            config.output_file = output_file;
+        }
+        config.binary_file = self.binary_file;
+        config.diagnostics_file = self.diagnostics_file;
+        config.elements_file = self.elements_file;
+        config.elements_primary = self.elements_primary;
+        if let Some(integrator) = self.integrator {
+            config.integrator = integrator;
+        }
+        // An opening angle implies Barnes-Hut and a thread count the
+        // parallel direct sum, unless a solver is named
+        config.force_solver = match (self.force_solver, self.opening_angle, self.threads) {
+            (Some(ForceSolver::Direct), _, _) => ForceSolver::Direct,
+            (Some(ForceSolver::ParallelDirect { .. }), _, threads) => ForceSolver::ParallelDirect {
+                threads: threads.unwrap_or(0),
+            },
+            (_, Some(angle), _) => ForceSolver::barnes_hut(angle),
+            (None, None, Some(threads)) => ForceSolver::ParallelDirect { threads },
+            (Some(solver), None, _) => solver,
+            (None, None, None) => ForceSolver::default(),
+        };
+        if let Some(softening) = self.softening {
+            config.softening = softening.scaled(length);
+        }
+        if let Some(post_newtonian) = self.post_newtonian {
+            config.post_newtonian = post_newtonian;
+        }
+        if let Some(collisions) = self.collisions {
+            config.collision_mode = collisions;
+        }
+        if let Some(output_columns) = self.output_columns {
+            config.output_columns = output_columns;
+        }
+        config.duration = self.duration.map(|duration| duration * time);
+        config.output_interval = self.output_interval.map(|interval| interval * time);
+
     }
 
-    if bodies.is_empty() {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::InvalidData,
-            "No bodies found in configuration file",
-        ));
+    /// Adaptive stepping settings in SI, if adaptive stepping is on
+    fn adaptive_settings(&self) -> Option<AdaptiveSettings> {
+        // Tolerance or step-bound keys imply adaptive stepping unless it is
+        // explicitly switched off
+        let has_settings = self.abs_tolerance.is_some()
+            || self.abs_velocity_tolerance.is_some()
+            || self.rel_tolerance.is_some()
+            || self.min_time_step.is_some()
+            || self.max_time_step.is_some();
+        if !self.adaptive.unwrap_or(has_settings) {
+            return None;
+        }
+        let units = self.units.unwrap_or_default();
+        let mut settings = AdaptiveSettings::default();
+        if let Some(abs_tolerance) = self.abs_tolerance {
+            settings.abs_tolerance = abs_tolerance * units.length();
+        }
+        // The velocity tolerance defaults to the position tolerance per unit
+        // of time, so it keeps the same meaning in every unit system
+        if let Some(abs_velocity_tolerance) = self.abs_velocity_tolerance.or(self.abs_tolerance) {
+            settings.abs_velocity_tolerance = abs_velocity_tolerance * units.length() / units.time();
+        }
+        if let Some(rel_tolerance) = self.rel_tolerance {
+            settings.rel_tolerance = rel_tolerance;
+        }
+        if let Some(min_dt) = self.min_time_step {
+            settings.min_dt = min_dt * units.time();
+        }
+        if let Some(max_dt) = self.max_time_step {
+            settings.max_dt = max_dt * units.time();
+        }
+        Some(settings)
     }
+}
 
-    Ok(SimulationConfig {
-        bodies,
-        time_step: 86400.0, // 1 day default
-        num_steps: 1000,    // 1000 steps default
-        output_file: String::from("results.csv"),
-    })
+/// Parse a boolean INI value such as `true`, `no` or `1`
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Some(true),
+        "false" | "no" | "off" | "0" => Some(false),
+        _ => None,
+    }
 }
 
+/// A problem with a body section and the key at fault, if any
+type BodyError = (ConfigErrorKind, Option<&'static str>);
+
 /// Temporary structure to hold body data while parsing
 #[derive(Debug, Clone)]
 struct BodyData {
-    mass: f64,
+    /// Line of the section header, for errors about the whole body
+    line: usize,
+    name: String,
+    mass: Option<f64>,
+    radius: f64,
+    color: Option<[f32; 3]>,
     position_x: f64,
     position_y: f64,
     position_z: f64,
     velocity_x: f64,
     velocity_y: f64,
     velocity_z: f64,
+    /// First position or velocity key given, which rules out a parent
+    state_key: Option<&'static str>,
+    /// Orbital elements, with angles in degrees
+    parent: Option<String>,
+    semi_major_axis: Option<f64>,
+    eccentricity: Option<f64>,
+    inclination: Option<f64>,
+    ascending_node: Option<f64>,
+    argument_of_periapsis: Option<f64>,
+    true_anomaly: Option<f64>,
+    mean_anomaly: Option<f64>,
 }
 
 impl BodyData {
-    fn new() -> Self {
+    fn new(line: usize, name: String) -> Self {
         BodyData {
-            mass: 0.0,
+            line,
+            name,
+            mass: None,
+            radius: 0.0,
+            color: None,
             position_x: 0.0,
             position_y: 0.0,
             position_z: 0.0,
             velocity_x: 0.0,
             velocity_y: 0.0,
             velocity_z: 0.0,
+            state_key: None,
+            parent: None,
+            semi_major_axis: None,
+            eccentricity: None,
+            inclination: None,
+            ascending_node: None,
+            argument_of_periapsis: None,
+            true_anomaly: None,
+            mean_anomaly: None,
         }
     }
 
-    fn to_body(&self) -> std::io::Result<Body> {
-        if self.mass <= 0.0 {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                "Body mass must be positive",
-            ));
+    fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigErrorKind> {
+        let (field, name) = match key {
+            "mass" => {
+                self.mass = Some(parse_positive(value)?);
+                return Ok(());
+            }
+            "name" => {
+                self.name = parse_name(value)?;
+                return Ok(());
+            }
+            "radius" => {
+                let radius = parse_number(value)?;
+                if radius < 0.0 {
+                    return Err(ConfigErrorKind::InvalidValue {
+                        value: value.to_string(),
+                        expected: "a non-negative radius",
+                    });
+                }
+                self.radius = radius;
+                return Ok(());
+            }
+            "color" => {
+                self.color = Some(parse_color(value)?);
+                return Ok(());
+            }
+            "parent" => {
+                self.parent = Some(parse_name(value)?);
+                return Ok(());
+            }
+            "semi_major_axis" => {
+                self.semi_major_axis = Some(parse_positive(value)?);
+                return Ok(());
+            }
+            "eccentricity" => {
+                let eccentricity = parse_number(value)?;
+                if !(0.0..1.0).contains(&eccentricity) {
+                    return Err(ConfigErrorKind::InvalidValue {
+                        value: value.to_string(),
+                        expected: "an eccentricity of at least 0 and below 1",
+                    });
+                }
+                self.eccentricity = Some(eccentricity);
+                return Ok(());
+            }
+            "inclination" | "ascending_node" | "argument_of_periapsis" | "true_anomaly" | "mean_anomaly" => {
+                let angle = Some(parse_number(value)?);
+                match key {
+                    "inclination" => self.inclination = angle,
+                    "ascending_node" => self.ascending_node = angle,
+                    "argument_of_periapsis" => self.argument_of_periapsis = angle,
+                    "true_anomaly" => self.true_anomaly = angle,
+                    _ => self.mean_anomaly = angle,
+                }
+                return Ok(());
+            }
+            "position_x" => (&mut self.position_x, "position_x"),
+            "position_y" => (&mut self.position_y, "position_y"),
+            "position_z" => (&mut self.position_z, "position_z"),
+            "velocity_x" => (&mut self.velocity_x, "velocity_x"),
+            "velocity_y" => (&mut self.velocity_y, "velocity_y"),
+            "velocity_z" => (&mut self.velocity_z, "velocity_z"),
+            _ => return Err(ConfigErrorKind::UnknownKey),
+        };
+        *field = parse_number(value)?;
+        self.state_key.get_or_insert(name);
+        Ok(())
+    }
+
+    /// The parent and orbital elements, if the body is placed by an orbit,
+    /// or which key is at fault
+    fn orbit(&self) -> Result<Option<(String, OrbitalElements)>, BodyError> {
+        let has_elements = [
+            self.semi_major_axis,
+            self.eccentricity,
+            self.inclination,
+            self.ascending_node,
+            self.argument_of_periapsis,
+            self.true_anomaly,
+            self.mean_anomaly,
+        ]
+        .iter()
+        .any(Option::is_some);
+        let Some(ref parent) = self.parent else {
+            if has_elements {
+                return Err((ConfigErrorKind::MissingKey, Some("parent")));
+            }
+            return Ok(None);
+        };
+        if let Some(key) = self.state_key {
+            return Err((ConfigErrorKind::ConflictingKey(String::from("parent")), Some(key)));
         }
+        let semi_major_axis = self
+            .semi_major_axis
+            .ok_or((ConfigErrorKind::MissingKey, Some("semi_major_axis")))?;
+        let anomaly = match (self.true_anomaly, self.mean_anomaly) {
+            (Some(_), Some(_)) => {
+                return Err((ConfigErrorKind::ConflictingKey(String::from("true_anomaly")), Some("mean_anomaly")))
+            }
+            (_, Some(mean)) => Anomaly::Mean(mean.to_radians()),
+            (nu, None) => Anomaly::True(nu.unwrap_or(0.0).to_radians()),
+        };
+        let elements = OrbitalElements {
+            semi_major_axis,
+            eccentricity: self.eccentricity.unwrap_or(0.0),
+            inclination: self.inclination.unwrap_or(0.0).to_radians(),
+            ascending_node: self.ascending_node.unwrap_or(0.0).to_radians(),
+            argument_of_periapsis: self.argument_of_periapsis.unwrap_or(0.0).to_radians(),
+            anomaly,
+        };
+        Ok(Some((parent.clone(), elements)))
+    }
+
+    /// Build the body, or report which key is at fault
+    fn to_body(&self) -> Result<Body, BodyError> {
+        let mass = self.mass.ok_or((ConfigErrorKind::MissingMass, Some("mass")))?;
 
-        Ok(Body::new(
-            self.mass,
+        let mut body = Body::new(
+            mass,
             [self.position_x, self.position_y, self.position_z],
             [self.velocity_x, self.velocity_y, self.velocity_z],
-        ))
+        )
+        .with_name(&self.name)
+        .with_radius(self.radius);
+        body.color = self.color;
+        Ok(body)
+    }
+}
+
+/// Parse a body name, which must be usable as a CSV column prefix
+fn parse_name(value: &str) -> Result<String, ConfigErrorKind> {
+    if value.is_empty() || value.contains([',', '"', '\'']) {
+        return Err(ConfigErrorKind::InvalidValue {
+            value: value.to_string(),
+            expected: "a non-empty name without commas or quotes",
+        });
     }
+    Ok(value.to_string())
+}
+
+/// Parse a colour given as `#rrggbb` or as three components in [0, 1]
+fn parse_color(value: &str) -> Result<[f32; 3], ConfigErrorKind> {
+    let invalid = || ConfigErrorKind::InvalidValue {
+        value: value.to_string(),
+        expected: "#rrggbb or three components between 0 and 1",
+    };
+
+    if let Some(hex) = value.strip_prefix('#') {
+        if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+        let mut color = [0.0; 3];
+        for (channel, component) in color.iter_mut().enumerate() {
+            let byte = u8::from_str_radix(&hex[2 * channel..2 * channel + 2], 16).map_err(|_| invalid())?;
+            *component = byte as f32 / 255.0;
+        }
+        return Ok(color);
+    }
+
+    let components: Vec<&str> = value.split(',').map(str::trim).collect();
+    if components.len() != 3 {
+        return Err(invalid());
+    }
+    let mut color = [0.0; 3];
+    for (component, text) in color.iter_mut().zip(components) {
+        let number: f32 = text.parse().map_err(|_| invalid())?;
+        if !(0.0..=1.0).contains(&number) {
+            return Err(invalid());
+        }
+        *component = number;
+    }
+    Ok(color)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn parse_ini_content(content: &str) -> Result<SimulationConfig, ConfigError> {
+        parse_ini_str(content, ParseMode::Strict)
+    }
+
     #[test]
     fn test_parse_simple_config() {
         let content = r#"
@@ -211,4 +1281,466 @@ velocity_z = 0
         let result = parse_ini_content(content);
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_parse_simulation_section() {
+        let content = r#"
+[Simulation]
+time_step = 3600
+duration = 8.64e6
+output_file = ./data/custom.csv  # Inline comment
+output_interval = 86400
+output_columns = velocity, angular_momentum
+diagnostics_file = ./data/diagnostics.csv
+binary_file = ./data/custom.traj
+integrator = yoshida4
+
+[Body1]
+mass = 1e30
+"#;
+
+        let config = parse_ini_content(content).unwrap();
+        assert_eq!(config.bodies.len(), 1);
+        assert_eq!(config.time_step, 3600.0);
+        assert_eq!(config.duration, Some(8.64e6));
+        assert_eq!(config.output_file, "./data/custom.csv");
+        assert_eq!(config.output_interval, Some(86400.0));
+        assert!(config.output_columns.velocity && config.output_columns.angular_momentum);
+        assert!(!config.output_columns.total_energy);
+        assert_eq!(config.diagnostics_file.as_deref(), Some("./data/diagnostics.csv"));
+        assert_eq!(config.binary_file.as_deref(), Some("./data/custom.traj"));
+        assert_eq!(config.integrator, IntegratorKind::Yoshida4);
+        assert!(config.adaptive.is_none());
+    }
+
+    #[test]
+    fn test_duration_needs_forward_steps() {
+        let content = "[Simulation]\ntime_step = -3600\nduration = 86400\n[Body1]\nmass = 1\n";
+        let err = parse_ini_content(content).unwrap_err();
+        assert_eq!((err.line, err.key.as_deref()), (Some(2), Some("time_step")));
+        assert_eq!(err.section.as_deref(), Some("Simulation"));
+
+        let config = parse_ini_str(content, ParseMode::Lenient).unwrap();
+        assert_eq!(config.time_step, 86400.0);
+        assert_eq!(config.duration, Some(86400.0));
+        assert_eq!(config.warnings.len(), 1);
+
+        let err = parse_ini_content("[Simulation]\nduration = -1\n[Body1]\nmass = 1\n").unwrap_err();
+        assert_eq!(err.key.as_deref(), Some("duration"));
+
+        // Without a duration a negative step runs the system backwards
+        let config = parse_ini_content("[Simulation]\ndt = -3600\nnum_steps = 10\n[Body1]\nmass = 1\n").unwrap();
+        assert_eq!(config.time_step, -3600.0);
+    }
+
+    #[test]
+    fn test_parse_adaptive_settings() {
+        let content = r#"
+[Body1]
+mass = 1e30
+
+[Simulation]
+num_steps = 50
+rel_tolerance = 1e-8
+max_time_step = 1e6
+"#;
+
+        let config = parse_ini_content(content).unwrap();
+        assert_eq!(config.num_steps, 50);
+        assert_eq!(config.integrator, IntegratorKind::Rkf45);
+        let adaptive = config.adaptive.unwrap();
+        assert_eq!(adaptive.rel_tolerance, 1e-8);
+        assert_eq!(adaptive.max_dt, 1e6);
+        assert_eq!(adaptive.min_dt, AdaptiveSettings::default().min_dt);
+    }
+
+    #[test]
+    fn test_adaptive_stepping_needs_an_error_estimate() {
+        let content = "[Simulation]\nintegrator = leapfrog\nrel_tolerance = 1e-9\n[Body1]\nmass = 1\n";
+        let err = parse_ini_content(content).unwrap_err();
+        assert_eq!((err.line, err.key.as_deref()), (Some(3), Some("rel_tolerance")));
+        assert!(matches!(err.kind, ConfigErrorKind::ConflictingKey(ref other) if other == "integrator"));
+
+        let config = parse_ini_str(content, ParseMode::Lenient).unwrap();
+        assert_eq!(config.warnings.len(), 1);
+        assert_eq!((config.integrator, config.adaptive), (IntegratorKind::Leapfrog, None));
+
+        let err = parse_ini_content("[Simulation]\nadaptive = true\nintegrator = yoshida4\n[Body1]\nmass = 1\n")
+            .unwrap_err();
+        assert_eq!(err.key.as_deref(), Some("adaptive"));
+
+        let content = "[Simulation]\nintegrator = leapfrog\nadaptive = false\nrel_tolerance = 1e-9\n[Body1]\nmass = 1\n";
+        assert_eq!(parse_ini_content(content).unwrap().adaptive, None);
+    }
+
+    #[test]
+    fn test_absolute_tolerances_follow_the_units() {
+        let units = UnitSystem::Astronomical;
+        let config = parse_ini_content("[Simulation]\nunits = au\nabs_tolerance = 1e-3\n[Body1]\nmass = 1\n").unwrap();
+        let adaptive = config.adaptive.unwrap();
+        assert_eq!(adaptive.abs_tolerance, 1e-3 * units.length());
+        assert_eq!(adaptive.abs_velocity_tolerance, 1e-3 * units.length() / units.time());
+
+        let content = "[Simulation]\nunits = au\nabs_tolerance = 1e-3\nabs_velocity_tolerance = 1e-6\n[Body1]\nmass = 1\n";
+        let adaptive = parse_ini_content(content).unwrap().adaptive.unwrap();
+        assert_eq!(adaptive.abs_velocity_tolerance, 1e-6 * units.length() / units.time());
+    }
+
+    #[test]
+    fn test_adaptive_step_bounds_must_be_ordered() {
+        // The default minimum step is 1 second
+        let content = "[Simulation]\nmax_time_step = 0.5\n[Body1]\nmass = 1\n";
+        let err = parse_ini_content(content).unwrap_err();
+        assert_eq!((err.line, err.key.as_deref()), (Some(2), Some("max_time_step")));
+        assert_eq!(err.section.as_deref(), Some("Simulation"));
+        assert!(err.to_string().contains("no smaller than min_time_step"), "{}", err);
+
+        let config = parse_ini_str(content, ParseMode::Lenient).unwrap();
+        assert_eq!(config.warnings.len(), 1);
+        assert_eq!(config.adaptive, Some(AdaptiveSettings::default()));
+
+        let content = "[Simulation]\nunits = au\nmin_time_step = 365\n[Body1]\nmass = 1\n";
+        let err = parse_ini_content(content).unwrap_err();
+        assert_eq!((err.line, err.key.as_deref()), (Some(3), Some("min_time_step")));
+
+        let content = "[Simulation]\nmin_time_step = 10\nmax_time_step = 10\n[Body1]\nmass = 1\n";
+        assert!(parse_ini_content(content).is_ok());
+    }
+
+    #[test]
+    fn test_parse_force_solver() {
+        let config = parse_ini_content("[Simulation]\nforce_solver = barnes-hut\n[Body1]\nmass = 1\n").unwrap();
+        assert_eq!(config.force_solver, ForceSolver::barnes_hut(crate::forces::DEFAULT_OPENING_ANGLE));
+
+        let config = parse_ini_content("[Simulation]\nopening_angle = 0.7\n[Body1]\nmass = 1\n").unwrap();
+        assert_eq!(config.force_solver, ForceSolver::barnes_hut(0.7));
+
+        let config = parse_ini_content("[Body1]\nmass = 1\n").unwrap();
+        assert_eq!(config.force_solver, ForceSolver::Direct);
+
+        let err = parse_ini_content("[Simulation]\nopening_angle = -1\n[Body1]\nmass = 1\n").unwrap_err();
+        assert_eq!(err.key.as_deref(), Some("opening_angle"));
+
+        let config = parse_ini_content("[Simulation]\nforce_solver = parallel\n[Body1]\nmass = 1\n").unwrap();
+        assert_eq!(config.force_solver, ForceSolver::ParallelDirect { threads: 0 });
+
+        let config = parse_ini_content("[Simulation]\nthreads = 3\n[Body1]\nmass = 1\n").unwrap();
+        assert_eq!(config.force_solver, ForceSolver::ParallelDirect { threads: 3 });
+
+        let err = parse_ini_content("[Simulation]\nthreads = -2\n[Body1]\nmass = 1\n").unwrap_err();
+        assert_eq!(err.key.as_deref(), Some("threads"));
+    }
+
+    #[test]
+    fn test_solver_settings_need_their_solver() {
+        let content = "[Simulation]\nforce_solver = direct\nopening_angle = 0.7\n[Body1]\nmass = 1\n";
+        let err = parse_ini_content(content).unwrap_err();
+        assert_eq!((err.line, err.key.as_deref()), (Some(3), Some("opening_angle")));
+        assert!(matches!(err.kind, ConfigErrorKind::ConflictingKey(ref other) if other == "force_solver"));
+
+        let config = parse_ini_str(content, ParseMode::Lenient).unwrap();
+        assert_eq!(config.force_solver, ForceSolver::Direct);
+        assert_eq!(config.warnings.len(), 1);
+
+        let err = parse_ini_content("[Simulation]\nforce_solver = parallel\nopening_angle = 0.7\n[Body1]\nmass = 1\n")
+            .unwrap_err();
+        assert_eq!(err.key.as_deref(), Some("opening_angle"));
+
+        let err = parse_ini_content("[Simulation]\nforce_solver = barnes-hut\nthreads = 2\n[Body1]\nmass = 1\n")
+            .unwrap_err();
+        assert_eq!(err.key.as_deref(), Some("threads"));
+
+        let config = parse_ini_content("[Simulation]\nforce_solver = barnes-hut\nopening_angle = 0.3\n[Body1]\nmass = 1\n")
+            .unwrap();
+        assert_eq!(config.force_solver, ForceSolver::barnes_hut(0.3));
+
+        let err = parse_ini_content("[Simulation]\nopening_angle = 0.3\nthreads = 2\n[Body1]\nmass = 1\n").unwrap_err();
+        assert_eq!(err.key.as_deref(), Some("threads"));
+    }
+
+    #[test]
+    fn test_parse_softening() {
+        let config = parse_ini_content("[Simulation]\nsoftening = plummer 1e7\n[Body1]\nmass = 1\n").unwrap();
+        assert_eq!(config.softening, Softening::plummer(1e7));
+
+        let config = parse_ini_content("[Body1]\nmass = 1\n").unwrap();
+        assert_eq!(config.softening, Softening::None);
+
+        let err = parse_ini_content("[Simulation]\nsoftening = spline\n[Body1]\nmass = 1\n").unwrap_err();
+        assert_eq!(err.key.as_deref(), Some("softening"));
+        assert_eq!(err.line, Some(2));
+    }
+
+    #[test]
+    fn test_parse_collisions() {
+        let config = parse_ini_content("[Simulation]\ncollisions = merge\n[Body1]\nmass = 1\n").unwrap();
+        assert_eq!(config.collision_mode, CollisionMode::Merge);
+
+        let config = parse_ini_content("[Body1]\nmass = 1\n").unwrap();
+        assert_eq!(config.collision_mode, CollisionMode::Ignore);
+
+        let err = parse_ini_content("[Simulation]\ncollisions = explode\n[Body1]\nmass = 1\n").unwrap_err();
+        assert_eq!(err.key.as_deref(), Some("collisions"));
+    }
+
+    #[test]
+    fn test_units_are_converted_to_si() {
+        let content = "[Simulation]\nunits = au\ntime_step = 0.5\nsoftening = plummer 0.01\n\
+                       [Body1]\nmass = 1\n[Body2]\nmass = 3e-6\nposition_x = 1\nvelocity_y = 0.0172\n";
+        let config = parse_ini_content(content).unwrap();
+        assert_eq!(config.units, UnitSystem::Astronomical);
+        assert_eq!(config.output_units, UnitSystem::Astronomical);
+        assert_eq!(config.time_step, 43200.0);
+        assert_eq!(config.softening, Softening::plummer(0.01 * 1.495978707e11));
+        assert_eq!(config.bodies[0].mass, 1.98847e30);
+        assert_eq!(config.bodies[1].position[0], 1.495978707e11);
+        assert!((config.bodies[1].velocity[1] - 29_780.0).abs() < 10.0);
+
+        let config = parse_ini_content("[Simulation]\nunits = nbody\noutput_units = si\n[Body1]\nmass = 1\n").unwrap();
+        assert_eq!(config.units, UnitSystem::nbody(1.0, 1.0));
+        assert_eq!(config.output_units, UnitSystem::Si);
+        // Defaults are SI whatever the units
+        assert_eq!(config.time_step, 86400.0);
+
+        let err = parse_ini_content("[Simulation]\nunits = furlongs\n[Body1]\nmass = 1\n").unwrap_err();
+        assert_eq!(err.key.as_deref(), Some("units"));
+    }
+
+    #[test]
+    fn test_barycentric_and_output_frame() {
+        let content = "[Simulation]\nbarycentric = true\noutput_frame = body Moon\n\
+                       [Body1]\nname = Earth\nmass = 3\nvelocity_y = 1\n\
+                       [Body2]\nname = Moon\nmass = 1\nposition_x = 4\n";
+        let config = parse_ini_content(content).unwrap();
+        assert!(config.barycentric);
+        assert_eq!(config.bodies[0].position, [-1.0, 0.0, 0.0]);
+        assert_eq!(config.bodies[1].position, [3.0, 0.0, 0.0]);
+        assert_eq!(config.bodies[0].velocity, [0.0, 0.25, 0.0]);
+        assert_eq!(config.bodies[1].velocity, [0.0, -0.75, 0.0]);
+        assert_eq!(config.output_frame, OutputFrame::centred_on("Moon"));
+
+        let err = parse_ini_content("[Simulation]\noutput_frame = body Sun\n[Body1]\nmass = 1\n").unwrap_err();
+        assert_eq!(err.kind, ConfigErrorKind::UnknownBody(String::from("Sun")));
+        assert_eq!(err.key.as_deref(), Some("output_frame"));
+    }
+
+    #[test]
+    fn test_parse_elements_output() {
+        let content = "[Simulation]\nelements_file = ./elements.csv\nelements_primary = Earth\n\
+                       [Body1]\nname = Earth\nmass = 3\n";
+        let config = parse_ini_content(content).unwrap();
+        assert_eq!(config.elements_file.as_deref(), Some("./elements.csv"));
+        assert_eq!(config.elements_primary.as_deref(), Some("Earth"));
+
+        let content = "[Simulation]\nelements_primary = Sun\n[Body1]\nmass = 1\n";
+        let err = parse_ini_content(content).unwrap_err();
+        assert_eq!(err.kind, ConfigErrorKind::UnknownBody(String::from("Sun")));
+        assert_eq!(err.key.as_deref(), Some("elements_primary"));
+    }
+
+    #[test]
+    fn test_parse_post_newtonian() {
+        let config = parse_ini_content("[Simulation]\npost_newtonian = yes\n[Body1]\nmass = 1\n").unwrap();
+        assert!(config.post_newtonian);
+
+        let config = parse_ini_content("[Body1]\nmass = 1\n").unwrap();
+        assert!(!config.post_newtonian);
+
+        let err = parse_ini_content("[Simulation]\npost_newtonian = maybe\n[Body1]\nmass = 1\n").unwrap_err();
+        assert_eq!(err.key.as_deref(), Some("post_newtonian"));
+    }
+
+    #[test]
+    fn test_defaults_without_simulation_section() {
+        let content = "[Body1]\nmass = 1e30\n";
+        let config = parse_ini_content(content).unwrap();
+        assert_eq!(config.time_step, 86400.0);
+        assert_eq!(config.num_steps, 1000);
+        assert_eq!(config.duration, None);
+        assert!(config.adaptive.is_none());
+    }
+
+    #[test]
+    fn test_invalid_value_reports_location() {
+        let content = "[Body1]\nmass = 1e30\nposition_x = abc\n";
+        let err = parse_ini_content(content).unwrap_err();
+        assert_eq!(err.line, Some(3));
+        assert_eq!(err.section.as_deref(), Some("Body1"));
+        assert_eq!(err.key.as_deref(), Some("position_x"));
+        assert!(matches!(err.kind, ConfigErrorKind::InvalidValue { .. }));
+        assert_eq!(
+            err.to_string(),
+            "line 3: [Body1] position_x: invalid value 'abc' (expected a number)"
+        );
+    }
+
+    #[test]
+    fn test_strict_errors() {
+        let cases = [
+            ("[Body1]\nmass = 1e30\ncolour = red\n", ConfigErrorKind::UnknownKey, 3),
+            ("[Body1]\nmass = 1e30\n[Body1]\nmass = 2e30\n", ConfigErrorKind::DuplicateSection, 3),
+            ("[Body1]\nmass = 1e30\nmass = 2e30\n", ConfigErrorKind::DuplicateKey, 3),
+            ("[Simulation]\ntime_step = 60\ndt = 3600\n[Body1]\nmass = 1e30\n", ConfigErrorKind::DuplicateKey, 3),
+            ("[Body1]\nposition_x = 1\n[Body2]\nmass = 1e30\n", ConfigErrorKind::MissingMass, 1),
+            ("[Body1]\nmass = 1e30\nvelocity_x = inf\n", ConfigErrorKind::NonFiniteValue(String::from("inf")), 3),
+            ("[Body1]\nmass = 1e30\n[Planets]\n", ConfigErrorKind::UnknownSection, 3),
+            ("mass = 1e30\n[Body1]\nmass = 1e30\n", ConfigErrorKind::KeyOutsideSection, 1),
+            ("[Body1]\nmass = 1e30\noops\n", ConfigErrorKind::MalformedLine(String::from("oops")), 3),
+        ];
+
+        for (content, kind, line) in cases {
+            let err = parse_ini_content(content).unwrap_err();
+            assert_eq!(err.kind, kind, "for {:?}", content);
+            assert_eq!(err.line, Some(line), "for {:?}", content);
+        }
+    }
+
+    #[test]
+    fn test_nonpositive_mass_fails() {
+        let err = parse_ini_content("[Body1]\nmass = -5\n").unwrap_err();
+        assert_eq!(err.key.as_deref(), Some("mass"));
+        assert!(matches!(err.kind, ConfigErrorKind::InvalidValue { .. }));
+    }
+
+    #[test]
+    fn test_lenient_mode_collects_warnings() {
+        let content = r#"
+[Simulation]
+time_step = fast
+integrator = euler
+
+[Body1]
+mass = 1e30
+spin = 3
+
+[Body2]
+mass = 0
+
+[Body3]
+mass = 2e30
+"#;
+
+        let config = parse_ini_str(content, ParseMode::Lenient).unwrap();
+        assert_eq!(config.bodies.len(), 2);
+        assert_eq!(config.time_step, 86400.0);
+        assert_eq!(config.integrator, IntegratorKind::Rkf45);
+
+        let keys: Vec<_> = config.warnings.iter().map(|w| w.key.as_deref()).collect();
+        assert_eq!(keys, [Some("time_step"), Some("integrator"), Some("spin"), Some("mass"), Some("mass")]);
+    }
+
+    #[test]
+    fn test_parse_body_metadata() {
+        let content = r#"
+[Body1]
+mass = 1.989e30
+radius = 6.96e8
+color = #ffcc00   # hex colour, then a comment
+
+[Body2]
+name = Earth
+mass = 5.972e24
+color = 0.2, 0.4, 1.0
+"#;
+
+        let config = parse_ini_content(content).unwrap();
+        assert_eq!(config.bodies.len(), 2);
+        assert_eq!(config.bodies[0].name, "Body1");
+        assert_eq!(config.bodies[0].radius, 6.96e8);
+        assert_eq!(config.bodies[0].color, Some([1.0, 0.8, 0.0]));
+        assert_eq!(config.bodies[1].name, "Earth");
+        assert_eq!(config.bodies[1].radius, 0.0);
+        assert_eq!(config.bodies[1].color, Some([0.2, 0.4, 1.0]));
+    }
+
+    #[test]
+    fn test_body_metadata_errors() {
+        let cases = [
+            "[Body1]\nmass = 1e30\nradius = -1\n",
+            "[Body1]\nmass = 1e30\ncolor = #12345\n",
+            "[Body1]\nmass = 1e30\ncolor = 0.5, 1.5, 0\n",
+            "[Body1]\nmass = 1e30\nname = a,b\n",
+        ];
+        for content in cases {
+            let err = parse_ini_content(content).unwrap_err();
+            assert!(matches!(err.kind, ConfigErrorKind::InvalidValue { .. }), "for {:?}", content);
+            assert_eq!(err.line, Some(3), "for {:?}", content);
+        }
+
+        let err = parse_ini_content("[Body1]\nname = Sun\nmass = 1e30\n[Body2]\nname = Sun\nmass = 1e24\n").unwrap_err();
+        assert_eq!(err.kind, ConfigErrorKind::DuplicateName(String::from("Sun")));
+        assert_eq!(err.line, Some(4));
+    }
+
+    #[test]
+    fn test_orbital_elements() {
+        let content = r#"
+[Simulation]
+units = au
+
+[Body1]
+name = Sun
+mass = 1
+velocity_x = 0.001
+
+[Body2]
+name = Earth
+mass = 3e-6
+parent = Sun
+semi_major_axis = 1
+eccentricity = 0.5
+inclination = 90
+true_anomaly = 90
+"#;
+        let config = parse_ini_content(content).unwrap();
+        let (sun, earth) = (&config.bodies[0], &config.bodies[1]);
+        let au = crate::units::ASTRONOMICAL_UNIT;
+        // Semi-latus rectum a (1 - e^2) straight up the inclined plane
+        assert!(earth.position[0].abs() < 1.0);
+        assert!(earth.position[1].abs() < 1.0);
+        assert!((earth.position[2] - 0.75 * au).abs() < 1.0);
+        // Moving back towards x, where periapsis lies, and outwards along z
+        let mu = crate::simulator::G * (sun.mass + earth.mass);
+        let speed = (mu / (0.75 * au)).sqrt();
+        assert!((earth.velocity[0] - sun.velocity[0] + speed).abs() < 1e-6);
+        assert!(earth.velocity[1].abs() < 1e-6);
+        assert!((earth.velocity[2] - 0.5 * speed).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_orbital_element_errors() {
+        let body1 = "[Body1]\nname = Sun\nmass = 1e30\n";
+        let cases = [
+            ("[Body2]\nmass = 1e24\nparent = Moon\nsemi_major_axis = 1e11\n", Some("parent")),
+            ("[Body2]\nmass = 1e24\nparent = Sun\n", Some("semi_major_axis")),
+            ("[Body2]\nmass = 1e24\nsemi_major_axis = 1e11\n", Some("parent")),
+            ("[Body2]\nmass = 1e24\nparent = Sun\nsemi_major_axis = 1e11\nposition_y = 2\n", Some("position_y")),
+            ("[Body2]\nmass = 1e24\nparent = Sun\nsemi_major_axis = 1e11\ntrue_anomaly = 1\nmean_anomaly = 2\n", Some("mean_anomaly")),
+        ];
+        for (body2, key) in cases {
+            let content = format!("{}{}", body1, body2);
+            let err = parse_ini_content(&content).unwrap_err();
+            assert_eq!(err.key.as_deref(), key, "for {:?}", body2);
+            assert_eq!(err.line, Some(4), "for {:?}", body2);
+        }
+
+        let err = parse_ini_content("[Body1]\nmass = 1\nparent = Body1\neccentricity = 1\n").unwrap_err();
+        assert!(matches!(err.kind, ConfigErrorKind::InvalidValue { .. }));
+
+        // A parent defined later does not count, and a skipped body skips its moons
+        let content = "[Body1]\nmass = 1e24\nparent = Body2\nsemi_major_axis = 1e8\n\
+                       [Body2]\nmass = 1e30\n\
+                       [Body3]\nmass = 1e20\nparent = Body1\nsemi_major_axis = 1e6\n";
+        let config = parse_ini_str(content, ParseMode::Lenient).unwrap();
+        assert_eq!(config.bodies.len(), 1);
+        assert_eq!(config.bodies[0].name, "Body2");
+        assert_eq!(config.warnings.len(), 2);
+    }
+
+    #[test]
+    fn test_file_errors_name_the_file() {
+        let err = parse_ini_file("./does/not/exist.ini").unwrap_err();
+        assert!(matches!(err.kind, ConfigErrorKind::Io(std::io::ErrorKind::NotFound, _)));
+        assert!(err.to_string().starts_with("./does/not/exist.ini: "));
+    }
 }
@@ -0,0 +1,300 @@
+//! Conserved-quantity diagnostics
+//!
+//! A closed gravitating system conserves its energy, linear momentum and
+//! angular momentum, and its centre of mass moves in a straight line, so
+//! how far these wander shows how much to trust a run. `Diagnostics` is a
+//! snapshot of all of them plus the virial ratio 2K/|W|, which is 1 for a
+//! system in virial equilibrium. `DriftTracker` remembers the values at the
+//! start of a run and reports the relative error of later snapshots, along
+//! with the largest error seen so far.
+//!
+//! `Simulator::enable_diagnostics` writes a snapshot and its drift to a
+//! separate CSV file whenever a trajectory row is due:
+//!
+//! ```text
+//! time,kinetic_energy,potential_energy,energy,px,py,pz,Lx,Ly,Lz,com_x,com_y,com_z,com_vx,com_vy,com_vz,virial_ratio,energy_error,momentum_error,angular_momentum_error
+//! ```
+//!
+//! Energies use the same softening as the forces; the 1PN correction and
+//! force models are not included, so their effects show up as drift.
+
+use crate::body::Body;
+use crate::simulator::{system_angular_momentum, system_kinetic_energy, system_potential_energy};
+use crate::softening::Softening;
+use crate::units::UnitSystem;
+use crate::vector::{distance, norm};
+use std::fs::File;
+use std::io::Write;
+
+/// Header of a diagnostics CSV file
+pub const DIAGNOSTICS_HEADER: &str = "time,kinetic_energy,potential_energy,energy,px,py,pz,Lx,Ly,Lz,\
+com_x,com_y,com_z,com_vx,com_vy,com_vz,virial_ratio,energy_error,momentum_error,angular_momentum_error";
+
+/// Conserved and derived quantities of a system at one moment
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Diagnostics {
+    pub time: f64,
+    pub kinetic_energy: f64,
+    pub potential_energy: f64,
+    pub total_energy: f64,
+    /// Total linear momentum
+    pub momentum: [f64; 3],
+    /// Total angular momentum about the origin
+    pub angular_momentum: [f64; 3],
+    pub centre_of_mass: [f64; 3],
+    pub centre_of_mass_velocity: [f64; 3],
+    /// 2K/|W|: 1 in virial equilibrium, below 1 for a collapsing system
+    pub virial_ratio: f64,
+}
+
+impl Diagnostics {
+    /// Measure the given bodies, with energies softened like the forces
+    pub fn measure(time: f64, bodies: &[Body], softening: Softening) -> Self {
+        let kinetic_energy = system_kinetic_energy(bodies);
+        let potential_energy = system_potential_energy(bodies, softening);
+        let total_mass: f64 = bodies.iter().map(|body| body.mass).sum();
+        let momentum = momentum(bodies);
+        let mut centre_of_mass = [0.0; 3];
+        for body in bodies {
+            for (c, x) in centre_of_mass.iter_mut().zip(body.position) {
+                *c += body.mass * x / total_mass;
+            }
+        }
+        Diagnostics {
+            time,
+            kinetic_energy,
+            potential_energy,
+            total_energy: kinetic_energy + potential_energy,
+            momentum,
+            angular_momentum: system_angular_momentum(bodies),
+            centre_of_mass,
+            centre_of_mass_velocity: momentum.map(|p| p / total_mass),
+            virial_ratio: 2.0 * kinetic_energy / potential_energy.abs(),
+        }
+    }
+
+    /// CSV row laid out like `DIAGNOSTICS_HEADER`, converted from SI to `units`
+    pub fn row(&self, drift: Drift, units: UnitSystem) -> String {
+        let mut line = format!(
+            "{:.8},{:.10e},{:.10e},{:.10e}",
+            self.time / units.time(),
+            self.kinetic_energy / units.energy(),
+            self.potential_energy / units.energy(),
+            self.total_energy / units.energy()
+        );
+        let vectors = [
+            (self.momentum, units.mass() * units.velocity()),
+            (self.angular_momentum, units.angular_momentum()),
+            (self.centre_of_mass, units.length()),
+            (self.centre_of_mass_velocity, units.velocity()),
+        ];
+        for (vector, unit) in vectors {
+            for component in vector {
+                line.push_str(&format!(",{:.10e}", component / unit));
+            }
+        }
+        line.push_str(&format!(
+            ",{:.10e},{:.6e},{:.6e},{:.6e}",
+            self.virial_ratio, drift.energy, drift.momentum, drift.angular_momentum
+        ));
+        line
+    }
+}
+
+/// Total linear momentum of a set of bodies
+pub(crate) fn momentum(bodies: &[Body]) -> [f64; 3] {
+    let mut total = [0.0; 3];
+    for body in bodies {
+        for (p, v) in total.iter_mut().zip(body.velocity) {
+            *p += body.mass * v;
+        }
+    }
+    total
+}
+
+/// Relative errors of the conserved quantities against a reference
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Drift {
+    /// |E - E0| / |E0|
+    pub energy: f64,
+    /// |P - P0| over the sum of the bodies' momentum magnitudes at the start
+    pub momentum: f64,
+    /// |L - L0| over the sum of the bodies' angular momentum magnitudes at the start
+    pub angular_momentum: f64,
+}
+
+impl Drift {
+    /// The larger of each error
+    fn max(self, other: Drift) -> Drift {
+        Drift {
+            energy: self.energy.max(other.energy),
+            momentum: self.momentum.max(other.momentum),
+            angular_momentum: self.angular_momentum.max(other.angular_momentum),
+        }
+    }
+}
+
+/// Relative error of the conserved quantities over a run
+///
+/// Total momentum and angular momentum are often zero by construction, so
+/// their errors are measured against the sum of the individual bodies'
+/// magnitudes instead. A reference of zero leaves the error absolute.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DriftTracker {
+    /// Time of the reference values
+    pub(crate) time: f64,
+    pub(crate) energy: f64,
+    pub(crate) momentum: [f64; 3],
+    pub(crate) angular_momentum: [f64; 3],
+    pub(crate) momentum_scale: f64,
+    pub(crate) angular_momentum_scale: f64,
+    /// Largest errors recorded so far
+    pub(crate) max: Drift,
+}
+
+impl DriftTracker {
+    /// Take the given state as the reference
+    pub fn new(time: f64, bodies: &[Body], softening: Softening) -> Self {
+        let reference = Diagnostics::measure(time, bodies, softening);
+        DriftTracker {
+            time,
+            energy: reference.total_energy,
+            momentum: reference.momentum,
+            angular_momentum: reference.angular_momentum,
+            momentum_scale: bodies.iter().map(|b| b.mass * norm(b.velocity)).sum(),
+            angular_momentum_scale: bodies.iter().map(|b| norm(b.angular_momentum())).sum(),
+            max: Drift::default(),
+        }
+    }
+
+    /// Time of the reference state
+    pub fn reference_time(&self) -> f64 {
+        self.time
+    }
+
+    /// Total energy of the reference state
+    pub fn reference_energy(&self) -> f64 {
+        self.energy
+    }
+
+    /// Errors of a snapshot against the reference
+    pub fn drift(&self, current: &Diagnostics) -> Drift {
+        let relative = |error: f64, scale: f64| if scale > 0.0 { error / scale } else { error };
+        Drift {
+            energy: relative(
+                (current.total_energy - self.energy).abs(),
+                self.energy.abs(),
+            ),
+            momentum: relative(
+                distance(current.momentum, self.momentum),
+                self.momentum_scale,
+            ),
+            angular_momentum: relative(
+                distance(current.angular_momentum, self.angular_momentum),
+                self.angular_momentum_scale,
+            ),
+        }
+    }
+
+    /// Errors of a snapshot against the reference, remembering the largest
+    pub fn record(&mut self, current: &Diagnostics) -> Drift {
+        let drift = self.drift(current);
+        self.max = self.max.max(drift);
+        drift
+    }
+
+    /// The largest errors recorded so far
+    pub fn max_drift(&self) -> Drift {
+        self.max
+    }
+}
+
+/// A diagnostics CSV file whose header is written with the first row
+pub(crate) struct DiagnosticsOutput {
+    file: File,
+    header_written: bool,
+}
+
+impl DiagnosticsOutput {
+    pub(crate) fn new(file: File, header_written: bool) -> Self {
+        DiagnosticsOutput {
+            file,
+            header_written,
+        }
+    }
+
+    /// Whether the header, and so the first row, has been written
+    pub(crate) fn header_written(&self) -> bool {
+        self.header_written
+    }
+
+    /// Measure the bodies and write a row, with the drift against `tracker`
+    /// or zeros without one
+    pub(crate) fn write_row(
+        &mut self,
+        time: f64,
+        bodies: &[Body],
+        softening: Softening,
+        tracker: Option<&DriftTracker>,
+        units: UnitSystem,
+    ) -> std::io::Result<()> {
+        if !self.header_written {
+            writeln!(self.file, "{}", DIAGNOSTICS_HEADER)?;
+            self.header_written = true;
+        }
+        let diagnostics = Diagnostics::measure(time, bodies, softening);
+        let drift = tracker.map_or(Drift::default(), |tracker| tracker.drift(&diagnostics));
+        writeln!(self.file, "{}", diagnostics.row(drift, units))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn binary() -> Vec<Body> {
+        vec![
+            Body::new(3.0, [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+            Body::new(1.0, [-3.0, 0.0, 0.0], [0.0, -3.0, 1.0]),
+        ]
+    }
+
+    #[test]
+    fn test_measure() {
+        let d = Diagnostics::measure(2.0, &binary(), Softening::None);
+        assert_eq!(d.momentum, [0.0, 0.0, 1.0]);
+        assert_eq!(d.centre_of_mass, [0.0; 3]);
+        assert_eq!(d.centre_of_mass_velocity, [0.0, 0.0, 0.25]);
+        // 3 * (1 * 1) + 1 * (-3 * -3) about z; -3 * 1 from the z velocity about y
+        assert_eq!(d.angular_momentum, [0.0, 3.0, 12.0]);
+        assert_eq!(d.kinetic_energy, 1.5 + 5.0);
+        let w = -6.67430e-11 * 3.0 / 4.0;
+        assert!((d.potential_energy - w).abs() < 1e-25);
+        assert!((d.virial_ratio * w.abs() / 13.0 - 1.0).abs() < 1e-12);
+
+        let row = d.row(Drift::default(), UnitSystem::Si);
+        assert_eq!(
+            row.split(',').count(),
+            DIAGNOSTICS_HEADER.split(',').count()
+        );
+    }
+
+    #[test]
+    fn test_drift_tracker() {
+        let bodies = binary();
+        let mut tracker = DriftTracker::new(0.0, &bodies, Softening::None);
+        let start = Diagnostics::measure(0.0, &bodies, Softening::None);
+        assert_eq!(tracker.record(&start), Drift::default());
+
+        // Slow one body down: momentum changes by 0.5 of a scale of 3 + sqrt(10)
+        let mut moved = bodies.clone();
+        moved[0].velocity[1] = 5.0 / 6.0;
+        let drift = tracker.record(&Diagnostics::measure(1.0, &moved, Softening::None));
+        assert!((drift.momentum - 0.5 / (3.0 + 10f64.sqrt())).abs() < 1e-12);
+        assert!(drift.energy > 0.0);
+
+        // The maximum outlives a return to the start
+        assert_eq!(tracker.record(&start), Drift::default());
+        assert_eq!(tracker.max_drift(), drift);
+    }
+}
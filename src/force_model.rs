@@ -0,0 +1,140 @@
+//! Additional forces on top of gravity
+//!
+//! A `ForceModel` adds accelerations after the force solver has computed
+//! the gravitational ones, which is how drag, radiation pressure, thrust or
+//! extra potentials enter a simulation. `Simulator::add_force_model` keeps
+//! any number of them in a list and applies them in the order they were
+//! added. Closures taking the time and the bodies are force models too, so
+//! a one-off force needs no new type:
+//!
+//! ```
+//! use threebody_sim::{Body, Simulator};
+//!
+//! let bodies = vec![Body::new(1.0, [0.0; 3], [1.0, 0.0, 0.0])];
+//! let mut sim = Simulator::new(bodies, 0.1);
+//! // Linear drag with a damping time of 10 s
+//! sim.add_force_model(|_time: f64, bodies: &mut [Body]| {
+//!     for body in bodies.iter_mut() {
+//!         for k in 0..3 {
+//!             body.acceleration[k] -= body.velocity[k] / 10.0;
+//!         }
+//!     }
+//! });
+//! sim.run(10);
+//! assert!(sim.bodies()[0].velocity[0] < 1.0);
+//! ```
+
+use crate::body::Body;
+
+/// A source of acceleration evaluated alongside gravity
+pub trait ForceModel {
+    /// Add this model's contribution to every body's acceleration
+    ///
+    /// `time` is the simulation time of the state in `bodies`; integrators
+    /// with several stages evaluate forces at times inside the step, and
+    /// adaptive stepping may evaluate a step that is then rejected. The
+    /// accelerations already hold gravity and the contributions of the
+    /// models added before this one.
+    fn add_accelerations(&mut self, time: f64, bodies: &mut [Body]);
+
+    /// Short name for messages
+    fn name(&self) -> &str {
+        "custom"
+    }
+}
+
+impl<F> ForceModel for F
+where
+    F: FnMut(f64, &mut [Body]),
+{
+    fn add_accelerations(&mut self, time: f64, bodies: &mut [Body]) {
+        self(time, bodies)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempFile;
+    use crate::simulator::Simulator;
+    use crate::symplectic::Leapfrog;
+
+    /// Constant thrust along x on one body, switched off at `cutoff`
+    struct Thrust {
+        body: usize,
+        acceleration: f64,
+        cutoff: f64,
+        evaluations: usize,
+    }
+
+    impl ForceModel for Thrust {
+        fn add_accelerations(&mut self, time: f64, bodies: &mut [Body]) {
+            self.evaluations += 1;
+            if time < self.cutoff {
+                bodies[self.body].acceleration[0] += self.acceleration;
+            }
+        }
+
+        fn name(&self) -> &str {
+            "thrust"
+        }
+    }
+
+    #[test]
+    fn test_stateful_model_sees_stage_times() {
+        let bodies = vec![Body::new(1.0, [0.0; 3], [0.0; 3])];
+        let mut sim = Simulator::with_integrator(bodies, 1.0, Box::new(Leapfrog::new()));
+        sim.add_force_model(Thrust {
+            body: 0,
+            acceleration: 2.0,
+            cutoff: 5.5,
+            evaluations: 0,
+        });
+        assert_eq!(sim.force_model_names(), ["thrust"]);
+        sim.run(10);
+
+        // Leapfrog samples the thrust at whole seconds: five full steps and
+        // the half kick from t = 5 give v = 2 * 5.5
+        assert!((sim.bodies()[0].velocity[0] - 11.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_output_does_not_evaluate_models() {
+        use crate::output::OutputColumns;
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        let path = TempFile::new("model_output.csv");
+        let evaluations = |output: bool| {
+            let count = Rc::new(Cell::new(0));
+            let bodies = vec![Body::new(1.0, [0.0; 3], [0.0; 3])];
+            let mut sim = match output {
+                true => Simulator::with_output(bodies, 1.0, path.to_str().unwrap()).unwrap(),
+                false => Simulator::new(bodies, 1.0),
+            };
+            sim.set_output_columns(OutputColumns::all());
+            let counter = Rc::clone(&count);
+            sim.add_force_model(move |_: f64, _: &mut [Body]| counter.set(counter.get() + 1));
+            sim.run(5);
+            count.get()
+        };
+        assert_eq!(evaluations(true), evaluations(false));
+    }
+
+    #[test]
+    fn test_models_compose_in_order() {
+        let bodies = vec![Body::new(1.0, [0.0; 3], [0.0; 3])];
+        let mut sim = Simulator::new(bodies, 0.5);
+        sim.add_force_model(|_: f64, bodies: &mut [Body]| bodies[0].acceleration[1] += 1.0);
+        // Sees the first model's contribution and doubles it
+        sim.add_force_model(|_: f64, bodies: &mut [Body]| bodies[0].acceleration[1] *= 2.0);
+        sim.run(4);
+
+        // Constant acceleration 2 for 2 s
+        assert!((sim.bodies()[0].velocity[1] - 4.0).abs() < 1e-12);
+        assert!((sim.bodies()[0].position[1] - 4.0).abs() < 1e-12);
+
+        sim.clear_force_models();
+        assert!(sim.force_model_names().is_empty());
+    }
+}
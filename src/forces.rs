@@ -0,0 +1,254 @@
+//! Gravitational force solvers
+//!
+//! `ForceSolver::Direct` sums every pair of bodies exactly, at a cost that
+//! grows as N^2. `ForceSolver::ParallelDirect` does the same sum spread
+//! over several threads; each body's acceleration is summed over the other
+//! bodies in index order by exactly one thread, so the result does not
+//! depend on the number of threads. `ForceSolver::BarnesHut` groups
+//! distant bodies in an octree and treats each group as a single mass at
+//! its centre of mass, reducing the cost to roughly N log N for a small,
+//! tunable error. The opening angle sets that trade-off: a cell is only
+//! approximated when its size divided by its distance is below the angle,
+//! so 0 reproduces the direct sum and larger values are faster and less
+//! accurate.
+//!
+//! Every solver applies the same `Softening` kernel to each pairwise (or
+//! body-cell) interaction.
+
+use crate::barnes_hut::Octree;
+use crate::body::Body;
+use crate::softening::Softening;
+use std::fmt;
+use std::str::FromStr;
+
+/// Opening angle used when Barnes-Hut is chosen without one
+pub const DEFAULT_OPENING_ANGLE: f64 = 0.5;
+
+/// How gravitational accelerations are computed
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ForceSolver {
+    /// Exact pairwise summation
+    #[default]
+    Direct,
+    /// Exact summation on `threads` threads, or one per core when 0
+    ParallelDirect { threads: usize },
+    /// Octree approximation with the given opening angle
+    BarnesHut { opening_angle: f64 },
+}
+
+impl ForceSolver {
+    /// Barnes-Hut with the given opening angle
+    pub fn barnes_hut(opening_angle: f64) -> Self {
+        ForceSolver::BarnesHut { opening_angle }
+    }
+
+    /// Name used in configuration files and messages
+    pub fn name(&self) -> &'static str {
+        match self {
+            ForceSolver::Direct => "direct",
+            ForceSolver::ParallelDirect { .. } => "parallel",
+            ForceSolver::BarnesHut { .. } => "barnes-hut",
+        }
+    }
+
+    /// The opening angle, for Barnes-Hut
+    pub fn opening_angle(&self) -> Option<f64> {
+        match self {
+            ForceSolver::BarnesHut { opening_angle } => Some(*opening_angle),
+            _ => None,
+        }
+    }
+
+    /// Replace every body's acceleration with its gravitational acceleration
+    pub fn compute_accelerations(&self, bodies: &mut [Body], g: f64, softening: Softening) {
+        match self {
+            ForceSolver::Direct => direct_accelerations(bodies, g, softening),
+            ForceSolver::ParallelDirect { threads } => {
+                parallel_direct_accelerations(bodies, g, softening, *threads)
+            }
+            ForceSolver::BarnesHut { opening_angle } => {
+                Octree::build(bodies).accelerations(bodies, g, softening, *opening_angle)
+            }
+        }
+    }
+}
+
+impl FromStr for ForceSolver {
+    type Err = String;
+
+    /// Parse a solver name; Barnes-Hut gets `DEFAULT_OPENING_ANGLE` and
+    /// the parallel solver one thread per core
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().replace('_', "-").as_str() {
+            "direct" | "direct-sum" => Ok(ForceSolver::Direct),
+            "parallel" | "parallel-direct" => Ok(ForceSolver::ParallelDirect { threads: 0 }),
+            "barnes-hut" | "bh" | "tree" => Ok(ForceSolver::barnes_hut(DEFAULT_OPENING_ANGLE)),
+            other => Err(format!(
+                "unknown force solver '{}' (expected direct, parallel or barnes-hut)",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ForceSolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ForceSolver::Direct => f.write_str("direct"),
+            ForceSolver::ParallelDirect { threads: 0 } => f.write_str("parallel (one thread per core)"),
+            ForceSolver::ParallelDirect { threads } => write!(f, "parallel ({} threads)", threads),
+            ForceSolver::BarnesHut { opening_angle } => {
+                write!(f, "barnes-hut (opening angle {})", opening_angle)
+            }
+        }
+    }
+}
+
+/// Compute gravitational accelerations by summing over every pair
+pub fn direct_accelerations(bodies: &mut [Body], g: f64, softening: Softening) {
+    // Reset accelerations
+    for body in bodies.iter_mut() {
+        body.reset_acceleration();
+    }
+
+    // Compute pairwise gravitational forces
+    let n = bodies.len();
+    for i in 0..n {
+        for j in (i + 1)..n {
+            let r_vec = bodies[i].vector_to(&bodies[j]);
+            let r_squared = r_vec[0] * r_vec[0] + r_vec[1] * r_vec[1] + r_vec[2] * r_vec[2];
+
+            // Gravitational force magnitude: F = G * m1 * m2 / r^2, i.e.
+            // G * m1 * m2 * r / r^3 with the softened form of 1 / r^3
+            let force_over_dist = g * bodies[i].mass * bodies[j].mass * softening.force_factor(r_squared);
+
+            // Apply forces (Newton's 3rd law)
+            for (k, component) in r_vec.iter().enumerate() {
+                let f = force_over_dist * component;
+                bodies[i].acceleration[k] += f / bodies[i].mass;
+                bodies[j].acceleration[k] -= f / bodies[j].mass;
+            }
+        }
+    }
+}
+
+/// Compute gravitational accelerations by summing over every other body
+/// on several threads
+///
+/// `threads` of 0 uses one thread per available core. Every body's sum
+/// runs over the other bodies in index order, so the result is identical
+/// for any thread count; it can differ from `direct_accelerations`, which
+/// pairs up the terms, in the last bits.
+pub fn parallel_direct_accelerations(bodies: &mut [Body], g: f64, softening: Softening, threads: usize) {
+    let n = bodies.len();
+    if n == 0 {
+        return;
+    }
+    let threads = match threads {
+        0 => std::thread::available_parallelism().map_or(1, |cores| cores.get()),
+        threads => threads,
+    }
+    .min(n);
+
+    let sources: Vec<([f64; 3], f64)> = bodies.iter().map(|b| (b.position, b.mass)).collect();
+    let mut accelerations = vec![[0.0; 3]; n];
+    let chunk = n.div_ceil(threads);
+    if threads == 1 {
+        for (i, acceleration) in accelerations.iter_mut().enumerate() {
+            *acceleration = acceleration_on(i, &sources, g, softening);
+        }
+    } else {
+        std::thread::scope(|scope| {
+            for (c, block) in accelerations.chunks_mut(chunk).enumerate() {
+                let sources = &sources;
+                scope.spawn(move || {
+                    for (offset, acceleration) in block.iter_mut().enumerate() {
+                        *acceleration = acceleration_on(c * chunk + offset, sources, g, softening);
+                    }
+                });
+            }
+        });
+    }
+
+    for (body, acceleration) in bodies.iter_mut().zip(accelerations) {
+        body.acceleration = acceleration;
+    }
+}
+
+/// Acceleration of body `i` from every other `(position, mass)` source
+fn acceleration_on(i: usize, sources: &[([f64; 3], f64)], g: f64, softening: Softening) -> [f64; 3] {
+    let position = sources[i].0;
+    let mut acceleration = [0.0; 3];
+    for (j, (source, mass)) in sources.iter().enumerate() {
+        if j == i {
+            continue;
+        }
+        let d = [
+            source[0] - position[0],
+            source[1] - position[1],
+            source[2] - position[2],
+        ];
+        let factor = g * mass * softening.force_factor(d[0] * d[0] + d[1] * d[1] + d[2] * d[2]);
+        for (a, component) in acceleration.iter_mut().zip(d) {
+            *a += factor * component;
+        }
+    }
+    acceleration
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generators::random_cluster;
+    use crate::units::{ASTRONOMICAL_UNIT, SOLAR_MASS};
+
+    #[test]
+    fn test_force_solver_names() {
+        assert_eq!("direct".parse::<ForceSolver>().unwrap(), ForceSolver::Direct);
+        assert_eq!(
+            "Barnes_Hut".parse::<ForceSolver>().unwrap(),
+            ForceSolver::barnes_hut(DEFAULT_OPENING_ANGLE)
+        );
+        assert!("fmm".parse::<ForceSolver>().is_err());
+        assert_eq!(ForceSolver::barnes_hut(0.3).opening_angle(), Some(0.3));
+        assert_eq!(ForceSolver::Direct.name(), "direct");
+        assert_eq!(
+            "parallel".parse::<ForceSolver>().unwrap(),
+            ForceSolver::ParallelDirect { threads: 0 }
+        );
+    }
+
+    fn cluster(n: usize) -> Vec<Body> {
+        random_cluster(n, 1.0, 17, SOLAR_MASS, ASTRONOMICAL_UNIT).bodies
+    }
+
+    #[test]
+    fn test_parallel_direct_is_independent_of_thread_count() {
+        let bodies = cluster(301);
+        let mut reference = bodies.clone();
+        parallel_direct_accelerations(&mut reference, 6.67430e-11, Softening::None, 1);
+
+        for threads in [2, 3, 8, 0, 1000] {
+            let mut parallel = bodies.clone();
+            parallel_direct_accelerations(&mut parallel, 6.67430e-11, Softening::None, threads);
+            for (a, b) in reference.iter().zip(&parallel) {
+                assert_eq!(a.acceleration.map(f64::to_bits), b.acceleration.map(f64::to_bits));
+            }
+        }
+    }
+
+    #[test]
+    fn test_parallel_direct_matches_serial() {
+        let mut serial = cluster(200);
+        let mut parallel = serial.clone();
+        direct_accelerations(&mut serial, 6.67430e-11, Softening::None);
+        parallel_direct_accelerations(&mut parallel, 6.67430e-11, Softening::None, 4);
+
+        for (a, b) in serial.iter().zip(&parallel) {
+            let norm = a.acceleration.iter().map(|x| x * x).sum::<f64>().sqrt();
+            for k in 0..3 {
+                assert!((a.acceleration[k] - b.acceleration[k]).abs() <= 1e-12 * norm);
+            }
+        }
+    }
+}
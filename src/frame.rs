@@ -0,0 +1,164 @@
+//! Reference frames for initial conditions and output
+//!
+//! Initial conditions are rarely set up with zero total momentum, so the
+//! whole system drifts through space as it evolves. `to_barycentric`
+//! shifts a set of bodies so that their centre of mass sits at rest at the
+//! origin, which changes nothing about the dynamics.
+//!
+//! `OutputFrame` chooses the frame trajectory rows are written in: the
+//! inertial frame of the simulation, or a frame that moves with one body,
+//! which then stays at the origin. Positions, velocities and accelerations
+//! are taken relative to that body; the energy and angular momentum
+//! columns are still computed from the inertial state.
+
+use crate::body::Body;
+use std::fmt;
+use std::str::FromStr;
+
+/// Shift bodies so that their centre of mass is at rest at the origin
+pub fn to_barycentric(bodies: &mut [Body]) {
+    let total_mass: f64 = bodies.iter().map(|body| body.mass).sum();
+    if total_mass <= 0.0 {
+        return;
+    }
+    let mut position = [0.0; 3];
+    let mut velocity = [0.0; 3];
+    for body in bodies.iter() {
+        for k in 0..3 {
+            position[k] += body.mass * body.position[k] / total_mass;
+            velocity[k] += body.mass * body.velocity[k] / total_mass;
+        }
+    }
+    for body in bodies.iter_mut() {
+        for k in 0..3 {
+            body.position[k] -= position[k];
+            body.velocity[k] -= velocity[k];
+        }
+    }
+}
+
+/// The frame trajectory output is written in
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum OutputFrame {
+    /// The frame the simulation runs in
+    #[default]
+    Inertial,
+    /// Centred on, and moving with, the named body
+    Body(String),
+}
+
+impl OutputFrame {
+    /// A frame centred on the named body
+    pub fn centred_on(name: &str) -> Self {
+        OutputFrame::Body(name.to_string())
+    }
+
+    /// The bodies as seen in this frame
+    ///
+    /// `labels` are the bodies' output names. If the centre body no longer
+    /// exists, for instance after merging into another, every position,
+    /// velocity and acceleration is `NaN`.
+    pub fn transform(&self, bodies: &[Body], labels: &[String]) -> Vec<Body> {
+        let name = match self {
+            OutputFrame::Inertial => return bodies.to_vec(),
+            OutputFrame::Body(name) => name,
+        };
+        let centre = labels
+            .iter()
+            .position(|label| label == name)
+            .map(|idx| bodies[idx].clone());
+        bodies
+            .iter()
+            .map(|body| {
+                let mut relative = body.clone();
+                for k in 0..3 {
+                    let (x, v, a) = match centre {
+                        Some(ref centre) => (
+                            centre.position[k],
+                            centre.velocity[k],
+                            centre.acceleration[k],
+                        ),
+                        None => (f64::NAN, f64::NAN, f64::NAN),
+                    };
+                    relative.position[k] -= x;
+                    relative.velocity[k] -= v;
+                    relative.acceleration[k] -= a;
+                }
+                relative
+            })
+            .collect()
+    }
+}
+
+impl FromStr for OutputFrame {
+    type Err = String;
+
+    /// Parse `inertial`, or `body <name>` for a frame centred on a body
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("inertial") {
+            return Ok(OutputFrame::Inertial);
+        }
+        match s.split_once(char::is_whitespace) {
+            Some((kind, name)) if kind.eq_ignore_ascii_case("body") && !name.trim().is_empty() => {
+                Ok(OutputFrame::centred_on(name.trim()))
+            }
+            _ => Err(format!(
+                "unknown output frame '{}' (expected inertial or body <name>)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for OutputFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputFrame::Inertial => f.write_str("inertial"),
+            OutputFrame::Body(name) => write!(f, "body {}", name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_barycentric() {
+        let mut bodies = vec![
+            Body::new(3.0, [1.0, 2.0, 0.0], [0.0, 1.0, 0.0]),
+            Body::new(1.0, [5.0, 2.0, 4.0], [4.0, 1.0, 0.0]),
+        ];
+        to_barycentric(&mut bodies);
+        assert_eq!(bodies[0].position, [-1.0, 0.0, -1.0]);
+        assert_eq!(bodies[1].position, [3.0, 0.0, 3.0]);
+        assert_eq!(bodies[0].velocity, [-1.0, 0.0, 0.0]);
+        assert_eq!(bodies[1].velocity, [3.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_body_frame() {
+        let bodies = vec![
+            Body::new(1.0, [1.0, 1.0, 1.0], [2.0, 0.0, 0.0]).with_name("Sun"),
+            Body::new(1.0, [3.0, 1.0, 1.0], [2.0, 5.0, 0.0]).with_name("Earth"),
+        ];
+        let labels = vec![String::from("Sun"), String::from("Earth")];
+        let frame: OutputFrame = "body Sun".parse().unwrap();
+        let seen = frame.transform(&bodies, &labels);
+        assert_eq!(seen[0].position, [0.0; 3]);
+        assert_eq!(seen[1].position, [2.0, 0.0, 0.0]);
+        assert_eq!(seen[1].velocity, [0.0, 5.0, 0.0]);
+
+        let gone = OutputFrame::centred_on("Moon").transform(&bodies, &labels);
+        assert!(gone[1].position[0].is_nan());
+
+        assert_eq!(OutputFrame::Inertial.transform(&bodies, &labels), bodies);
+        assert_eq!(frame.to_string().parse::<OutputFrame>().unwrap(), frame);
+        assert_eq!(
+            "Inertial".parse::<OutputFrame>().unwrap(),
+            OutputFrame::Inertial
+        );
+        assert!("Sun".parse::<OutputFrame>().is_err());
+    }
+}
@@ -0,0 +1,499 @@
+//! Initial conditions for well-known N-body setups
+//!
+//! Each generator builds a `Scenario`: the bodies, in SI like everything
+//! the simulator uses, together with settings that suit them. The classic
+//! setups are defined in N-body units (G = 1), so every generator takes
+//! the mass and length unit to scale them to, and the scenario keeps those
+//! units for export: `Scenario::save_ini` writes a configuration file in
+//! which the textbook numbers appear as they are.
+//!
+//! - `figure_eight`: the Chenciner-Montgomery three-body choreography
+//! - `lagrange_triangle`: three bodies rotating as a rigid equilateral
+//!   triangle
+//! - `pythagorean`: masses 3, 4 and 5 at rest on a 3-4-5 triangle
+//! - `plummer_sphere`: a star cluster in equilibrium
+//! - `uniform_disk`: test particles on circular orbits around a central
+//!   mass
+//! - `random_cluster`: stars scattered uniformly in a sphere with a chosen
+//!   virial ratio
+//!
+//! Random setups take a seed and always produce the same bodies for it.
+
+use crate::body::Body;
+use crate::integrator::IntegratorKind;
+use crate::simulator::{system_kinetic_energy, system_potential_energy, AdaptiveSettings};
+use crate::softening::Softening;
+use crate::units::UnitSystem;
+use std::f64::consts::PI;
+use std::fmt::Write as _;
+use std::path::Path;
+
+/// Period of the figure-eight orbit in N-body units
+pub const FIGURE_EIGHT_PERIOD: f64 = 6.325_913_985;
+
+/// A set of initial conditions with settings to run them
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scenario {
+    /// Written as a comment at the top of an exported file
+    pub description: String,
+    /// Bodies in SI
+    pub bodies: Vec<Body>,
+    /// Units an exported file is written in
+    pub units: UnitSystem,
+    pub integrator: IntegratorKind,
+    /// Time step in seconds
+    pub time_step: f64,
+    /// Run length in seconds
+    pub duration: f64,
+    /// Time between output rows in seconds; `None` writes every step
+    pub output_interval: Option<f64>,
+    pub adaptive: Option<AdaptiveSettings>,
+    pub softening: Softening,
+}
+
+impl Scenario {
+    /// A scenario of bodies given in N-body units with the given mass and
+    /// length unit (kg and m)
+    fn new(description: &str, bodies: Vec<Body>, mass: f64, length: f64) -> Self {
+        let units = UnitSystem::nbody(mass, length);
+        Scenario {
+            description: description.to_string(),
+            bodies: bodies.iter().map(|body| units.body_to_si(body)).collect(),
+            units,
+            integrator: IntegratorKind::default(),
+            time_step: units.time() / 1000.0,
+            duration: units.time(),
+            output_interval: None,
+            adaptive: None,
+            softening: Softening::None,
+        }
+    }
+
+    /// The scenario as an INI configuration in `self.units`
+    ///
+    /// Numbers are written in exact round-trip form, so parsing the file
+    /// gives the same bodies up to the unit conversion.
+    pub fn to_ini(&self) -> String {
+        let units = self.units;
+        let mut text = String::new();
+        for line in self.description.lines() {
+            let _ = writeln!(text, "# {}", line);
+        }
+        let _ = writeln!(text, "\n[Simulation]");
+        match units {
+            UnitSystem::NBody { mass, length } => {
+                let _ = writeln!(text, "units = {} {:e} {:e}", units.name(), mass, length);
+            }
+            _ => {
+                let _ = writeln!(text, "units = {}", units.name());
+            }
+        }
+        let _ = writeln!(text, "integrator = {}", self.integrator);
+        let _ = writeln!(text, "time_step = {:e}", self.time_step / units.time());
+        let _ = writeln!(text, "duration = {:e}", self.duration / units.time());
+        if let Some(interval) = self.output_interval {
+            let _ = writeln!(text, "output_interval = {:e}", interval / units.time());
+        }
+        if self.softening != Softening::None {
+            let _ = writeln!(
+                text,
+                "softening = {} {:e}",
+                self.softening.name(),
+                self.softening.length() / units.length()
+            );
+        }
+        if let Some(settings) = self.adaptive {
+            let _ = writeln!(text, "adaptive = true");
+            let _ = writeln!(
+                text,
+                "abs_tolerance = {:e}",
+                settings.abs_tolerance / units.length()
+            );
+            let _ = writeln!(
+                text,
+                "abs_velocity_tolerance = {:e}",
+                settings.abs_velocity_tolerance / (units.length() / units.time())
+            );
+            let _ = writeln!(text, "rel_tolerance = {:e}", settings.rel_tolerance);
+            let _ = writeln!(text, "min_time_step = {:e}", settings.min_dt / units.time());
+            let _ = writeln!(text, "max_time_step = {:e}", settings.max_dt / units.time());
+        }
+
+        for (idx, body) in self.bodies.iter().enumerate() {
+            let body = units.body_from_si(body);
+            let _ = writeln!(text, "\n[Body{}]", idx + 1);
+            if !body.name.is_empty() {
+                let _ = writeln!(text, "name = {}", body.name);
+            }
+            let _ = writeln!(text, "mass = {:e}", body.mass);
+            if body.radius > 0.0 {
+                let _ = writeln!(text, "radius = {:e}", body.radius);
+            }
+            if let Some([r, g, b]) = body.color {
+                let _ = writeln!(text, "color = {}, {}, {}", r, g, b);
+            }
+            for (axis, x) in ["x", "y", "z"].iter().zip(body.position) {
+                // Adding zero turns -0 into 0
+                let _ = writeln!(text, "position_{} = {:e}", axis, x + 0.0);
+            }
+            for (axis, v) in ["x", "y", "z"].iter().zip(body.velocity) {
+                let _ = writeln!(text, "velocity_{} = {:e}", axis, v + 0.0);
+            }
+        }
+        text
+    }
+
+    /// Write the scenario to an INI file
+    pub fn save_ini<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        std::fs::write(path, self.to_ini())
+    }
+}
+
+/// The figure-eight choreography of three equal masses
+///
+/// Chenciner and Montgomery's solution: the bodies chase each other
+/// around one figure-eight curve, returning to the start after
+/// `FIGURE_EIGHT_PERIOD`. Each body has one mass unit. The scenario runs
+/// one period.
+pub fn figure_eight(mass: f64, length: f64) -> Scenario {
+    let position = [0.970_004_36, -0.243_087_53, 0.0];
+    let velocity = [-0.932_407_37, -0.864_731_46, 0.0];
+    let bodies = vec![
+        Body::new(1.0, position, velocity.map(|v| -v / 2.0)).with_name("A"),
+        Body::new(1.0, position.map(|x| -x), velocity.map(|v| -v / 2.0)).with_name("B"),
+        Body::new(1.0, [0.0; 3], velocity).with_name("C"),
+    ];
+    let mut scenario = Scenario::new("Figure-eight three-body choreography", bodies, mass, length);
+    scenario.integrator = IntegratorKind::Yoshida4;
+    scenario.duration = FIGURE_EIGHT_PERIOD * scenario.units.time();
+    scenario.time_step = scenario.duration / 5000.0;
+    scenario
+}
+
+/// Lagrange's equilateral solution for three bodies of the given masses
+///
+/// The bodies sit at the corners of a triangle with sides of one length
+/// unit and rotate about their centre of mass as a rigid body, with
+/// angular velocity sqrt(G M / L^3). The configuration is only stable when
+/// one mass dominates (Routh's criterion, roughly 27 (m1 m2 + m2 m3 + m3
+/// m1) < M^2). The scenario runs ten turns.
+pub fn lagrange_triangle(masses: [f64; 3], mass: f64, length: f64) -> Scenario {
+    let corners = [
+        [1.0, 0.0],
+        [-0.5, 0.75f64.sqrt()],
+        [-0.5, -(0.75f64.sqrt())],
+    ]
+    .map(|[x, y]| [x / 3f64.sqrt(), y / 3f64.sqrt()]);
+    let total: f64 = masses.iter().sum();
+    let centre = [0, 1].map(|k| (0..3).map(|i| masses[i] * corners[i][k]).sum::<f64>() / total);
+    let omega = total.sqrt();
+    let bodies = (0..3)
+        .map(|i| {
+            let (x, y) = (corners[i][0] - centre[0], corners[i][1] - centre[1]);
+            let name = ["A", "B", "C"][i];
+            Body::new(masses[i], [x, y, 0.0], [-omega * y, omega * x, 0.0]).with_name(name)
+        })
+        .collect();
+    let mut scenario = Scenario::new("Lagrange equilateral triangle", bodies, mass, length);
+    let period = 2.0 * PI / omega * scenario.units.time();
+    scenario.duration = 10.0 * period;
+    scenario.time_step = period / 1000.0;
+    scenario
+}
+
+/// Burrau's Pythagorean three-body problem
+///
+/// Masses of 3, 4 and 5 units start at rest at the corners of a right
+/// triangle with sides 3, 4 and 5, each opposite the side of its own
+/// length. After a series of close encounters the lightest body is
+/// ejected around t = 60 and the other two leave as a binary. The scenario
+/// runs to t = 70 with tight adaptive RKF45 tolerances, which the close
+/// encounters need.
+pub fn pythagorean(mass: f64, length: f64) -> Scenario {
+    let bodies = vec![
+        Body::new(3.0, [1.0, 3.0, 0.0], [0.0; 3]).with_name("Mass3"),
+        Body::new(4.0, [-2.0, -1.0, 0.0], [0.0; 3]).with_name("Mass4"),
+        Body::new(5.0, [1.0, -1.0, 0.0], [0.0; 3]).with_name("Mass5"),
+    ];
+    let mut scenario = Scenario::new("Pythagorean three-body problem", bodies, mass, length);
+    let time = scenario.units.time();
+    scenario.duration = 70.0 * time;
+    scenario.time_step = 1e-3 * time;
+    scenario.output_interval = Some(0.01 * time);
+    scenario.adaptive = Some(AdaptiveSettings {
+        abs_tolerance: 1e-12 * length,
+        abs_velocity_tolerance: 1e-12 * length / time,
+        rel_tolerance: 1e-12,
+        min_dt: 1e-9 * time,
+        max_dt: 0.01 * time,
+        ..AdaptiveSettings::default()
+    });
+    scenario
+}
+
+/// A Plummer sphere of `n` equal-mass stars
+///
+/// Drawn with the method of Aarseth, Hénon and Wielen (1974) in Hénon
+/// units: one mass unit in total, a virial radius of one length unit
+/// (Plummer radius 3π/16) and total energy -1/4. Stars beyond ten Plummer
+/// radii are redrawn. The cluster is moved to rest at the origin and
+/// softened by 0.01 length units; the scenario runs ten time units with
+/// leapfrog.
+pub fn plummer_sphere(n: usize, seed: u64, mass: f64, length: f64) -> Scenario {
+    let mut rng = Rng::new(seed);
+    let scale = 3.0 * PI / 16.0;
+    let mut bodies: Vec<Body> = (0..n)
+        .map(|i| {
+            let r = loop {
+                let x = rng.next_f64().max(f64::MIN_POSITIVE);
+                let r = 1.0 / (x.powf(-2.0 / 3.0) - 1.0).sqrt();
+                if r <= 10.0 {
+                    break r;
+                }
+            };
+            // Speed as a fraction of the escape speed, by von Neumann rejection
+            let q = loop {
+                let (q, y) = (rng.next_f64(), 0.1 * rng.next_f64());
+                if y < q * q * (1.0 - q * q).powf(3.5) {
+                    break q;
+                }
+            };
+            let speed = q * 2f64.sqrt() * (1.0 + r * r).powf(-0.25);
+            Body::new(
+                1.0 / n as f64,
+                rng.unit_vector().map(|x| x * r * scale),
+                rng.unit_vector().map(|v| v * speed / scale.sqrt()),
+            )
+            .with_name(&format!("Star{}", i + 1))
+        })
+        .collect();
+    crate::frame::to_barycentric(&mut bodies);
+    let mut scenario = Scenario::new(
+        &format!("Plummer sphere of {} stars, seed {}", n, seed),
+        bodies,
+        mass,
+        length,
+    );
+    let time = scenario.units.time();
+    scenario.integrator = IntegratorKind::Leapfrog;
+    scenario.softening = Softening::plummer(0.01 * length);
+    scenario.duration = 10.0 * time;
+    scenario.time_step = time / 128.0;
+    scenario.output_interval = Some(time / 16.0);
+    scenario
+}
+
+/// `n` light particles spread uniformly over a disk around a central mass
+///
+/// The disk lies in the x-y plane between 0.1 and 1 length unit from the
+/// centre, holds `disk_mass` mass units in total and turns counter-clockwise
+/// seen from +z. Each particle starts on a circular orbit for the central
+/// mass plus the disk mass inside its radius. The central body has
+/// `central_mass` units; the scenario runs one turn of the outer edge.
+pub fn uniform_disk(
+    n: usize,
+    central_mass: f64,
+    disk_mass: f64,
+    seed: u64,
+    mass: f64,
+    length: f64,
+) -> Scenario {
+    let mut rng = Rng::new(seed);
+    let (inner, outer) = (0.1f64, 1.0f64);
+    let mut bodies = vec![Body::new(central_mass, [0.0; 3], [0.0; 3]).with_name("Centre")];
+    for i in 0..n {
+        // Uniform in area between the two radii
+        let r = (inner * inner + rng.next_f64() * (outer * outer - inner * inner)).sqrt();
+        let angle = 2.0 * PI * rng.next_f64();
+        let enclosed =
+            central_mass + disk_mass * (r * r - inner * inner) / (outer * outer - inner * inner);
+        let speed = (enclosed / r).sqrt();
+        let (sin, cos) = angle.sin_cos();
+        bodies.push(
+            Body::new(
+                disk_mass / n as f64,
+                [r * cos, r * sin, 0.0],
+                [-speed * sin, speed * cos, 0.0],
+            )
+            .with_name(&format!("Particle{}", i + 1)),
+        );
+    }
+    crate::frame::to_barycentric(&mut bodies);
+    let description = format!(
+        "Uniform disk of {} particles around a central mass, seed {}",
+        n, seed
+    );
+    let mut scenario = Scenario::new(&description, bodies, mass, length);
+    let time = scenario.units.time();
+    let inner_period = 2.0 * PI * (inner.powi(3) / central_mass).sqrt();
+    scenario.integrator = IntegratorKind::Yoshida4;
+    scenario.softening = Softening::plummer(0.005 * length);
+    scenario.duration = 2.0 * PI * (outer.powi(3) / (central_mass + disk_mass)).sqrt() * time;
+    scenario.time_step = inner_period / 200.0 * time;
+    scenario.output_interval = Some(scenario.duration / 200.0);
+    scenario
+}
+
+/// `n` equal-mass stars scattered uniformly within a sphere of one length
+/// unit, with random velocities scaled to the given virial ratio 2K/|W|
+///
+/// A virial ratio of 1 starts the cluster in equilibrium and smaller ones
+/// make it collapse. The cluster has one mass unit in total and is moved
+/// to rest at the origin; like `plummer_sphere` it is softened by 0.01
+/// length units and runs ten time units with leapfrog.
+pub fn random_cluster(n: usize, virial_ratio: f64, seed: u64, mass: f64, length: f64) -> Scenario {
+    let mut rng = Rng::new(seed);
+    let mut bodies: Vec<Body> = (0..n)
+        .map(|i| {
+            let position = rng.in_unit_ball();
+            Body::new(1.0 / n as f64, position, rng.in_unit_ball())
+                .with_name(&format!("Star{}", i + 1))
+        })
+        .collect();
+    crate::frame::to_barycentric(&mut bodies);
+    let description = format!(
+        "Random cluster of {} stars, virial ratio {}, seed {}",
+        n, virial_ratio, seed
+    );
+    let mut scenario = Scenario::new(&description, bodies, mass, length);
+
+    // Scale the velocities in SI, where the energies are measured
+    let kinetic = system_kinetic_energy(&scenario.bodies);
+    let potential = system_potential_energy(&scenario.bodies, Softening::None);
+    if kinetic > 0.0 {
+        let factor = (virial_ratio * potential.abs() / (2.0 * kinetic)).sqrt();
+        for body in scenario.bodies.iter_mut() {
+            body.velocity = body.velocity.map(|v| v * factor);
+        }
+    }
+    let time = scenario.units.time();
+    scenario.integrator = IntegratorKind::Leapfrog;
+    scenario.softening = Softening::plummer(0.01 * length);
+    scenario.duration = 10.0 * time;
+    scenario.time_step = time / 128.0;
+    scenario.output_interval = Some(time / 16.0);
+    scenario
+}
+
+/// A small seeded xorshift generator, so that random setups are
+/// reproducible without a dependency
+struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // SplitMix64 spreads nearby seeds apart and never yields the
+        // all-zero state xorshift cannot leave
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        Rng {
+            state: (z ^ (z >> 31)).max(1),
+        }
+    }
+
+    /// Uniform in [0, 1)
+    fn next_f64(&mut self) -> f64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        (self.state >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Uniform within the unit ball
+    fn in_unit_ball(&mut self) -> [f64; 3] {
+        loop {
+            let point = [0, 1, 2].map(|_| 2.0 * self.next_f64() - 1.0);
+            if point.iter().map(|x| x * x).sum::<f64>() <= 1.0 {
+                return point;
+            }
+        }
+    }
+
+    /// Uniform on the unit sphere
+    fn unit_vector(&mut self) -> [f64; 3] {
+        let z = 2.0 * self.next_f64() - 1.0;
+        let angle = 2.0 * PI * self.next_f64();
+        let rho = (1.0 - z * z).sqrt();
+        [rho * angle.cos(), rho * angle.sin(), z]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{parse_ini_str, ParseMode};
+    use crate::diagnostics::Diagnostics;
+    use crate::units::{ASTRONOMICAL_UNIT, SOLAR_MASS};
+    use crate::vector::distance;
+    use crate::Simulator;
+
+    #[test]
+    fn test_figure_eight_returns_after_one_period() {
+        let scenario = figure_eight(SOLAR_MASS, ASTRONOMICAL_UNIT);
+        let mut sim = Simulator::with_integrator(
+            scenario.bodies.clone(),
+            scenario.time_step,
+            scenario.integrator.build(),
+        );
+        sim.run_until(scenario.duration);
+        for (start, end) in scenario.bodies.iter().zip(sim.bodies()) {
+            assert!(distance(start.position, end.position) < 1e-5 * ASTRONOMICAL_UNIT);
+        }
+    }
+
+    #[test]
+    fn test_lagrange_triangle_keeps_its_shape() {
+        let scenario = lagrange_triangle([1.0, 1e-3, 1e-3], 1.0, 1.0);
+        let d = Diagnostics::measure(0.0, &scenario.bodies, Softening::None);
+        assert!(d.momentum.iter().all(|p| p.abs() < 1e-15));
+        let mut sim = Simulator::new(scenario.bodies.clone(), scenario.time_step);
+        sim.run_until(scenario.duration / 10.0);
+        let b = sim.bodies();
+        for (i, j) in [(0, 1), (1, 2), (2, 0)] {
+            assert!((distance(b[i].position, b[j].position) - 1.0).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_random_setups_are_seeded() {
+        let a = plummer_sphere(200, 7, 1.0, 1.0);
+        assert_eq!(a.bodies, plummer_sphere(200, 7, 1.0, 1.0).bodies);
+        assert_ne!(a.bodies, plummer_sphere(200, 8, 1.0, 1.0).bodies);
+        let d = Diagnostics::measure(0.0, &a.bodies, Softening::None);
+        assert!((d.virial_ratio - 1.0).abs() < 0.3, "{}", d.virial_ratio);
+        assert!(d.centre_of_mass.iter().all(|x| x.abs() < 1e-12));
+
+        let cluster = random_cluster(100, 0.5, 3, SOLAR_MASS, ASTRONOMICAL_UNIT);
+        let d = Diagnostics::measure(0.0, &cluster.bodies, Softening::None);
+        assert!((d.virial_ratio - 0.5).abs() < 1e-12);
+
+        let disk = uniform_disk(50, 1.0, 0.01, 1, 1.0, 1.0);
+        assert_eq!(disk.bodies.len(), 51);
+        assert!(disk.bodies.iter().all(|body| body.position[2] == 0.0));
+    }
+
+    #[test]
+    fn test_export_round_trip() {
+        let scenario = pythagorean(SOLAR_MASS, ASTRONOMICAL_UNIT);
+        let ini = scenario.to_ini();
+        assert!(ini.contains("\nmass = 3e0\n"));
+        let config = parse_ini_str(&ini, ParseMode::Strict).unwrap();
+        assert_eq!(config.units, scenario.units);
+        assert_eq!(config.integrator, scenario.integrator);
+        assert!((config.duration.unwrap() / scenario.duration - 1.0).abs() < 1e-12);
+        let settings = config.adaptive.unwrap();
+        assert!((settings.min_dt / scenario.adaptive.unwrap().min_dt - 1.0).abs() < 1e-12);
+        for (read, body) in config.bodies.iter().zip(&scenario.bodies) {
+            assert_eq!(read.name, body.name);
+            assert!((read.mass / body.mass - 1.0).abs() < 1e-12);
+            assert!(distance(read.position, body.position) < 1e-12 * ASTRONOMICAL_UNIT);
+        }
+
+        let plummer = plummer_sphere(10, 1, 1.0, 1.0);
+        let config = parse_ini_str(&plummer.to_ini(), ParseMode::Strict).unwrap();
+        assert_eq!(config.softening, plummer.softening);
+        assert_eq!(config.bodies.len(), 10);
+    }
+}
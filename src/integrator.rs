@@ -1,19 +1,246 @@
-//! Runge-Kutta-Fehlberg (RKF45) adaptive time-stepping integrator
+//! Time integrators for N-body simulation
 //!
-//! This module provides a generic implementation of the RKF45 method
-//! for solving systems of first-order ODEs. It can be adapted to any
+//! This module defines the `Integrator` trait used by the simulator and
+//! provides a generic implementation of the Runge-Kutta-Fehlberg (RKF45)
+//! method for solving systems of first-order ODEs. It can be adapted to any
 //! N-body gravitational simulation by implementing the appropriate
-//! derivative function.
+//! derivative function. Symplectic schemes live in `symplectic`.
 
 use crate::body::Body;
+use crate::symplectic::{Leapfrog, WisdomHolman, Yoshida4};
+use std::fmt;
+use std::str::FromStr;
 
 /// State vector for a single body: [x, y, z, vx, vy, vz]
 pub type StateVector = [f64; 6];
 
 /// A function that computes derivatives (accelerations) for all bodies
-/// given their current state. The function receives a mutable slice
-/// of bodies and should compute/update their accelerations.
-pub type DerivativeFunction = fn(&mut [Body]);
+/// given their current state. The function receives the time elapsed since
+/// the start of the step being taken and a mutable slice of bodies, and
+/// should compute/update their accelerations. Closures may capture state
+/// such as the force solver in use.
+pub type DerivativeFunction<'a> = &'a mut dyn FnMut(f64, &mut [Body]);
+
+/// Information about a completed step, used for error control and dense output
+#[derive(Debug, Clone)]
+pub struct StepReport {
+    /// Per-body local error estimate in `[x, y, z, vx, vy, vz]` order, for
+    /// integrators that provide one
+    pub errors: Option<Vec<StateVector>>,
+    /// Per-body accelerations evaluated at the start of the step
+    pub start_accelerations: Vec<[f64; 3]>,
+    /// Per-body accelerations at the end of the step, taken from the force
+    /// evaluations the step already made; they may approximate the
+    /// accelerations of the final state rather than equal them
+    pub end_accelerations: Vec<[f64; 3]>,
+}
+
+/// A time-stepping scheme that the simulator can advance bodies with
+pub trait Integrator {
+    /// Short name identifying the scheme (e.g. in configs and output)
+    fn name(&self) -> &'static str;
+
+    /// Advance the bodies by one step of size `dt`
+    ///
+    /// Implementations must report the accelerations at the start and end
+    /// of the step without extra force evaluations, and return an error
+    /// estimate only if they can produce one; the simulator only adapts the
+    /// step size when one is available.
+    fn advance(
+        &mut self,
+        bodies: &mut [Body],
+        dt: f64,
+        derivative_fn: DerivativeFunction<'_>,
+    ) -> StepReport;
+}
+
+/// Selects one of the built-in integrators by name
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IntegratorKind {
+    /// Runge-Kutta-Fehlberg 4(5), the only scheme with an error estimate
+    #[default]
+    Rkf45,
+    /// Velocity-Verlet leapfrog
+    Leapfrog,
+    /// 4th-order Yoshida composition
+    Yoshida4,
+    /// Wisdom-Holman mapping around the most massive body
+    WisdomHolman,
+}
+
+impl IntegratorKind {
+    /// All built-in integrators
+    pub const ALL: [IntegratorKind; 4] = [
+        IntegratorKind::Rkf45,
+        IntegratorKind::Leapfrog,
+        IntegratorKind::Yoshida4,
+        IntegratorKind::WisdomHolman,
+    ];
+
+    /// Canonical name, matching `Integrator::name` of the built integrator
+    pub fn name(&self) -> &'static str {
+        match self {
+            IntegratorKind::Rkf45 => "rkf45",
+            IntegratorKind::Leapfrog => "leapfrog",
+            IntegratorKind::Yoshida4 => "yoshida4",
+            IntegratorKind::WisdomHolman => "wisdom-holman",
+        }
+    }
+
+    /// Whether the integrator estimates its error, as adaptive stepping needs
+    pub fn has_error_estimate(&self) -> bool {
+        *self == IntegratorKind::Rkf45
+    }
+
+    /// Create a new integrator of this kind
+    pub fn build(&self) -> Box<dyn Integrator> {
+        match self {
+            IntegratorKind::Rkf45 => Box::new(RungeKuttaFehlberg::new()),
+            IntegratorKind::Leapfrog => Box::new(Leapfrog::new()),
+            IntegratorKind::Yoshida4 => Box::new(Yoshida4::new()),
+            IntegratorKind::WisdomHolman => Box::new(WisdomHolman::new()),
+        }
+    }
+}
+
+impl FromStr for IntegratorKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().replace('_', "-").as_str() {
+            "rkf45" | "rkf" | "rk45" => Ok(IntegratorKind::Rkf45),
+            "leapfrog" | "verlet" | "velocity-verlet" => Ok(IntegratorKind::Leapfrog),
+            "yoshida4" | "yoshida" => Ok(IntegratorKind::Yoshida4),
+            "wisdom-holman" | "wh" => Ok(IntegratorKind::WisdomHolman),
+            other => Err(format!(
+                "unknown integrator '{}' (expected one of: {})",
+                other,
+                IntegratorKind::ALL.map(|k| k.name()).join(", ")
+            )),
+        }
+    }
+}
+
+impl fmt::Display for IntegratorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Continuous approximation of the bodies' motion across one step
+///
+/// Built from the states at both ends of a step, including their
+/// accelerations as reported in `StepReport`. Positions use quintic Hermite
+/// interpolation, matching position, velocity and acceleration at both ends;
+/// velocities and accelerations are the derivatives of that polynomial.
+///
+/// For RKF45 the end accelerations come from the stage with node 1 rather
+/// than from the final state, which keeps dense output free of extra force
+/// evaluations: within a step positions are then fourth and velocities third
+/// order accurate, the most the stages allow, and both ends still match the
+/// RKF45 solution exactly.
+#[derive(Debug, Clone)]
+pub struct DenseOutput {
+    /// Time at the start of the step
+    t0: f64,
+    /// Length of the step
+    dt: f64,
+    /// Bodies at the start of the step
+    start: Vec<Body>,
+    /// Bodies at the end of the step
+    end: Vec<Body>,
+}
+
+impl DenseOutput {
+    /// Create an interpolant for a step of length `dt` starting at `t0`
+    ///
+    /// Both `start` and `end` must carry the accelerations at (or
+    /// approximating) their state.
+    pub fn new(t0: f64, dt: f64, start: Vec<Body>, end: Vec<Body>) -> Self {
+        DenseOutput { t0, dt, start, end }
+    }
+
+    /// Time at the start of the step
+    pub fn start_time(&self) -> f64 {
+        self.t0
+    }
+
+    /// Time at the end of the step
+    pub fn end_time(&self) -> f64 {
+        self.t0 + self.dt
+    }
+
+    /// Evaluate the bodies at time `t` within the step
+    pub fn evaluate(&self, t: f64) -> Vec<Body> {
+        let h = self.dt;
+        if h == 0.0 {
+            return self.end.clone();
+        }
+        let theta = (t - self.t0) / h;
+        let t2 = theta * theta;
+        let t3 = t2 * theta;
+        let t4 = t3 * theta;
+        let t5 = t4 * theta;
+
+        // Quintic Hermite basis; the start-position weight is 1 - p1, so the
+        // positions enter as x0 + p1 * (x1 - x0) to avoid cancellation
+        let p1 = 10.0 * t3 - 15.0 * t4 + 6.0 * t5;
+        let v0 = theta - 6.0 * t3 + 8.0 * t4 - 3.0 * t5;
+        let a0 = 0.5 * t2 - 1.5 * t3 + 1.5 * t4 - 0.5 * t5;
+        let v1 = -4.0 * t3 + 7.0 * t4 - 3.0 * t5;
+        let a1 = 0.5 * t3 - t4 + 0.5 * t5;
+
+        // First derivatives with respect to theta
+        let dp1 = 30.0 * t2 - 60.0 * t3 + 30.0 * t4;
+        let dv0 = 1.0 - 18.0 * t2 + 32.0 * t3 - 15.0 * t4;
+        let da0 = theta - 4.5 * t2 + 6.0 * t3 - 2.5 * t4;
+        let dv1 = -12.0 * t2 + 28.0 * t3 - 15.0 * t4;
+        let da1 = 1.5 * t2 - 4.0 * t3 + 2.5 * t4;
+
+        // Second derivatives with respect to theta
+        let ddp1 = 60.0 * theta - 180.0 * t2 + 120.0 * t3;
+        let ddv0 = -36.0 * theta + 96.0 * t2 - 60.0 * t3;
+        let dda0 = 1.0 - 9.0 * theta + 18.0 * t2 - 10.0 * t3;
+        let ddv1 = -24.0 * theta + 84.0 * t2 - 60.0 * t3;
+        let dda1 = 3.0 * theta - 12.0 * t2 + 10.0 * t3;
+
+        self.start
+            .iter()
+            .zip(&self.end)
+            .map(|(b0, b1)| {
+                let mut body = b1.clone();
+                for dim in 0..3 {
+                    let dx = b1.position[dim] - b0.position[dim];
+                    let (vel0, vel1) = (b0.velocity[dim], b1.velocity[dim]);
+                    let (acc0, acc1) = (b0.acceleration[dim], b1.acceleration[dim]);
+
+                    body.position[dim] = b0.position[dim]
+                        + p1 * dx
+                        + h * (v0 * vel0 + v1 * vel1)
+                        + h * h * (a0 * acc0 + a1 * acc1);
+                    body.velocity[dim] =
+                        dp1 * dx / h + dv0 * vel0 + dv1 * vel1 + h * (da0 * acc0 + da1 * acc1);
+                    body.acceleration[dim] = ddp1 * dx / (h * h)
+                        + (ddv0 * vel0 + ddv1 * vel1) / h
+                        + dda0 * acc0
+                        + dda1 * acc1;
+                }
+                body
+            })
+            .collect()
+    }
+}
+
+/// The evaluated stages of one RKF45 step
+struct Stages {
+    /// `k[stage][body]`, `dt` times the derivative of the body's state
+    /// vector at that stage
+    k: Vec<Vec<StateVector>>,
+    /// Accelerations at the first stage, the start of the step
+    start_accelerations: Vec<[f64; 3]>,
+    /// Accelerations at the stage with node 1, the end of the step
+    end_accelerations: Vec<[f64; 3]>,
+}
 
 /// Runge-Kutta-Fehlberg (RKF45) integrator for N-body simulations
 ///
@@ -21,8 +248,8 @@ pub type DerivativeFunction = fn(&mut [Body]);
 /// 4th-order error estimation for adaptive time-stepping.
 pub struct RungeKuttaFehlberg {
     // RKF45 coefficients
+    /// Stage nodes, as fractions of the step
     c: [f64; 6],
-    a: [f64; 6],
     b: [[f64; 5]; 6],
     /// 5th order weights
     b5: [f64; 6],
@@ -35,7 +262,6 @@ impl RungeKuttaFehlberg {
     pub fn new() -> Self {
         RungeKuttaFehlberg {
             c: [0.0, 0.25, 3.0 / 8.0, 12.0 / 13.0, 1.0, 0.5],
-            a: [0.0, 0.25, 3.0 / 8.0, 12.0 / 13.0, 1.0, 0.5],
             b: [
                 [0.0, 0.0, 0.0, 0.0, 0.0],
                 [1.0 / 4.0, 0.0, 0.0, 0.0, 0.0],
@@ -57,115 +283,155 @@ impl RungeKuttaFehlberg {
     /// * `derivative_fn` - Function to compute accelerations from current state
     ///
     /// # Returns
-    /// A tuple of (new_error_estimate, old_error_estimate) which can be used
-    /// for adaptive time-stepping if desired
+    /// A tuple of (new_error_estimate, old_error_estimate). Both hold the
+    /// largest magnitude of any component of the local error estimate (the
+    /// difference between the 5th and 4th order solutions), mixing position
+    /// and velocity units; `step_with_error` gives it per component
     pub fn step(
         &self,
         bodies: &mut [Body],
         dt: f64,
-        derivative_fn: DerivativeFunction,
+        derivative_fn: DerivativeFunction<'_>,
     ) -> (f64, f64) {
+        let report = self.step_with_error(bodies, dt, derivative_fn);
+        let max_error = report
+            .errors
+            .unwrap_or_default()
+            .iter()
+            .flat_map(|e| e.iter())
+            .fold(0.0_f64, |acc, &e| acc.max(e.abs()));
+        (max_error, max_error)
+    }
+
+    /// Perform a single RKF45 step and return the per-body local error estimate
+    ///
+    /// The bodies are advanced with the 5th order solution. The report's
+    /// errors hold, for each body, the difference between the 5th and 4th
+    /// order solutions, which is what an adaptive step controller compares
+    /// against its tolerances.
+    ///
+    /// The report also carries the accelerations from the first stage, which
+    /// are the exact accelerations at the start of the step, and those of the
+    /// stage at the end of the step, which dense output uses in place of an
+    /// extra evaluation at the final state.
+    pub fn step_with_error(
+        &self,
+        bodies: &mut [Body],
+        dt: f64,
+        derivative_fn: DerivativeFunction<'_>,
+    ) -> StepReport {
+        let Stages { k, start_accelerations, end_accelerations } = self.compute_stages(bodies, dt, derivative_fn);
+
+        let mut errors = vec![[0.0; 6]; bodies.len()];
+        for (i, (body, error)) in bodies.iter_mut().zip(errors.iter_mut()).enumerate() {
+            let mut sol5 = [0.0; 6];
+            let mut sol4 = [0.0; 6];
+            for (stage, k_stage) in k.iter().enumerate() {
+                for dim in 0..6 {
+                    sol5[dim] += self.b5[stage] * k_stage[i][dim];
+                    sol4[dim] += self.b4[stage] * k_stage[i][dim];
+                }
+            }
+
+            // Apply 5th order solution
+            for dim in 0..3 {
+                body.position[dim] += sol5[dim];
+                body.velocity[dim] += sol5[dim + 3];
+            }
+
+            // Error estimate (difference between 5th and 4th order solutions)
+            for dim in 0..6 {
+                error[dim] = sol5[dim] - sol4[dim];
+            }
+        }
+
+        StepReport {
+            errors: Some(errors),
+            start_accelerations,
+            end_accelerations,
+        }
+    }
+
+    /// Evaluate the six RKF45 stages, leaving `bodies` in their initial state
+    fn compute_stages(
+        &self,
+        bodies: &mut [Body],
+        dt: f64,
+        derivative_fn: DerivativeFunction<'_>,
+    ) -> Stages {
         let n = bodies.len();
 
         // Store initial state
-        let initial_bodies: Vec<Body> = bodies.iter().copied().collect();
+        let initial_bodies: Vec<Body> = bodies.to_vec();
 
         // Compute k values (derivatives at various stages)
         let mut k = vec![vec![[0.0; 6]; n]; 6];
+        let mut start_accelerations = vec![[0.0; 3]; n];
+        let mut end_accelerations = vec![[0.0; 3]; n];
 
-        // k0: evaluate at current state (c0 = 0)
-        derivative_fn(bodies);
-        for i in 0..n {
-            k[0][i][0] = dt * bodies[i].velocity[0];
-            k[0][i][1] = dt * bodies[i].velocity[1];
-            k[0][i][2] = dt * bodies[i].velocity[2];
-            k[0][i][3] = dt * bodies[i].acceleration[0];
-            k[0][i][4] = dt * bodies[i].acceleration[1];
-            k[0][i][5] = dt * bodies[i].acceleration[2];
-        }
+        for stage in 0..6 {
+            if stage > 0 {
+                // Restore initial state
+                bodies.clone_from_slice(&initial_bodies);
 
-        // Compute remaining k values (k1 through k5)
-        for stage in 1..6 {
-            // Restore initial state
-            bodies.copy_from_slice(&initial_bodies);
-
-            // Compute weighted sum of previous k values to get intermediate state
-            for i in 0..n {
-                let mut dx = [0.0; 3];
-                let mut dv = [0.0; 3];
-                
-                for prev_stage in 0..stage {
-                    dx[0] += self.b[stage - 1][prev_stage] * k[prev_stage][i][0];
-                    dx[1] += self.b[stage - 1][prev_stage] * k[prev_stage][i][1];
-                    dx[2] += self.b[stage - 1][prev_stage] * k[prev_stage][i][2];
-                    dv[0] += self.b[stage - 1][prev_stage] * k[prev_stage][i][3];
-                    dv[1] += self.b[stage - 1][prev_stage] * k[prev_stage][i][4];
-                    dv[2] += self.b[stage - 1][prev_stage] * k[prev_stage][i][5];
+                // Compute weighted sum of previous k values to get intermediate state
+                for (i, body) in bodies.iter_mut().enumerate() {
+                    let mut delta = [0.0; 6];
+                    for (prev_stage, k_prev) in k.iter().enumerate().take(stage) {
+                        let weight = self.b[stage][prev_stage];
+                        for dim in 0..6 {
+                            delta[dim] += weight * k_prev[i][dim];
+                        }
+                    }
+
+                    for dim in 0..3 {
+                        body.position[dim] += delta[dim];
+                        body.velocity[dim] += delta[dim + 3];
+                    }
                 }
-                
-                bodies[i].position[0] += dx[0];
-                bodies[i].position[1] += dx[1];
-                bodies[i].position[2] += dx[2];
-                bodies[i].velocity[0] += dv[0];
-                bodies[i].velocity[1] += dv[1];
-                bodies[i].velocity[2] += dv[2];
             }
 
             // Compute derivatives at this stage
-            derivative_fn(bodies);
+            derivative_fn(self.c[stage] * dt, bodies);
 
             // Store k values
-            for i in 0..n {
-                k[stage][i][0] = dt * bodies[i].velocity[0];
-                k[stage][i][1] = dt * bodies[i].velocity[1];
-                k[stage][i][2] = dt * bodies[i].velocity[2];
-                k[stage][i][3] = dt * bodies[i].acceleration[0];
-                k[stage][i][4] = dt * bodies[i].acceleration[1];
-                k[stage][i][5] = dt * bodies[i].acceleration[2];
+            for (i, body) in bodies.iter().enumerate() {
+                for dim in 0..3 {
+                    k[stage][i][dim] = dt * body.velocity[dim];
+                    k[stage][i][dim + 3] = dt * body.acceleration[dim];
+                }
+                if stage == 0 {
+                    start_accelerations[i] = body.acceleration;
+                }
+                if self.c[stage] == 1.0 {
+                    end_accelerations[i] = body.acceleration;
+                }
             }
         }
 
         // Restore initial state
-        bodies.copy_from_slice(&initial_bodies);
-
-        // Apply 5th order solution
-        for i in 0..n {
-            for dim in 0..6 {
-                let mut update = 0.0;
-                for stage in 0..6 {
-                    update += self.b5[stage] * k[stage][i][dim];
-                }
+        bodies.clone_from_slice(&initial_bodies);
 
-                match dim {
-                    0 => bodies[i].position[0] += update,
-                    1 => bodies[i].position[1] += update,
-                    2 => bodies[i].position[2] += update,
-                    3 => bodies[i].velocity[0] += update,
-                    4 => bodies[i].velocity[1] += update,
-                    5 => bodies[i].velocity[2] += update,
-                    _ => {}
-                }
-            }
+        Stages {
+            k,
+            start_accelerations,
+            end_accelerations,
         }
+    }
+}
 
-        // Compute error estimate (difference between 5th and 4th order solutions)
-        let mut error_5th: f64 = 0.0;
-        let mut error_4th: f64 = 0.0;
-        for i in 0..n {
-            for dim in 0..6 {
-                let mut sol5 = 0.0;
-                let mut sol4 = 0.0;
-                for stage in 0..6 {
-                    sol5 += self.b5[stage] * k[stage][i][dim];
-                    sol4 += self.b4[stage] * k[stage][i][dim];
-                }
-                let diff = (sol5 - sol4).abs();
-                error_5th = error_5th.max(diff);
-                error_4th = error_4th.max(diff);
-            }
-        }
+impl Integrator for RungeKuttaFehlberg {
+    fn name(&self) -> &'static str {
+        "rkf45"
+    }
 
-        (error_5th, error_4th)
+    fn advance(
+        &mut self,
+        bodies: &mut [Body],
+        dt: f64,
+        derivative_fn: DerivativeFunction<'_>,
+    ) -> StepReport {
+        self.step_with_error(bodies, dt, derivative_fn)
     }
 }
 
@@ -191,4 +457,81 @@ mod tests {
         assert!((integrator.c[1] - 0.25).abs() < 1e-10);
         assert!((integrator.b5[0] - (16.0 / 135.0)).abs() < 1e-10);
     }
+
+    /// Harmonic oscillator a = -x, used to check the tableau against an exact solution
+    fn harmonic(_time: f64, bodies: &mut [Body]) {
+        for body in bodies.iter_mut() {
+            for dim in 0..3 {
+                body.acceleration[dim] = -body.position[dim];
+            }
+        }
+    }
+
+    #[test]
+    fn test_rkf45_step_accuracy() {
+        let integrator = RungeKuttaFehlberg::new();
+        let mut bodies = [Body::new(1.0, [1.0, 0.0, 0.0], [0.0, 1.0, 0.0])];
+        let dt = 0.1;
+        let errors = integrator.step_with_error(&mut bodies, dt, &mut harmonic).errors.unwrap();
+
+        // Exact solution: x = cos(t), vy = cos(t)
+        assert!((bodies[0].position[0] - dt.cos()).abs() < 1e-9);
+        assert!((bodies[0].position[1] - dt.sin()).abs() < 1e-9);
+        assert!((bodies[0].velocity[0] + dt.sin()).abs() < 1e-9);
+
+        // The embedded error estimate should be small but nonzero
+        let max_error = errors[0].iter().fold(0.0_f64, |acc, e| acc.max(e.abs()));
+        assert!(max_error > 0.0 && max_error < 1e-6);
+
+        let mut again = [Body::new(1.0, [1.0, 0.0, 0.0], [0.0, 1.0, 0.0])];
+        assert_eq!(integrator.step(&mut again, dt, &mut harmonic), (max_error, max_error));
+        assert_eq!(again[0].position, bodies[0].position);
+    }
+
+    #[test]
+    fn test_dense_output_interpolation() {
+        let integrator = RungeKuttaFehlberg::new();
+        // Largest interpolation errors in position and velocity over a step
+        let errors = |dt: f64| {
+            let start = Body::new(1.0, [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]);
+            let mut end = [start.clone()];
+            let report = integrator.step_with_error(&mut end, dt, &mut harmonic);
+            assert_eq!(report.start_accelerations[0], [-1.0, 0.0, 0.0]);
+            let dense = DenseOutput::new(
+                0.0,
+                dt,
+                vec![Body { acceleration: report.start_accelerations[0], ..start.clone() }],
+                vec![Body { acceleration: report.end_accelerations[0], ..end[0].clone() }],
+            );
+
+            // The interpolant meets the RKF45 solution at both ends
+            assert_eq!(dense.evaluate(0.0)[0].position, start.position);
+            assert_eq!(dense.evaluate(dt)[0].velocity, end[0].velocity);
+
+            let mut max = [0.0_f64; 2];
+            for i in 0..=10 {
+                let t = dt * i as f64 / 10.0;
+                let body = &dense.evaluate(t)[0];
+                max[0] = max[0].max((body.position[0] - t.cos()).abs()).max((body.position[1] - t.sin()).abs());
+                max[1] = max[1].max((body.velocity[0] + t.sin()).abs()).max((body.velocity[1] - t.cos()).abs());
+            }
+            max
+        };
+
+        let (coarse, fine) = (errors(0.25), errors(0.125));
+        assert!(coarse[0] < 1e-5 && coarse[1] < 1e-4, "{:?}", coarse);
+        // Fourth order in position and third in velocity within the step
+        assert!(coarse[0] / fine[0] > 25.0, "{:?} {:?}", coarse, fine);
+        assert!(coarse[1] / fine[1] > 12.0, "{:?} {:?}", coarse, fine);
+    }
+
+    #[test]
+    fn test_integrator_kind_names() {
+        for kind in IntegratorKind::ALL {
+            assert_eq!(kind.name().parse::<IntegratorKind>().unwrap(), kind);
+            assert_eq!(kind.build().name(), kind.name());
+        }
+        assert_eq!("WH".parse::<IntegratorKind>().unwrap(), IntegratorKind::WisdomHolman);
+        assert!("euler".parse::<IntegratorKind>().is_err());
+    }
 }
@@ -3,14 +3,57 @@
 //! This library provides generic functions for simulating N-body gravitational dynamics
 //! using the Runge-Kutta-Fehlberg (RKF45) method for adaptive time-stepping.
 
+// Component-wise loops over x, y, z index several arrays at once and
+// read more clearly with an index than zipped iterators
+#![allow(clippy::needless_range_loop)]
+
 pub mod integrator;
+pub mod symplectic;
 pub mod body;
 pub mod simulator;
 pub mod config;
 pub mod trajectory;
+pub mod binary;
+pub mod npy;
+pub mod output;
+pub mod checkpoint;
+pub mod forces;
+pub mod barnes_hut;
+pub mod softening;
+pub mod collision;
+pub mod force_model;
+pub mod relativity;
+pub mod units;
+pub mod diagnostics;
+pub mod frame;
+pub mod orbit;
+pub mod osculating;
+pub mod generators;
+pub mod cli;
+mod vector;
+#[cfg(test)]
+mod test_support;
 
-pub use integrator::RungeKuttaFehlberg;
+pub use integrator::{Integrator, RungeKuttaFehlberg};
+pub use symplectic::{Leapfrog, WisdomHolman, Yoshida4};
 pub use body::Body;
-pub use simulator::Simulator;
-pub use config::{SimulationConfig, parse_ini_file};
-pub use trajectory::{TrajectoryData, BodyTrajectory, Position};
+pub use simulator::{Simulator, AdaptiveSettings};
+pub use config::{
+    ConfigError, ConfigErrorKind, ParseMode, SimulationConfig, parse_ini_file,
+    parse_ini_file_with_mode, parse_ini_str,
+};
+pub use trajectory::{TrajectoryData, BodyTrajectory, Interpolation, Position, TrajectoryReader};
+pub use binary::TrajectoryHeader;
+pub use output::OutputColumns;
+pub use checkpoint::Checkpoint;
+pub use forces::ForceSolver;
+pub use softening::Softening;
+pub use collision::{CollisionEvent, CollisionMode, CollisionOutcome};
+pub use force_model::ForceModel;
+pub use relativity::PostNewtonian;
+pub use units::UnitSystem;
+pub use diagnostics::{Diagnostics, Drift, DriftTracker};
+pub use frame::OutputFrame;
+pub use orbit::{Anomaly, OrbitalElements};
+pub use osculating::{ElementHistory, ElementSeries};
+pub use generators::Scenario;
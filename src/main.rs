@@ -1,58 +1,455 @@
-use threebody_sim::{Simulator, parse_ini_file};
+use threebody_sim::{
+    CollisionMode, Diagnostics, OutputFrame, SimulationConfig, Simulator, Softening, TrajectoryData,
+    TrajectoryReader, UnitSystem, parse_ini_file_with_mode,
+};
+use threebody_sim::binary::is_trajectory_file;
+use threebody_sim::cli::{
+    self, CheckOptions, Command, ExportOptions, JsonObject, OutputMode, RunOptions, EXIT_FAILURE,
+    EXIT_INVALID_CONFIG, EXIT_SUCCESS, EXIT_USAGE,
+};
+use threebody_sim::frame::to_barycentric;
 use std::env;
 
-fn main() -> std::io::Result<()> {
-    let args: Vec<String> = env::args().collect();
+fn main() {
+    // See `threebody-sim --help`; the commands and options are parsed in
+    // `threebody_sim::cli`.
+    let code = match cli::parse_args(env::args().skip(1)) {
+        Ok(Command::Help(command)) => {
+            print!("{}", cli::help(command.as_deref()));
+            EXIT_SUCCESS
+        }
+        Ok(Command::Validate(options)) => finish(validate(&options), options.output_mode),
+        Ok(Command::Info(options)) => finish(info(&options), options.output_mode),
+        Ok(Command::Run(options)) => finish(run(&options), options.check.output_mode),
+        Ok(Command::Export(options)) => finish(export(&options), options.output_mode),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            eprintln!("Run 'threebody-sim --help' for usage");
+            EXIT_USAGE
+        }
+    };
+    std::process::exit(code);
+}
+
+/// Why a command failed, and the exit code to report it with
+struct Failure {
+    code: i32,
+    message: String,
+}
+
+impl Failure {
+    fn new(code: i32, message: String) -> Self {
+        Failure { code, message }
+    }
+}
+
+impl From<std::io::Error> for Failure {
+    fn from(e: std::io::Error) -> Self {
+        Failure::new(EXIT_FAILURE, e.to_string())
+    }
+}
+
+/// Report how a command ended and return its exit code
+fn finish(result: Result<(), Failure>, mode: OutputMode) -> i32 {
+    match result {
+        Ok(()) => EXIT_SUCCESS,
+        Err(failure) => {
+            eprintln!("Error: {}", failure.message);
+            if mode == OutputMode::Json {
+                let json = JsonObject::new()
+                    .string("status", "error")
+                    .integer("exit_code", failure.code as u64)
+                    .string("error", &failure.message);
+                println!("{}", json);
+            }
+            failure.code
+        }
+    }
+}
+
+/// Parse the configuration, printing any warnings to stderr
+fn load_config(options: &CheckOptions) -> Result<SimulationConfig, Failure> {
+    let config = parse_ini_file_with_mode(&options.config, options.parse_mode)
+        .map_err(|e| Failure::new(EXIT_INVALID_CONFIG, e.to_string()))?;
+    for warning in &config.warnings {
+        eprintln!("Warning: {}", warning);
+    }
+    Ok(config)
+}
+
+fn validate(options: &CheckOptions) -> Result<(), Failure> {
+    let config = load_config(options)?;
+    match options.output_mode {
+        OutputMode::Normal => println!(
+            "{}: valid, {} bodies, {} warnings",
+            options.config,
+            config.bodies.len(),
+            config.warnings.len()
+        ),
+        OutputMode::Quiet => {}
+        OutputMode::Json => {
+            let warnings = config.warnings.iter().map(|w| cli::json_string(&w.to_string()));
+            let json = JsonObject::new()
+                .string("status", "ok")
+                .string("config", &options.config)
+                .integer("bodies", config.bodies.len() as u64)
+                .raw("warnings", cli::json_array(warnings));
+            println!("{}", json);
+        }
+    }
+    Ok(())
+}
 
-    // Get config file path from command line argument
-    let config_file = if args.len() > 1 {
-        args[1].clone()
+fn info(options: &CheckOptions) -> Result<(), Failure> {
+    let config = load_config(options)?;
+    let diagnostics = Diagnostics::measure(0.0, &config.bodies, config.softening);
+    match options.output_mode {
+        OutputMode::Normal => {
+            println!("Configuration: {}", options.config);
+            print_bodies(&config);
+            match config.duration {
+                Some(duration) => println!(
+                    "Integrator: {}, time step {:.4e} s, duration {:.4e} s",
+                    config.integrator, config.time_step, duration
+                ),
+                None => println!(
+                    "Integrator: {}, time step {:.4e} s, {} steps",
+                    config.integrator, config.time_step, config.num_steps
+                ),
+            }
+            println!("Force solver: {}", config.force_solver);
+            if config.units != UnitSystem::Si {
+                println!("Input units: {} (values below are in SI)", config.units);
+            }
+            println!("\nKinetic energy:   {:.6e} J", diagnostics.kinetic_energy);
+            println!("Potential energy: {:.6e} J", diagnostics.potential_energy);
+            println!("Total energy:     {:.6e} J", diagnostics.total_energy);
+            println!("Momentum:         {}", format_vector(diagnostics.momentum));
+            println!("Angular momentum: {}", format_vector(diagnostics.angular_momentum));
+            println!("Centre of mass:   {}", format_vector(diagnostics.centre_of_mass));
+            println!("Virial ratio:     {:.6}", diagnostics.virial_ratio);
+        }
+        OutputMode::Quiet => {}
+        OutputMode::Json => {
+            let bodies = config.bodies.iter().enumerate().map(|(idx, body)| {
+                JsonObject::new()
+                    .string("name", &body.label(idx))
+                    .number("mass", body.mass)
+                    .vector("position", body.position)
+                    .vector("velocity", body.velocity)
+                    .number("kinetic_energy", body.kinetic_energy())
+                    .to_string()
+            });
+            let json = JsonObject::new()
+                .string("status", "ok")
+                .string("config", &options.config)
+                .string("integrator", config.integrator.name())
+                .number("time_step", config.time_step)
+                .integer("num_steps", config.num_steps as u64)
+                .raw("duration", config.duration.map_or(String::from("null"), cli::json_number))
+                .string("force_solver", &config.force_solver.to_string())
+                .raw("bodies", cli::json_array(bodies))
+                .number("kinetic_energy", diagnostics.kinetic_energy)
+                .number("potential_energy", diagnostics.potential_energy)
+                .number("total_energy", diagnostics.total_energy)
+                .vector("momentum", diagnostics.momentum)
+                .vector("angular_momentum", diagnostics.angular_momentum)
+                .vector("centre_of_mass", diagnostics.centre_of_mass)
+                .vector("centre_of_mass_velocity", diagnostics.centre_of_mass_velocity)
+                .number("virial_ratio", diagnostics.virial_ratio);
+            println!("{}", json);
+        }
+    }
+    Ok(())
+}
+
+fn export(options: &ExportOptions) -> Result<(), Failure> {
+    let reading = |e: std::io::Error| Failure::new(EXIT_FAILURE, format!("{}: {}", options.input, e));
+    let writing = |e: std::io::Error| Failure::new(EXIT_FAILURE, format!("{}: {}", options.output, e));
+    let (frames, bodies) = if is_trajectory_file(&options.input).map_err(reading)? {
+        let mut reader = TrajectoryReader::open(&options.input).map_err(reading)?;
+        reader.save_npz(&options.output).map_err(writing)?;
+        (reader.num_frames(), reader.header().names.len())
     } else {
-        String::from("./data/ic.ini")
+        let data = TrajectoryData::load_csv(&options.input).map_err(reading)?;
+        data.save_npz(&options.output).map_err(writing)?;
+        (data.num_frames, data.bodies.len())
     };
+    match options.output_mode {
+        OutputMode::Normal => println!(
+            "Exported {} frames of {} bodies from {} to {}",
+            frames, bodies, options.input, options.output
+        ),
+        OutputMode::Quiet => {}
+        OutputMode::Json => {
+            let json = JsonObject::new()
+                .string("status", "ok")
+                .string("input", &options.input)
+                .string("output", &options.output)
+                .integer("frames", frames as u64)
+                .integer("bodies", bodies as u64);
+            println!("{}", json);
+        }
+    }
+    Ok(())
+}
 
-    // Get output file from command line or use default
-    let output_file = if args.len() > 2 {
-        args[2].clone()
-    } else {
-        String::from("./data/results.csv")
+fn run(options: &RunOptions) -> Result<(), Failure> {
+    // Only the normal mode narrates the run
+    let verbose = options.check.output_mode == OutputMode::Normal;
+    macro_rules! report {
+        ($($arg:tt)*) => {
+            if verbose {
+                println!($($arg)*);
+            }
+        };
+    }
+
+    // A resumed run keeps the frame it was started with and updates the
+    // same checkpoint unless another one is given
+    let checkpoint = options.checkpoint.clone().or_else(|| options.resume.clone());
+
+    report!("Loading configuration from: {}", options.check.config);
+    let mut config = load_config(&options.check)?;
+    options.apply(&mut config);
+    if config.adaptive.is_some() && !config.integrator.has_error_estimate() {
+        return Err(Failure::new(
+            EXIT_USAGE,
+            format!(
+                "--integrator {}: the configuration uses adaptive stepping, which needs an error estimate",
+                config.integrator
+            ),
+        ));
+    }
+    if options.barycentric && !config.barycentric {
+        to_barycentric(&mut config.bodies);
+        config.barycentric = true;
+    }
+    if let Some(ref name) = options.centre_on {
+        if !config.bodies.iter().any(|body| &body.name == name) {
+            return Err(Failure::new(EXIT_USAGE, format!("--centre-on: no body named '{}'", name)));
+        }
+        config.output_frame = OutputFrame::Body(name.clone());
+    }
+
+    let primary = elements_primary(&config);
+    let output_file = options.output.clone().unwrap_or_else(|| config.output_file.clone());
+
+    if verbose {
+        print_bodies(&config);
+    }
+
+    let mut sim = match options.resume {
+        Some(ref path) => {
+            // The checkpoint, not the config, decides the bodies and stepping
+            let mut sim = Simulator::load_checkpoint(path)?;
+            sim.append_output(&output_file)?;
+            if let Some(ref binary_file) = config.binary_file {
+                sim.append_binary_output(binary_file)?;
+            }
+            if let Some(ref diagnostics_file) = config.diagnostics_file {
+                sim.append_diagnostics(diagnostics_file)?;
+            }
+            if let Some(ref elements_file) = config.elements_file {
+                sim.append_elements_output(elements_file, &primary)?;
+            }
+            report!(
+                "\nResuming {} simulator from {} at t = {:.2} s after {} steps",
+                sim.integrator_name(),
+                path,
+                sim.time(),
+                sim.accepted_steps()
+            );
+            sim
+        }
+        None => {
+            // Create simulator with the configuration
+            match config.duration {
+                Some(duration) => report!(
+                    "\nCreating {} simulator with time step: {:.2} s, duration {:.2} s",
+                    config.integrator, config.time_step, duration
+                ),
+                None => report!(
+                    "\nCreating {} simulator with time step: {:.2} s, {} steps",
+                    config.integrator, config.time_step, config.num_steps
+                ),
+            }
+            report!("Force solver: {}", config.force_solver);
+            if config.units != UnitSystem::Si {
+                report!("Input units: {}", config.units);
+            }
+            if config.output_units != UnitSystem::Si {
+                report!("Output units: {}", config.output_units);
+            }
+            if config.softening != Softening::None {
+                report!("Softening: {}", config.softening);
+            }
+            if config.post_newtonian {
+                report!("Relativity: 1PN corrections");
+            }
+            if config.collision_mode != CollisionMode::Ignore {
+                report!("Collisions: {}", config.collision_mode);
+            }
+            if config.barycentric {
+                report!("Initial conditions shifted to the centre-of-mass frame");
+            }
+            if config.output_frame != OutputFrame::Inertial {
+                report!("Output frame: {}", config.output_frame);
+            }
+            let mut sim = Simulator::with_output(config.bodies, config.time_step, &output_file)?;
+            sim.set_integrator(config.integrator.build());
+            sim.set_force_solver(config.force_solver);
+            sim.set_softening(config.softening);
+            sim.set_post_newtonian(config.post_newtonian);
+            sim.set_collision_mode(config.collision_mode);
+            sim.set_adaptive(config.adaptive)
+                .map_err(|message| Failure::new(EXIT_INVALID_CONFIG, format!("adaptive stepping: {}", message)))?;
+            sim.set_output_interval(config.output_interval);
+            sim.set_output_columns(config.output_columns);
+            sim.set_output_units(config.output_units);
+            sim.set_output_frame(config.output_frame.clone());
+            if let Some(ref binary_file) = config.binary_file {
+                sim.enable_binary_output(binary_file)?;
+            }
+            if let Some(ref diagnostics_file) = config.diagnostics_file {
+                sim.enable_diagnostics(diagnostics_file)?;
+            }
+            if let Some(ref elements_file) = config.elements_file {
+                sim.enable_elements_output(elements_file, &primary)?;
+            }
+            sim
+        }
     };
 
-    println!("Loading configuration from: {}", config_file);
-    
-    // Parse the configuration file
-    let config = parse_ini_file(&config_file)?;
-    
+    let initial_energy = sim.total_energy();
+    if verbose {
+        println!("\nInitial state:");
+        sim.print_positions();
+    }
+    report!("Initial total energy: {:.6e} J", initial_energy);
+
+    // Run the simulation in batches, checkpointing between them. A step
+    // count is the total since the start, so a resumed run only does the rest.
+    let remaining_steps = config.num_steps.saturating_sub(sim.accepted_steps());
+    match config.duration {
+        Some(duration) => report!("\nRunning simulation until {:.2} s...", duration),
+        None => report!("\nRunning simulation for {} steps...", remaining_steps),
+    }
+    let mut steps_left = remaining_steps;
+    loop {
+        let finished = match config.duration {
+            Some(duration) => sim.run_until_bounded(duration, options.checkpoint_every),
+            None => {
+                let batch = steps_left.min(options.checkpoint_every);
+                sim.run(batch);
+                steps_left -= batch;
+                steps_left == 0 || sim.is_stopped()
+            }
+        };
+        if let Some(ref path) = checkpoint {
+            sim.save_checkpoint(path)?;
+        }
+        if finished {
+            break;
+        }
+    }
+    for event in sim.collisions() {
+        report!("Collision: {}", event);
+    }
+    if sim.is_stopped() {
+        report!("Stopped by a collision at t = {:.2} s", sim.time());
+    }
+    if sim.adaptive().is_some() {
+        report!(
+            "Adaptive stepping: {} accepted, {} rejected steps",
+            sim.accepted_steps(),
+            sim.rejected_steps()
+        );
+    }
+
+    let final_energy = sim.total_energy();
+    if verbose {
+        println!("\nFinal state:");
+        sim.print_positions();
+    }
+    report!("Final total energy: {:.6e} J", final_energy);
+    if let Some(tracker) = sim.drift_tracker() {
+        let drift = tracker.max_drift();
+        report!(
+            "Largest relative drift: energy {:.3e}, momentum {:.3e}, angular momentum {:.3e}",
+            drift.energy, drift.momentum, drift.angular_momentum
+        );
+    }
+
+    report!("Simulation time elapsed: {:.2} days", sim.time() / 86400.0);
+    report!("\nResults saved to: {}", output_file);
+    if let Some(ref binary_file) = config.binary_file {
+        report!("Binary trajectory saved to: {}", binary_file);
+    }
+    if let Some(ref diagnostics_file) = config.diagnostics_file {
+        report!("Diagnostics saved to: {}", diagnostics_file);
+    }
+    if let Some(ref elements_file) = config.elements_file {
+        report!("Orbital elements around {} saved to: {}", primary, elements_file);
+    }
+    if let Some(ref path) = checkpoint {
+        report!("Checkpoint saved to: {}", path);
+    }
+
+    if options.check.output_mode == OutputMode::Json {
+        let collisions = sim.collisions().iter().map(|event| cli::json_string(&event.to_string()));
+        let json = JsonObject::new()
+            .string("status", "ok")
+            .string("config", &options.check.config)
+            .string("integrator", sim.integrator_name())
+            .number("time", sim.time())
+            .integer("accepted_steps", sim.accepted_steps() as u64)
+            .integer("rejected_steps", sim.rejected_steps() as u64)
+            .number("initial_energy", initial_energy)
+            .number("final_energy", final_energy)
+            .number("energy_error", ((final_energy - initial_energy) / initial_energy).abs())
+            .raw("collisions", cli::json_array(collisions))
+            .boolean("stopped", sim.is_stopped())
+            .string("output_file", &output_file)
+            .optional_string("binary_file", config.binary_file.as_deref())
+            .optional_string("diagnostics_file", config.diagnostics_file.as_deref())
+            .optional_string("elements_file", config.elements_file.as_deref())
+            .optional_string("checkpoint", checkpoint.as_deref());
+        println!("{}", json);
+    }
+
+    Ok(())
+}
+
+/// Print the configuration's bodies, in SI
+fn print_bodies(config: &SimulationConfig) {
     println!("Loaded {} bodies", config.bodies.len());
     for (idx, body) in config.bodies.iter().enumerate() {
         println!(
-            "  Body {}: mass={:.4e}, pos=[{:.4e}, {:.4e}, {:.4e}], vel=[{:.4e}, {:.4e}, {:.4e}]",
-            idx,
+            "  {}: mass={:.4e}, pos=[{:.4e}, {:.4e}, {:.4e}], vel=[{:.4e}, {:.4e}, {:.4e}]",
+            body.label(idx),
             body.mass,
             body.position[0], body.position[1], body.position[2],
             body.velocity[0], body.velocity[1], body.velocity[2]
         );
     }
+}
 
-    // Create simulator with the configuration
-    println!("\nCreating simulator with time step: {:.2} s, {} steps", 
-             config.time_step, config.num_steps);
-    let mut sim = Simulator::with_output(config.bodies, config.time_step, &output_file)?;
-
-    println!("\nInitial state:");
-    sim.print_positions();
-    println!("Initial total energy: {:.6e} J", sim.total_energy());
-
-    // Run the simulation
-    println!("\nRunning simulation for {} steps...", config.num_steps);
-    sim.run(config.num_steps);
-
-    println!("\nFinal state:");
-    sim.print_positions();
-    println!("Final total energy: {:.6e} J", sim.total_energy());
-    
-    println!("Simulation time elapsed: {:.2} days", sim.time() / 86400.0);
-    println!("\nResults saved to: {}", output_file);
+fn format_vector(v: [f64; 3]) -> String {
+    format!("[{:.6e}, {:.6e}, {:.6e}]", v[0], v[1], v[2])
+}
 
-    Ok(())
+/// Name of the body orbital elements are taken around: the configured one,
+/// or else the most massive body
+fn elements_primary(config: &SimulationConfig) -> String {
+    if let Some(ref name) = config.elements_primary {
+        return name.clone();
+    }
+    let (idx, body) = config
+        .bodies
+        .iter()
+        .enumerate()
+        .max_by(|(_, a), (_, b)| a.mass.total_cmp(&b.mass))
+        .expect("configurations have at least one body");
+    body.label(idx)
 }
@@ -0,0 +1,356 @@
+//! NumPy `.npy` and `.npz` export
+//!
+//! Lets Python (`numpy.load`) and Julia (`NPZ.jl`) read trajectories
+//! directly, without a CSV parser. An `.npy` file is a short text header
+//! giving the dtype and shape followed by the raw array in C order; an
+//! `.npz` file is a ZIP archive of `.npy` files, one per named array.
+//! Arrays are written as little-endian `float64` (`<f8`) and strings as
+//! fixed-width unicode (`<U`), neither of which needs pickling to load.
+//!
+//! Archives are stored without compression and without ZIP64, so each
+//! array and the whole archive must stay under 4 GiB.
+
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// First bytes of every `.npy` file
+const NPY_MAGIC: &[u8; 6] = b"\x93NUMPY";
+
+/// `.npy` headers are padded so the data starts on this alignment
+const NPY_ALIGNMENT: usize = 64;
+
+/// Date field of every ZIP entry: 1 January 1980, the earliest there is
+const ZIP_DATE: u16 = (1 << 5) | 1;
+
+/// Header of a version 1.0 `.npy` file holding an array of `descr` values
+fn npy_header(descr: &str, shape: &[usize]) -> Vec<u8> {
+    let shape = match shape {
+        [n] => format!("({},)", n),
+        _ => format!("({})", shape.iter().map(|n| n.to_string()).collect::<Vec<_>>().join(", ")),
+    };
+    let mut dict = format!("{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}", descr, shape);
+    // Magic, version and header length take 10 bytes; the dict ends in a newline
+    let unpadded = 10 + dict.len() + 1;
+    dict.push_str(&" ".repeat((NPY_ALIGNMENT - unpadded % NPY_ALIGNMENT) % NPY_ALIGNMENT));
+    dict.push('\n');
+
+    let mut header = Vec::with_capacity(10 + dict.len());
+    header.extend(NPY_MAGIC);
+    header.extend([1, 0]);
+    header.extend((dict.len() as u16).to_le_bytes());
+    header.extend(dict.as_bytes());
+    header
+}
+
+/// A `float64` array in `.npy` format
+fn npy_f64(shape: &[usize], values: &[f64]) -> std::io::Result<Vec<u8>> {
+    check_len(shape, values.len())?;
+    let mut bytes = npy_header("<f8", shape);
+    bytes.reserve(8 * values.len());
+    for value in values {
+        bytes.extend(value.to_le_bytes());
+    }
+    Ok(bytes)
+}
+
+/// A unicode string array in `.npy` format
+fn npy_strings(shape: &[usize], values: &[String]) -> Vec<u8> {
+    let width = values.iter().map(|value| value.chars().count()).max().unwrap_or(0).max(1);
+    let mut bytes = npy_header(&format!("<U{}", width), shape);
+    for value in values {
+        let chars: Vec<char> = value.chars().collect();
+        for idx in 0..width {
+            let code = chars.get(idx).map_or(0, |&ch| ch as u32);
+            bytes.extend(code.to_le_bytes());
+        }
+    }
+    bytes
+}
+
+fn check_len(shape: &[usize], len: usize) -> std::io::Result<()> {
+    let expected: usize = shape.iter().product();
+    if expected != len {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{} values do not fill an array of shape {:?}", len, shape),
+        ));
+    }
+    Ok(())
+}
+
+/// Write a `float64` array of the given shape, in C order, to an `.npy` file
+pub fn save_npy<P: AsRef<Path>>(path: P, shape: &[usize], values: &[f64]) -> std::io::Result<()> {
+    std::fs::write(path, npy_f64(shape, values)?)
+}
+
+/// CRC-32 lookup table (IEEE polynomial, reflected), as used by ZIP
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 { 0xEDB8_8320 ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+};
+
+/// Continue a CRC-32 over more bytes; start from 0
+fn crc32(crc: u32, bytes: &[u8]) -> u32 {
+    let mut c = !crc;
+    for &byte in bytes {
+        c = CRC_TABLE[((c ^ byte as u32) & 0xFF) as usize] ^ (c >> 8);
+    }
+    !c
+}
+
+/// A ZIP entry already written, for the central directory
+struct Entry {
+    name: String,
+    crc: u32,
+    size: u32,
+    offset: u32,
+}
+
+/// Writes named arrays into an `.npz` archive
+///
+/// Arrays can be given whole or streamed value by value with
+/// `write_f64_with`, so an array larger than memory can be exported from
+/// a file. `finish` must be called to complete the archive, and an
+/// archive in which writing an array failed cannot be completed.
+pub struct NpzWriter<W: Write + Seek> {
+    writer: W,
+    entries: Vec<Entry>,
+    position: u64,
+}
+
+impl NpzWriter<BufWriter<File>> {
+    /// Create an `.npz` file
+    pub fn create<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        Ok(NpzWriter::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write + Seek> NpzWriter<W> {
+    pub fn new(writer: W) -> Self {
+        NpzWriter {
+            writer,
+            entries: Vec::new(),
+            position: 0,
+        }
+    }
+
+    /// Add a `float64` array of the given shape, in C order
+    pub fn write_f64(&mut self, name: &str, shape: &[usize], values: &[f64]) -> std::io::Result<()> {
+        let bytes = npy_f64(shape, values)?;
+        self.write_entry(name, |entry| entry.write_bytes(&bytes))
+    }
+
+    /// Add a `float64` array of the given shape whose values, in C order,
+    /// are pushed one at a time by `fill`
+    pub fn write_f64_with<F>(&mut self, name: &str, shape: &[usize], fill: F) -> std::io::Result<()>
+    where
+        F: FnOnce(&mut ArrayWriter<'_, W>) -> std::io::Result<()>,
+    {
+        let header = npy_header("<f8", shape);
+        self.write_entry(name, |array| {
+            array.write_bytes(&header)?;
+            fill(array)?;
+            check_len(shape, array.count)
+        })
+    }
+
+    /// Add a one-dimensional array of strings
+    pub fn write_strings(&mut self, name: &str, values: &[String]) -> std::io::Result<()> {
+        let bytes = npy_strings(&[values.len()], values);
+        self.write_entry(name, |entry| entry.write_bytes(&bytes))
+    }
+
+    /// Add a single string, as a zero-dimensional array
+    pub fn write_string(&mut self, name: &str, value: &str) -> std::io::Result<()> {
+        let bytes = npy_strings(&[], &[value.to_string()]);
+        self.write_entry(name, |entry| entry.write_bytes(&bytes))
+    }
+
+    /// Write the ZIP local header for `<name>.npy`, then its data, then go
+    /// back to fill in the checksum and size
+    fn write_entry<F>(&mut self, name: &str, write: F) -> std::io::Result<()>
+    where
+        F: FnOnce(&mut ArrayWriter<'_, W>) -> std::io::Result<()>,
+    {
+        let name = format!("{}.npy", name);
+        let offset = zip_u32(self.position)?;
+        let mut header = Vec::with_capacity(30 + name.len());
+        header.extend(0x0403_4b50u32.to_le_bytes());
+        for field in [20, 0, 0, 0, ZIP_DATE] {
+            header.extend(u16::to_le_bytes(field));
+        }
+        // Checksum and sizes, filled in once the data is written
+        header.extend([0u8; 12]);
+        header.extend((name.len() as u16).to_le_bytes());
+        header.extend(0u16.to_le_bytes());
+        header.extend(name.as_bytes());
+        self.writer.write_all(&header)?;
+
+        let mut entry = ArrayWriter {
+            writer: &mut self.writer,
+            crc: 0,
+            size: 0,
+            count: 0,
+        };
+        write(&mut entry)?;
+        let (crc, size) = (entry.crc, zip_u32(entry.size)?);
+
+        self.writer.seek(SeekFrom::Start(offset as u64 + 14))?;
+        let mut fields = Vec::with_capacity(12);
+        fields.extend(crc.to_le_bytes());
+        fields.extend(size.to_le_bytes());
+        fields.extend(size.to_le_bytes());
+        self.writer.write_all(&fields)?;
+        self.writer.seek(SeekFrom::End(0))?;
+
+        self.position += header.len() as u64 + size as u64;
+        self.entries.push(Entry { name, crc, size, offset });
+        Ok(())
+    }
+
+    /// Write the central directory, completing the archive
+    pub fn finish(mut self) -> std::io::Result<W> {
+        let directory_offset = zip_u32(self.position)?;
+        let mut directory = Vec::new();
+        for entry in &self.entries {
+            directory.extend(0x0201_4b50u32.to_le_bytes());
+            for field in [20, 20, 0, 0, 0, ZIP_DATE] {
+                directory.extend(u16::to_le_bytes(field));
+            }
+            directory.extend(entry.crc.to_le_bytes());
+            directory.extend(entry.size.to_le_bytes());
+            directory.extend(entry.size.to_le_bytes());
+            directory.extend((entry.name.len() as u16).to_le_bytes());
+            // Extra field and comment lengths, disk number, internal attributes
+            directory.extend([0u8; 8]);
+            directory.extend(0u32.to_le_bytes());
+            directory.extend(entry.offset.to_le_bytes());
+            directory.extend(entry.name.as_bytes());
+        }
+        let count = self.entries.len() as u16;
+        let mut end = Vec::with_capacity(22);
+        end.extend(0x0605_4b50u32.to_le_bytes());
+        for field in [0, 0, count, count] {
+            end.extend(u16::to_le_bytes(field));
+        }
+        end.extend((directory.len() as u32).to_le_bytes());
+        end.extend(directory_offset.to_le_bytes());
+        end.extend(0u16.to_le_bytes());
+
+        self.writer.write_all(&directory)?;
+        self.writer.write_all(&end)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// An offset or size as the 32-bit field ZIP has for it without ZIP64
+fn zip_u32(value: u64) -> std::io::Result<u32> {
+    u32::try_from(value).map_err(|_| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "arrays too large for an .npz file; write .npy files instead",
+        )
+    })
+}
+
+/// Receives the data of an array streamed into an `.npz` archive
+pub struct ArrayWriter<'a, W: Write> {
+    writer: &'a mut W,
+    crc: u32,
+    size: u64,
+    /// Values pushed so far
+    count: usize,
+}
+
+impl<W: Write> ArrayWriter<'_, W> {
+    /// Append the next value, in C order
+    pub fn push(&mut self, value: f64) -> std::io::Result<()> {
+        self.count += 1;
+        self.write_bytes(&value.to_le_bytes())
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        self.writer.write_all(bytes)?;
+        self.crc = crc32(self.crc, bytes);
+        self.size += bytes.len() as u64;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_npy_header() {
+        let header = npy_header("<f8", &[4, 2, 3]);
+        assert_eq!(header.len() % NPY_ALIGNMENT, 0);
+        assert_eq!(&header[..8], b"\x93NUMPY\x01\x00");
+        let text = std::str::from_utf8(&header[10..]).unwrap();
+        assert!(text.starts_with("{'descr': '<f8', 'fortran_order': False, 'shape': (4, 2, 3), }"));
+        assert!(text.ends_with(" \n"));
+        assert!(std::str::from_utf8(&npy_header("<U5", &[7])[10..]).unwrap().contains("'shape': (7,)"));
+        assert!(std::str::from_utf8(&npy_header("<U2", &[])[10..]).unwrap().contains("'shape': ()"));
+
+        let bytes = npy_strings(&[2], &[String::from("Sun"), String::from("Io")]);
+        let data = npy_header("<U3", &[2]).len();
+        assert!(std::str::from_utf8(&bytes[10..data]).unwrap().contains("'<U3'"));
+        assert_eq!(&bytes[data..data + 4], [b'S', 0, 0, 0]);
+        assert_eq!(bytes.len(), data + 2 * 3 * 4);
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(0, b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(crc32(0, b"12345"), b"6789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_npz_archive() {
+        let mut npz = NpzWriter::new(Cursor::new(Vec::new()));
+        npz.write_f64("time", &[3], &[0.0, 1.0, 2.0]).unwrap();
+        npz.write_f64_with("positions", &[2, 3], |array| {
+            (0..6).try_for_each(|k| array.push(k as f64))
+        })
+        .unwrap();
+        npz.write_strings("names", &[String::from("A")]).unwrap();
+        let bytes = npz.finish().unwrap().into_inner();
+
+        // The end record points at a central directory listing every array
+        let end = &bytes[bytes.len() - 22..];
+        assert_eq!(&end[..4], 0x0605_4b50u32.to_le_bytes());
+        let count = u16::from_le_bytes([end[10], end[11]]);
+        let directory = u32::from_le_bytes(end[16..20].try_into().unwrap()) as usize;
+        assert_eq!(&bytes[directory..directory + 4], 0x0201_4b50u32.to_le_bytes());
+        assert_eq!(count, 3);
+
+        // The second local header has its checksum and size filled in
+        let first_size = u32::from_le_bytes(bytes[18..22].try_into().unwrap()) as usize;
+        let second = 30 + "time.npy".len() + first_size;
+        assert_eq!(&bytes[second..second + 4], 0x0403_4b50u32.to_le_bytes());
+        let size = u32::from_le_bytes(bytes[second + 18..second + 22].try_into().unwrap()) as usize;
+        assert_eq!(size, npy_header("<f8", &[2, 3]).len() + 6 * 8);
+        let data = second + 30 + "positions.npy".len();
+        let crc = u32::from_le_bytes(bytes[second + 14..second + 18].try_into().unwrap());
+        assert_eq!(crc, crc32(0, &bytes[data..data + size]));
+        assert_eq!(f64::from_le_bytes(bytes[data + size - 8..data + size].try_into().unwrap()), 5.0);
+
+        let mut npz = NpzWriter::new(Cursor::new(Vec::new()));
+        assert!(npz.write_f64("short", &[4], &[1.0]).is_err());
+        assert!(npz.write_f64_with("short", &[4], |array| array.push(1.0)).is_err());
+    }
+}
@@ -0,0 +1,281 @@
+//! Keplerian orbital elements
+//!
+//! A bound two-body orbit is described by six elements: the semi-major axis
+//! a, the eccentricity e, the inclination i, the longitude of the ascending
+//! node Ω, the argument of periapsis ω and an anomaly placing the body
+//! along its orbit. Angles are in radians and measured against the x-y
+//! plane, with the node measured from the x axis.
+//!
+//! `OrbitalElements::to_state_vectors` turns a set of elements into a
+//! position and velocity relative to the parent body, for the gravitational
+//! parameter μ = G (M + m) of the pair, and
+//! `OrbitalElements::from_state_vectors` gives the osculating elements of a
+//! relative state: the orbit the body would follow if every other force
+//! vanished at that moment.
+
+use crate::vector::{cross, dot, norm, scale};
+use std::f64::consts::PI;
+
+/// Where a body is along its orbit
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Anomaly {
+    /// Angle from periapsis to the body, seen from the parent
+    True(f64),
+    /// Fraction of the period since periapsis, as an angle
+    Mean(f64),
+}
+
+/// The elements of a Keplerian orbit
+///
+/// Orbits built from elements are elliptical. Osculating elements of an
+/// unbound state have an eccentricity of 1 or more and a negative (or
+/// infinite) semi-major axis.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OrbitalElements {
+    pub semi_major_axis: f64,
+    /// In [0, 1) for a bound orbit
+    pub eccentricity: f64,
+    pub inclination: f64,
+    /// Longitude of the ascending node
+    pub ascending_node: f64,
+    pub argument_of_periapsis: f64,
+    pub anomaly: Anomaly,
+}
+
+impl OrbitalElements {
+    /// The osculating elements of a position and velocity relative to the
+    /// parent, for gravitational parameter `mu`
+    ///
+    /// Angles come out in [0, 2π) with a true anomaly. The node is
+    /// undefined for an orbit in the x-y plane and is then 0; likewise the
+    /// argument of periapsis of a circular orbit, whose anomaly is then
+    /// measured from the node.
+    pub fn from_state_vectors(position: [f64; 3], velocity: [f64; 3], mu: f64) -> Self {
+        let r = norm(position);
+        let v2 = dot(velocity, velocity);
+        let h = cross(position, velocity);
+        let h_norm = norm(h);
+        let h_hat = scale(h, 1.0 / h_norm);
+
+        let radial_speed = dot(position, velocity);
+        let mut e_vector = [0.0; 3];
+        for k in 0..3 {
+            e_vector[k] = ((v2 - mu / r) * position[k] - radial_speed * velocity[k]) / mu;
+        }
+        let eccentricity = norm(e_vector);
+        let semi_major_axis = 1.0 / (2.0 / r - v2 / mu);
+
+        // Towards the ascending node, or along x for an equatorial orbit
+        let node = [-h[1], h[0], 0.0];
+        let node_hat = if norm(node) > ANGLE_EPSILON * h_norm {
+            scale(node, 1.0 / norm(node))
+        } else {
+            [1.0, 0.0, 0.0]
+        };
+        // The signed angle from a to b about the orbit normal
+        let angle = |a: [f64; 3], b: [f64; 3]| dot(cross(a, b), h_hat).atan2(dot(a, b));
+
+        let r_hat = scale(position, 1.0 / r);
+        let (argument_of_periapsis, true_anomaly) = if eccentricity > ANGLE_EPSILON {
+            let e_hat = scale(e_vector, 1.0 / eccentricity);
+            (angle(node_hat, e_hat), angle(e_hat, r_hat))
+        } else {
+            (0.0, angle(node_hat, r_hat))
+        };
+
+        OrbitalElements {
+            semi_major_axis,
+            eccentricity,
+            inclination: (h[2] / h_norm).clamp(-1.0, 1.0).acos(),
+            ascending_node: wrap_angle(node_hat[1].atan2(node_hat[0])),
+            argument_of_periapsis: wrap_angle(argument_of_periapsis),
+            anomaly: Anomaly::True(wrap_angle(true_anomaly)),
+        }
+    }
+
+    /// Longitude of periapsis Ω + ω, in [0, 2π)
+    ///
+    /// Unlike the argument of periapsis it stays meaningful for orbits
+    /// close to the x-y plane, which makes it the angle to follow when
+    /// measuring apsidal precession.
+    pub fn longitude_of_periapsis(&self) -> f64 {
+        wrap_angle(self.ascending_node + self.argument_of_periapsis)
+    }
+
+    /// The true anomaly, solving Kepler's equation for a mean anomaly
+    pub fn true_anomaly(&self) -> f64 {
+        match self.anomaly {
+            Anomaly::True(nu) => nu,
+            Anomaly::Mean(mean) => {
+                let e = self.eccentricity;
+                let big_e = eccentric_anomaly(mean, e);
+                2.0 * ((1.0 + e).sqrt() * (big_e / 2.0).sin())
+                    .atan2((1.0 - e).sqrt() * (big_e / 2.0).cos())
+            }
+        }
+    }
+
+    /// Position and velocity relative to the parent for gravitational
+    /// parameter `mu`
+    pub fn to_state_vectors(&self, mu: f64) -> ([f64; 3], [f64; 3]) {
+        let e = self.eccentricity;
+        let nu = self.true_anomaly();
+        let p = self.semi_major_axis * (1.0 - e * e);
+        let r = p / (1.0 + e * nu.cos());
+        let speed = (mu / p).sqrt();
+
+        // Position and velocity in the orbital plane, x towards periapsis
+        let plane_position = [r * nu.cos(), r * nu.sin()];
+        let plane_velocity = [-speed * nu.sin(), speed * (e + nu.cos())];
+
+        // Unit vectors towards periapsis and 90 degrees ahead of it
+        let (sin_node, cos_node) = self.ascending_node.sin_cos();
+        let (sin_peri, cos_peri) = self.argument_of_periapsis.sin_cos();
+        let (sin_inc, cos_inc) = self.inclination.sin_cos();
+        let p_hat = [
+            cos_node * cos_peri - sin_node * sin_peri * cos_inc,
+            sin_node * cos_peri + cos_node * sin_peri * cos_inc,
+            sin_peri * sin_inc,
+        ];
+        let q_hat = [
+            -cos_node * sin_peri - sin_node * cos_peri * cos_inc,
+            -sin_node * sin_peri + cos_node * cos_peri * cos_inc,
+            cos_peri * sin_inc,
+        ];
+
+        let mut position = [0.0; 3];
+        let mut velocity = [0.0; 3];
+        for k in 0..3 {
+            position[k] = plane_position[0] * p_hat[k] + plane_position[1] * q_hat[k];
+            velocity[k] = plane_velocity[0] * p_hat[k] + plane_velocity[1] * q_hat[k];
+        }
+        (position, velocity)
+    }
+}
+
+/// Relative size below which an eccentricity or node vector counts as zero
+const ANGLE_EPSILON: f64 = 1e-12;
+
+/// An angle reduced to [0, 2π)
+fn wrap_angle(angle: f64) -> f64 {
+    let wrapped = angle.rem_euclid(2.0 * PI);
+    // rem_euclid can round up to 2π itself for tiny negative angles
+    if wrapped >= 2.0 * PI {
+        0.0
+    } else {
+        wrapped
+    }
+}
+
+/// Solve Kepler's equation M = E - e sin E for the eccentric anomaly
+pub fn eccentric_anomaly(mean_anomaly: f64, eccentricity: f64) -> f64 {
+    // Reduce to (-pi, pi], where the starting guess below converges
+    let mean = mean_anomaly - 2.0 * PI * ((mean_anomaly + PI) / (2.0 * PI)).floor();
+    let mut big_e = if eccentricity < 0.8 {
+        mean
+    } else {
+        PI.copysign(mean)
+    };
+    for _ in 0..50 {
+        let delta =
+            (big_e - eccentricity * big_e.sin() - mean) / (1.0 - eccentricity * big_e.cos());
+        big_e -= delta;
+        if delta.abs() < 1e-15 {
+            break;
+        }
+    }
+    big_e
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn elements(e: f64, anomaly: Anomaly) -> OrbitalElements {
+        OrbitalElements {
+            semi_major_axis: 2.0,
+            eccentricity: e,
+            inclination: 0.0,
+            ascending_node: 0.0,
+            argument_of_periapsis: 0.0,
+            anomaly,
+        }
+    }
+
+    #[test]
+    fn test_periapsis_and_apoapsis() {
+        let (r, v) = elements(0.5, Anomaly::True(0.0)).to_state_vectors(1.0);
+        assert!((r[0] - 1.0).abs() < 1e-12);
+        // vis-viva: v^2 = mu (2/r - 1/a)
+        assert!((v[1] - 1.5f64.sqrt()).abs() < 1e-12);
+
+        let (r, v) = elements(0.5, Anomaly::Mean(PI)).to_state_vectors(1.0);
+        assert!((r[0] + 3.0).abs() < 1e-12);
+        assert!((v[1] + (1.0f64 / 6.0).sqrt()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_kepler_equation() {
+        for &e in &[0.0, 0.3, 0.95] {
+            for &mean in &[-7.0, 0.1, 2.5, 3.1, 10.0] {
+                let big_e = eccentric_anomaly(mean, e);
+                let residual = big_e - e * big_e.sin() - mean;
+                let wrapped = residual - 2.0 * PI * (residual / (2.0 * PI)).round();
+                assert!(wrapped.abs() < 1e-12, "e = {}, M = {}", e, mean);
+            }
+        }
+    }
+
+    #[test]
+    fn test_orientation_preserves_energy_and_angular_momentum() {
+        let mu = 3.0;
+        let orbit = OrbitalElements {
+            semi_major_axis: 5.0,
+            eccentricity: 0.4,
+            inclination: 0.7,
+            ascending_node: 2.1,
+            argument_of_periapsis: -1.3,
+            anomaly: Anomaly::Mean(0.9),
+        };
+        let (r, v) = orbit.to_state_vectors(mu);
+        let energy = 0.5 * norm(v).powi(2) - mu / norm(r);
+        assert!((energy + mu / 10.0).abs() < 1e-12);
+
+        let h = cross(r, v);
+        assert!((norm(h) - (mu * 5.0 * (1.0 - 0.16)).sqrt()).abs() < 1e-12);
+        // The orbit normal is tilted by the inclination
+        assert!((h[2] / norm(h) - 0.7f64.cos()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_elements_round_trip() {
+        let mu = 2.5;
+        let orbit = OrbitalElements {
+            semi_major_axis: 3.0,
+            eccentricity: 0.3,
+            inclination: 2.0,
+            ascending_node: 5.5,
+            argument_of_periapsis: 1.2,
+            anomaly: Anomaly::True(4.0),
+        };
+        let (r, v) = orbit.to_state_vectors(mu);
+        let back = OrbitalElements::from_state_vectors(r, v, mu);
+        assert!((back.semi_major_axis - 3.0).abs() < 1e-12);
+        assert!((back.eccentricity - 0.3).abs() < 1e-12);
+        assert!((back.inclination - 2.0).abs() < 1e-12);
+        assert!((back.ascending_node - 5.5).abs() < 1e-12);
+        assert!((back.argument_of_periapsis - 1.2).abs() < 1e-12);
+        assert!((back.true_anomaly() - 4.0).abs() < 1e-12);
+        assert!((back.longitude_of_periapsis() - (6.7 - 2.0 * PI)).abs() < 1e-12);
+
+        // A circular equatorial orbit measures its anomaly from the x axis
+        let back = OrbitalElements::from_state_vectors([0.0, -2.0, 0.0], [0.5, 0.0, 0.0], 0.5);
+        assert!(back.eccentricity < 1e-12);
+        assert_eq!((back.inclination, back.ascending_node), (0.0, 0.0));
+        assert!((back.true_anomaly() - 1.5 * PI).abs() < 1e-12);
+
+        // Faster than escape speed
+        let back = OrbitalElements::from_state_vectors([1.0, 0.0, 0.0], [0.0, 2.0, 0.0], 1.0);
+        assert!(back.eccentricity > 1.0 && back.semi_major_axis < 0.0);
+    }
+}
@@ -0,0 +1,350 @@
+//! Osculating orbital elements over a run
+//!
+//! Perturbations from other bodies make the Keplerian orbit of each body
+//! around a primary change slowly: eccentricities oscillate and periapses
+//! precess. `osculating_elements` gives the elements of every body around a
+//! primary at one moment, `ElementHistory` collects them over time, from a
+//! running simulation or from a trajectory file with velocity columns, and
+//! `Simulator::enable_elements_output` streams them to a CSV file with one
+//! row per body whenever a trajectory row is due:
+//!
+//! ```text
+//! time,body,semi_major_axis,eccentricity,inclination,ascending_node,argument_of_periapsis,true_anomaly
+//! ```
+//!
+//! Lengths and times are in the output units and angles in degrees. Each
+//! body's gravitational parameter is G (M + m) with the primary.
+
+use crate::body::Body;
+use crate::orbit::OrbitalElements;
+use crate::output::labels;
+use crate::trajectory::TrajectoryData;
+use crate::units::UnitSystem;
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::Write;
+
+/// Header of an orbital elements CSV file
+pub const ELEMENTS_HEADER: &str =
+    "time,body,semi_major_axis,eccentricity,inclination,ascending_node,argument_of_periapsis,true_anomaly";
+
+/// Osculating elements of every body other than `primary` around it
+///
+/// Bodies are named by their output labels, and skipped when their state
+/// is not finite. Without a body named `primary` the list is empty.
+pub fn osculating_elements(
+    bodies: &[Body],
+    primary: &str,
+    g: f64,
+) -> Vec<(String, OrbitalElements)> {
+    let names = labels(bodies);
+    let Some(centre) = names.iter().position(|name| name == primary) else {
+        return Vec::new();
+    };
+    let centre_body = &bodies[centre];
+    bodies
+        .iter()
+        .zip(names)
+        .enumerate()
+        .filter(|&(idx, (body, _))| {
+            idx != centre
+                && body
+                    .position
+                    .iter()
+                    .chain(&body.velocity)
+                    .all(|x| x.is_finite())
+        })
+        .map(|(_, (body, name))| {
+            let position = std::array::from_fn(|k| body.position[k] - centre_body.position[k]);
+            let velocity = std::array::from_fn(|k| body.velocity[k] - centre_body.velocity[k]);
+            let mu = g * (centre_body.mass + body.mass);
+            (
+                name,
+                OrbitalElements::from_state_vectors(position, velocity, mu),
+            )
+        })
+        .collect()
+}
+
+/// The osculating elements of one body at a series of times
+#[derive(Debug, Clone, PartialEq)]
+pub struct ElementSeries {
+    pub name: String,
+    pub times: Vec<f64>,
+    pub elements: Vec<OrbitalElements>,
+}
+
+impl ElementSeries {
+    fn new(name: &str) -> Self {
+        ElementSeries {
+            name: name.to_string(),
+            times: Vec::new(),
+            elements: Vec::new(),
+        }
+    }
+
+    /// Smallest and largest eccentricity seen, if there are any samples
+    pub fn eccentricity_range(&self) -> Option<(f64, f64)> {
+        let mut eccentricities = self.elements.iter().map(|elements| elements.eccentricity);
+        let first = eccentricities.next()?;
+        Some(eccentricities.fold((first, first), |(min, max), e| (min.min(e), max.max(e))))
+    }
+
+    /// Longitude of periapsis at each sample, unwrapped so that it keeps
+    /// growing (or shrinking) past a full turn
+    ///
+    /// Assumes samples closer together than half a turn of precession.
+    pub fn unwrapped_longitude_of_periapsis(&self) -> Vec<f64> {
+        let mut unwrapped: Vec<f64> = Vec::with_capacity(self.elements.len());
+        for elements in &self.elements {
+            let longitude = elements.longitude_of_periapsis();
+            let value = match unwrapped.last() {
+                Some(&previous) => previous + (longitude - previous + PI).rem_euclid(2.0 * PI) - PI,
+                None => longitude,
+            };
+            unwrapped.push(value);
+        }
+        unwrapped
+    }
+
+    /// Apsidal precession rate in radians per unit time: the least-squares
+    /// slope of the unwrapped longitude of periapsis
+    ///
+    /// `None` with fewer than two samples at different times.
+    pub fn precession_rate(&self) -> Option<f64> {
+        let longitudes = self.unwrapped_longitude_of_periapsis();
+        let n = self.times.len() as f64;
+        let mean_t = self.times.iter().sum::<f64>() / n;
+        let mean_w = longitudes.iter().sum::<f64>() / n;
+        let mut covariance = 0.0;
+        let mut variance = 0.0;
+        for (t, w) in self.times.iter().zip(&longitudes) {
+            covariance += (t - mean_t) * (w - mean_w);
+            variance += (t - mean_t) * (t - mean_t);
+        }
+        if self.times.len() < 2 || variance == 0.0 {
+            return None;
+        }
+        Some(covariance / variance)
+    }
+}
+
+/// Osculating elements of every body around a primary over time
+#[derive(Debug, Clone, PartialEq)]
+pub struct ElementHistory {
+    /// Name of the body the orbits are taken around
+    pub primary: String,
+    /// One series per body, in the order they were first seen
+    pub series: Vec<ElementSeries>,
+}
+
+impl ElementHistory {
+    /// An empty history of orbits around the named body
+    pub fn new(primary: &str) -> Self {
+        ElementHistory {
+            primary: primary.to_string(),
+            series: Vec::new(),
+        }
+    }
+
+    /// Add the elements of the given bodies at `time`, with gravitational
+    /// constant `g` in the units of the bodies
+    pub fn record(&mut self, time: f64, bodies: &[Body], g: f64) {
+        for (name, elements) in osculating_elements(bodies, &self.primary, g) {
+            let idx = match self.series.iter().position(|series| series.name == name) {
+                Some(idx) => idx,
+                None => {
+                    self.series.push(ElementSeries::new(&name));
+                    self.series.len() - 1
+                }
+            };
+            self.series[idx].times.push(time);
+            self.series[idx].elements.push(elements);
+        }
+    }
+
+    /// Elements of the bodies in a loaded trajectory
+    ///
+    /// The trajectory needs velocity columns. `masses` gives each body's
+    /// mass in the order of `data.bodies`, in the units of the file, with
+    /// `g` to match; elements are recorded at the frame times.
+    pub fn from_trajectory(
+        data: &TrajectoryData,
+        primary: &str,
+        masses: &[f64],
+        g: f64,
+    ) -> std::io::Result<Self> {
+        let invalid =
+            |message: String| std::io::Error::new(std::io::ErrorKind::InvalidInput, message);
+        if masses.len() != data.bodies.len() {
+            return Err(invalid(format!(
+                "{} masses given for {} bodies",
+                masses.len(),
+                data.bodies.len()
+            )));
+        }
+        if !data.bodies.iter().any(|body| body.name == primary) {
+            return Err(invalid(format!(
+                "no body named '{}' in the trajectory",
+                primary
+            )));
+        }
+        if data
+            .bodies
+            .iter()
+            .any(|body| body.velocities.len() < body.positions.len())
+        {
+            return Err(invalid(String::from(
+                "the trajectory has no velocity columns",
+            )));
+        }
+
+        let mut history = ElementHistory::new(primary);
+        for frame in 0..data.num_frames {
+            let bodies: Vec<Body> = data
+                .bodies
+                .iter()
+                .zip(masses)
+                .map(|(trajectory, &mass)| {
+                    let p = trajectory.positions[frame];
+                    let v = trajectory.velocities[frame];
+                    Body::new(mass, p.to_array(), v.to_array()).with_name(&trajectory.name)
+                })
+                .collect();
+            history.record(data.times[frame], &bodies, g);
+        }
+        Ok(history)
+    }
+
+    /// The series of the named body, if it was ever recorded
+    pub fn get(&self, name: &str) -> Option<&ElementSeries> {
+        self.series.iter().find(|series| series.name == name)
+    }
+}
+
+/// An orbital elements CSV file whose header is written with the first row
+pub(crate) struct ElementsOutput {
+    file: File,
+    header_written: bool,
+    primary: String,
+}
+
+impl ElementsOutput {
+    pub(crate) fn new(file: File, header_written: bool, primary: &str) -> Self {
+        ElementsOutput {
+            file,
+            header_written,
+            primary: primary.to_string(),
+        }
+    }
+
+    /// Whether the header, and so the first row, has been written
+    pub(crate) fn header_written(&self) -> bool {
+        self.header_written
+    }
+
+    /// Write a row for every body orbiting the primary, given in SI
+    pub(crate) fn write_rows(
+        &mut self,
+        time: f64,
+        bodies: &[Body],
+        g: f64,
+        units: UnitSystem,
+    ) -> std::io::Result<()> {
+        if !self.header_written {
+            writeln!(self.file, "{}", ELEMENTS_HEADER)?;
+            self.header_written = true;
+        }
+        for (name, elements) in osculating_elements(bodies, &self.primary, g) {
+            writeln!(
+                self.file,
+                "{:.8},{},{:.10e},{:.10e},{:.10e},{:.10e},{:.10e},{:.10e}",
+                time / units.time(),
+                name,
+                elements.semi_major_axis / units.length(),
+                elements.eccentricity,
+                elements.inclination.to_degrees(),
+                elements.ascending_node.to_degrees(),
+                elements.argument_of_periapsis.to_degrees(),
+                elements.true_anomaly().to_degrees()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempFile;
+    use crate::orbit::Anomaly;
+
+    #[test]
+    fn test_osculating_elements_around_primary() {
+        let orbit = OrbitalElements {
+            semi_major_axis: 2.0,
+            eccentricity: 0.25,
+            inclination: 0.5,
+            ascending_node: 1.0,
+            argument_of_periapsis: 2.0,
+            anomaly: Anomaly::True(3.0),
+        };
+        let (r, v) = orbit.to_state_vectors(4.0);
+        let mut bodies = vec![
+            Body::new(1.0, [0.0; 3], [0.0; 3]).with_name("Probe"),
+            Body::new(3.0, [10.0, 0.0, 0.0], [0.0, 1.0, 0.0]).with_name("Star"),
+            Body::new(1.0, [f64::NAN; 3], [f64::NAN; 3]).with_name("Gone"),
+        ];
+        bodies[0].position = std::array::from_fn(|k| bodies[1].position[k] + r[k]);
+        bodies[0].velocity = std::array::from_fn(|k| bodies[1].velocity[k] + v[k]);
+
+        let elements = osculating_elements(&bodies, "Star", 1.0);
+        assert_eq!(elements.len(), 1);
+        assert_eq!(elements[0].0, "Probe");
+        assert!((elements[0].1.eccentricity - 0.25).abs() < 1e-12);
+        assert!((elements[0].1.argument_of_periapsis - 2.0).abs() < 1e-12);
+        assert!(osculating_elements(&bodies, "Planet", 1.0).is_empty());
+    }
+
+    #[test]
+    fn test_precession_rate() {
+        let mut series = ElementSeries::new("Mercury");
+        // Periapsis turning by 0.5 rad per unit time, through a full turn
+        for step in 0..40 {
+            let t = step as f64 * 0.5;
+            series.times.push(t);
+            series.elements.push(OrbitalElements {
+                semi_major_axis: 1.0,
+                eccentricity: 0.2 + 0.01 * (step % 3) as f64,
+                inclination: 0.0,
+                ascending_node: 0.0,
+                argument_of_periapsis: (0.5 * t).rem_euclid(2.0 * PI),
+                anomaly: Anomaly::True(0.0),
+            });
+        }
+        assert!((series.precession_rate().unwrap() - 0.5).abs() < 1e-12);
+        assert_eq!(series.eccentricity_range(), Some((0.2, 0.2 + 0.01 * 2.0)));
+        assert_eq!(ElementSeries::new("Venus").precession_rate(), None);
+    }
+
+    #[test]
+    fn test_from_trajectory() {
+        let path = TempFile::new("elements_trajectory.csv");
+        std::fs::write(
+            &path,
+            "time,Sun_x,Sun_y,Sun_z,Sun_vx,Sun_vy,Sun_vz,Earth_x,Earth_y,Earth_z,Earth_vx,Earth_vy,Earth_vz\n\
+             0,0,0,0,0,0,0,1,0,0,0,1,0\n\
+             2,0,0,0,0,0,0,0,1,0,-1,0,0\n",
+        )
+        .unwrap();
+        let data = TrajectoryData::load_csv(&path).unwrap();
+
+        let history = ElementHistory::from_trajectory(&data, "Sun", &[1.0, 0.0], 1.0).unwrap();
+        let earth = history.get("Earth").unwrap();
+        assert_eq!(earth.times, [0.0, 2.0]);
+        assert!(earth.elements.iter().all(|e| e.eccentricity < 1e-6));
+        assert!((earth.elements[1].true_anomaly() - PI / 2.0).abs() < 1e-6);
+        assert!(ElementHistory::from_trajectory(&data, "Moon", &[1.0, 0.0], 1.0).is_err());
+        assert!(ElementHistory::from_trajectory(&data, "Sun", &[1.0], 1.0).is_err());
+    }
+}
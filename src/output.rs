@@ -0,0 +1,391 @@
+//! Trajectory output columns and CSV writing
+//!
+//! Every row starts with the time and each body's position. Velocities,
+//! accelerations, per-body kinetic energy, the total energy and the total
+//! angular momentum can be added as extra columns through `OutputColumns`.
+//! Columns are named after the bodies, e.g. `Earth_x` or `Moon_vx`, so
+//! readers can find them by header name.
+//!
+//! Values are written in SI unless another `UnitSystem` is chosen, in
+//! which case every column, the time included, is converted to it.
+//!
+//! The header is fixed once written. When bodies merge, the columns of a
+//! body that no longer exists are written as `NaN`.
+//!
+//! In an `OutputFrame` centred on a body, positions, velocities and
+//! accelerations are relative to that body. Kinetic energies, the total
+//! energy and the angular momentum always describe the inertial state,
+//! so the conserved quantities stay conserved in the output.
+
+use crate::body::Body;
+use crate::frame::OutputFrame;
+use crate::simulator::{system_angular_momentum, system_energy};
+use crate::softening::Softening;
+use crate::units::UnitSystem;
+use std::fmt;
+use std::fs::File;
+use std::io::Write;
+use std::str::FromStr;
+
+/// Which optional columns to write alongside the body positions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct OutputColumns {
+    /// `<name>_vx`, `<name>_vy`, `<name>_vz` per body
+    pub velocity: bool,
+    /// `<name>_ax`, `<name>_ay`, `<name>_az` per body
+    pub acceleration: bool,
+    /// `<name>_ke` per body
+    pub kinetic_energy: bool,
+    /// `energy`: kinetic plus potential energy of the system
+    pub total_energy: bool,
+    /// `Lx`, `Ly`, `Lz`: total angular momentum about the origin
+    pub angular_momentum: bool,
+}
+
+impl OutputColumns {
+    /// Names accepted in an `output_columns` list
+    pub const NAMES: [&'static str; 5] = [
+        "velocity",
+        "acceleration",
+        "kinetic_energy",
+        "total_energy",
+        "angular_momentum",
+    ];
+
+    /// Every optional column
+    pub fn all() -> Self {
+        OutputColumns {
+            velocity: true,
+            acceleration: true,
+            kinetic_energy: true,
+            total_energy: true,
+            angular_momentum: true,
+        }
+    }
+
+    fn flags(&self) -> [bool; 5] {
+        [
+            self.velocity,
+            self.acceleration,
+            self.kinetic_energy,
+            self.total_energy,
+            self.angular_momentum,
+        ]
+    }
+
+    /// CSV header line for the given bodies, without a trailing newline
+    pub fn header(&self, bodies: &[Body]) -> String {
+        self.header_for(&labels(bodies))
+    }
+
+    /// CSV header line with a column group for each named body
+    pub fn header_for(&self, names: &[String]) -> String {
+        let mut header = String::from("time");
+        for name in names {
+            header.push_str(&format!(",{}_x,{}_y,{}_z", name, name, name));
+            if self.velocity {
+                header.push_str(&format!(",{}_vx,{}_vy,{}_vz", name, name, name));
+            }
+            if self.acceleration {
+                header.push_str(&format!(",{}_ax,{}_ay,{}_az", name, name, name));
+            }
+            if self.kinetic_energy {
+                header.push_str(&format!(",{}_ke", name));
+            }
+        }
+        if self.total_energy {
+            header.push_str(",energy");
+        }
+        if self.angular_momentum {
+            header.push_str(",Lx,Ly,Lz");
+        }
+        header
+    }
+
+    /// CSV row for the given state in SI, without a trailing newline
+    ///
+    /// Positions keep the fixed-point format of the original output; the
+    /// optional quantities span many orders of magnitude and are written
+    /// in scientific notation. The total energy uses the given softening.
+    pub fn row(&self, time: f64, bodies: &[Body], softening: Softening) -> String {
+        self.row_for(time, &labels(bodies), bodies, &OutputFrame::Inertial, softening, UnitSystem::Si)
+    }
+
+    /// CSV row laid out like `header_for(names)`, converted from SI to `units`
+    ///
+    /// `bodies` are the inertial state and must appear in the same order as
+    /// their names do in `names`; names without a body, such as bodies lost
+    /// in a merger, get `NaN`. Vector columns are written in `frame`.
+    pub fn row_for(
+        &self,
+        time: f64,
+        names: &[String],
+        bodies: &[Body],
+        frame: &OutputFrame,
+        softening: Softening,
+        units: UnitSystem,
+    ) -> String {
+        let values = self.values_for(time, names, bodies, frame, softening, units);
+        let per_body = self.columns_per_body();
+        let body_columns = names.len() * per_body;
+        let mut line = format!("{:.8}", values[0]);
+        for (idx, value) in values[1..].iter().enumerate() {
+            if idx < body_columns && idx % per_body < 3 {
+                line.push_str(&format!(",{:.8}", value));
+            } else {
+                line.push_str(&format!(",{:.10e}", value));
+            }
+        }
+        line
+    }
+
+    /// Values of a row laid out like `header_for(names)`, as in `row_for`
+    /// but at full precision
+    pub fn values_for(
+        &self,
+        time: f64,
+        names: &[String],
+        bodies: &[Body],
+        frame: &OutputFrame,
+        softening: Softening,
+        units: UnitSystem,
+    ) -> Vec<f64> {
+        let mut values = Vec::with_capacity(1 + names.len() * self.columns_per_body() + 4);
+        values.push(time / units.time());
+        let seen = frame.transform(bodies, &labels(bodies));
+        let mut next = 0;
+        for name in names {
+            let (body, relative) = match bodies.get(next) {
+                Some(body) if body.label(next) == *name => (body, &seen[next]),
+                _ => {
+                    values.resize(values.len() + self.columns_per_body(), f64::NAN);
+                    continue;
+                }
+            };
+            next += 1;
+            let relative = units.body_from_si(relative);
+            values.extend(relative.position);
+            if self.velocity {
+                values.extend(relative.velocity);
+            }
+            if self.acceleration {
+                values.extend(relative.acceleration);
+            }
+            if self.kinetic_energy {
+                values.push(units.body_from_si(body).kinetic_energy());
+            }
+        }
+        if self.total_energy {
+            values.push(system_energy(bodies, softening) / units.energy());
+        }
+        if self.angular_momentum {
+            let l = system_angular_momentum(bodies);
+            values.extend(l.map(|component| component / units.angular_momentum()));
+        }
+        values
+    }
+
+    /// Number of columns written for each body
+    pub(crate) fn columns_per_body(&self) -> usize {
+        3 + 3 * self.velocity as usize + 3 * self.acceleration as usize + self.kinetic_energy as usize
+    }
+}
+
+/// Each body's label, as used in column names
+pub(crate) fn labels(bodies: &[Body]) -> Vec<String> {
+    bodies.iter().enumerate().map(|(idx, body)| body.label(idx)).collect()
+}
+
+impl FromStr for OutputColumns {
+    type Err = String;
+
+    /// Parse a comma-separated list such as `velocity, total_energy`
+    ///
+    /// `all` selects every column and `positions` (always written) or
+    /// `none` select nothing extra.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut columns = OutputColumns::default();
+        for item in s.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+            match item.to_lowercase().replace('-', "_").as_str() {
+                "positions" | "position" | "none" => {}
+                "all" => columns = OutputColumns::all(),
+                "velocity" | "velocities" => columns.velocity = true,
+                "acceleration" | "accelerations" => columns.acceleration = true,
+                "kinetic_energy" | "ke" => columns.kinetic_energy = true,
+                "total_energy" | "energy" => columns.total_energy = true,
+                "angular_momentum" => columns.angular_momentum = true,
+                other => {
+                    return Err(format!(
+                        "unknown output column '{}' (expected any of: {})",
+                        other,
+                        Self::NAMES.join(", ")
+                    ))
+                }
+            }
+        }
+        Ok(columns)
+    }
+}
+
+impl fmt::Display for OutputColumns {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<&str> = Self::NAMES
+            .iter()
+            .zip(self.flags())
+            .filter(|(_, on)| *on)
+            .map(|(name, _)| *name)
+            .collect();
+        if names.is_empty() {
+            f.write_str("positions")
+        } else {
+            write!(f, "positions, {}", names.join(", "))
+        }
+    }
+}
+
+/// A CSV trajectory file whose header is written with the first row
+///
+/// Deferring the header lets the columns be chosen after the file is
+/// opened; once the first row is out they are fixed for the whole file.
+pub(crate) struct CsvOutput {
+    file: File,
+    header_written: bool,
+}
+
+impl CsvOutput {
+    pub(crate) fn new(file: File) -> Self {
+        CsvOutput {
+            file,
+            header_written: false,
+        }
+    }
+
+    /// Continue a file that may already hold a header and rows
+    pub(crate) fn appending(file: File, header_written: bool) -> Self {
+        CsvOutput { file, header_written }
+    }
+
+    /// Whether the header, and so the column layout, has been written
+    pub(crate) fn header_written(&self) -> bool {
+        self.header_written
+    }
+
+    /// Write a row laid out for the named bodies, as in `OutputColumns::row_for`
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn write_row(
+        &mut self,
+        columns: &OutputColumns,
+        time: f64,
+        names: &[String],
+        bodies: &[Body],
+        frame: &OutputFrame,
+        softening: Softening,
+        units: UnitSystem,
+    ) -> std::io::Result<()> {
+        if !self.header_written {
+            writeln!(self.file, "{}", columns.header_for(names))?;
+            self.header_written = true;
+        }
+        writeln!(self.file, "{}", columns.row_for(time, names, bodies, frame, softening, units))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_output_columns() {
+        let columns: OutputColumns = "velocity, total_energy".parse().unwrap();
+        assert!(columns.velocity && columns.total_energy);
+        assert!(!columns.acceleration && !columns.kinetic_energy && !columns.angular_momentum);
+        assert_eq!(columns.to_string(), "positions, velocity, total_energy");
+
+        assert_eq!("all".parse::<OutputColumns>().unwrap(), OutputColumns::all());
+        assert_eq!("positions".parse::<OutputColumns>().unwrap(), OutputColumns::default());
+        assert!("spin".parse::<OutputColumns>().is_err());
+    }
+
+    #[test]
+    fn test_header_and_row_line_up() {
+        let bodies = vec![
+            Body::new(2.0, [1.0, 0.0, 0.0], [0.0, 3.0, 0.0]).with_name("A"),
+            Body::new(1.0, [-2.0, 0.0, 0.0], [0.0, -6.0, 0.0]),
+        ];
+        let columns = OutputColumns::all();
+        let header = columns.header(&bodies);
+        let row = columns.row(5.0, &bodies, Softening::None);
+
+        let names: Vec<&str> = header.split(',').collect();
+        let values: Vec<f64> = row.split(',').map(|v| v.parse().unwrap()).collect();
+        assert_eq!(names.len(), values.len());
+
+        let value = |name: &str| values[names.iter().position(|n| *n == name).unwrap()];
+        assert_eq!(value("time"), 5.0);
+        assert_eq!(value("A_vy"), 3.0);
+        assert_eq!(value("body1_x"), -2.0);
+        assert_eq!(value("A_ke"), 9.0);
+        assert_eq!(value("body1_ke"), 18.0);
+        // L = 2 * (1 * 3) + 1 * (-2 * -6) = 18 about z
+        assert_eq!(value("Lz"), 18.0);
+        assert_eq!(value("Lx"), 0.0);
+    }
+
+    #[test]
+    fn test_conserved_columns_stay_inertial_in_a_body_frame() {
+        let bodies = vec![
+            Body::new(2.0, [1.0, 0.0, 0.0], [0.0, 3.0, 0.0]).with_name("A"),
+            Body::new(1.0, [-2.0, 0.0, 0.0], [0.0, -6.0, 0.0]).with_name("B"),
+        ];
+        let columns = OutputColumns::all();
+        let names = labels(&bodies);
+        let frame = OutputFrame::centred_on("A");
+        let inertial = columns.values_for(0.0, &names, &bodies, &OutputFrame::Inertial, Softening::None, UnitSystem::Si);
+        let relative = columns.values_for(0.0, &names, &bodies, &frame, Softening::None, UnitSystem::Si);
+
+        let header = columns.header_for(&names);
+        let value = |values: &[f64], name: &str| values[header.split(',').position(|n| n == name).unwrap()];
+        assert_eq!(value(&relative, "A_x"), 0.0);
+        assert_eq!(value(&relative, "B_x"), -3.0);
+        assert_eq!(value(&relative, "B_vy"), -9.0);
+        for name in ["A_ke", "B_ke", "energy", "Lx", "Ly", "Lz"] {
+            assert_eq!(value(&relative, name), value(&inertial, name), "{}", name);
+        }
+        assert_eq!(value(&relative, "Lz"), 18.0);
+    }
+
+    #[test]
+    fn test_missing_bodies_are_nan() {
+        let names: Vec<String> = ["A", "B", "C"].iter().map(|name| name.to_string()).collect();
+        let bodies = vec![
+            Body::new(1.0, [1.0, 2.0, 3.0], [0.0; 3]).with_name("A"),
+            Body::new(1.0, [4.0, 5.0, 6.0], [0.0; 3]).with_name("C"),
+        ];
+        let columns = OutputColumns {
+            kinetic_energy: true,
+            ..OutputColumns::default()
+        };
+        assert_eq!(columns.header_for(&names), "time,A_x,A_y,A_z,A_ke,B_x,B_y,B_z,B_ke,C_x,C_y,C_z,C_ke");
+        let row = columns.row_for(0.0, &names, &bodies, &OutputFrame::Inertial, Softening::None, UnitSystem::Si);
+        let values: Vec<&str> = row.split(',').collect();
+        assert_eq!(values.len(), 13);
+        assert_eq!(&values[5..9], ["NaN"; 4]);
+        assert_eq!(values[9].parse::<f64>().unwrap(), 4.0);
+    }
+
+    #[test]
+    fn test_row_in_other_units() {
+        let units = UnitSystem::Astronomical;
+        let bodies = vec![units.body_to_si(&Body::new(1.0, [1.0, -2.0, 0.5], [0.0, 0.01, 0.0]))];
+        let columns = OutputColumns::all();
+        let row = columns.row_for(3.0 * 86400.0, &labels(&bodies), &bodies, &OutputFrame::Inertial, Softening::None, units);
+        let values: Vec<f64> = row.split(',').map(|v| v.parse().unwrap()).collect();
+
+        assert_eq!(values[0], 3.0);
+        assert_eq!(&values[1..4], [1.0, -2.0, 0.5]);
+        assert!((values[5] - 0.01).abs() < 1e-15);
+        // Kinetic energy and angular momentum of one solar mass
+        assert!((values[10] - 0.5e-4).abs() < 1e-15);
+        assert!((values[14] - 0.01).abs() < 1e-15);
+    }
+}
@@ -0,0 +1,211 @@
+//! First post-Newtonian (1PN) corrections to gravity
+//!
+//! Newtonian gravity misses the relativistic precession of orbits close to
+//! a massive body: Mercury's perihelion advances by about 43 arcseconds per
+//! century more than the planets alone explain. The Einstein-Infeld-Hoffmann
+//! equations add the leading corrections, of order (v/c)^2 and GM/(rc^2),
+//! for any number of bodies. They depend on the velocities as well as the
+//! positions, so the symplectic integrators lose their exact energy
+//! behaviour with them switched on; the corrections are small enough that
+//! they still work well in practice.
+//!
+//! The corrections treat every body as a point mass and ignore softening.
+
+use crate::body::Body;
+use crate::force_model::ForceModel;
+use crate::vector::dot;
+
+/// Speed of light in vacuum (m/s)
+pub const SPEED_OF_LIGHT: f64 = 299_792_458.0;
+
+/// The 1PN Einstein-Infeld-Hoffmann correction as a force model
+///
+/// `Simulator::set_post_newtonian` applies it right after gravity and
+/// records it in checkpoints; adding it with `Simulator::add_force_model`
+/// works as well for other constants.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PostNewtonian {
+    /// Gravitational constant
+    pub g: f64,
+    /// Speed of light
+    pub c: f64,
+}
+
+impl PostNewtonian {
+    /// The correction for the given gravitational constant and speed of light
+    pub fn new(g: f64, c: f64) -> Self {
+        PostNewtonian { g, c }
+    }
+}
+
+impl ForceModel for PostNewtonian {
+    fn add_accelerations(&mut self, _time: f64, bodies: &mut [Body]) {
+        post_newtonian_accelerations(bodies, self.g, self.c);
+    }
+
+    fn name(&self) -> &str {
+        "post-newtonian"
+    }
+}
+
+/// Add the 1PN Einstein-Infeld-Hoffmann terms to every body's acceleration
+///
+/// The Newtonian accelerations the terms depend on are computed here from
+/// point masses, so whatever is already in `acceleration` is left alone.
+pub fn post_newtonian_accelerations(bodies: &mut [Body], g: f64, c: f64) {
+    let n = bodies.len();
+
+    // Newtonian acceleration and potential (as a positive G m / r sum) at each body
+    let mut newtonian = vec![[0.0; 3]; n];
+    let mut potential = vec![0.0; n];
+    for a in 0..n {
+        for b in 0..n {
+            if a == b {
+                continue;
+            }
+            let d = bodies[a].vector_to(&bodies[b]);
+            let r = dot(d, d).sqrt();
+            if r == 0.0 {
+                continue;
+            }
+            let gm = g * bodies[b].mass;
+            for k in 0..3 {
+                newtonian[a][k] += gm * d[k] / (r * r * r);
+            }
+            potential[a] += gm / r;
+        }
+    }
+
+    let c_squared = c * c;
+    let mut corrections = vec![[0.0; 3]; n];
+    for a in 0..n {
+        let va = bodies[a].velocity;
+        for b in 0..n {
+            if a == b {
+                continue;
+            }
+            // Unit vector from b to a
+            let d = bodies[b].vector_to(&bodies[a]);
+            let r = dot(d, d).sqrt();
+            if r == 0.0 {
+                continue;
+            }
+            let n_ab = [d[0] / r, d[1] / r, d[2] / r];
+            let vb = bodies[b].velocity;
+            let gm = g * bodies[b].mass;
+
+            let n_dot_vb = dot(n_ab, vb);
+            let radial = dot(va, va) + 2.0 * dot(vb, vb)
+                - 4.0 * dot(va, vb)
+                - 1.5 * n_dot_vb * n_dot_vb
+                - 4.0 * potential[a]
+                - potential[b]
+                - 0.5 * dot(d, newtonian[b]);
+            let along = dot(
+                n_ab,
+                [
+                    4.0 * va[0] - 3.0 * vb[0],
+                    4.0 * va[1] - 3.0 * vb[1],
+                    4.0 * va[2] - 3.0 * vb[2],
+                ],
+            );
+            for k in 0..3 {
+                corrections[a][k] += gm / (r * r) * (along * (va[k] - vb[k]) - radial * n_ab[k])
+                    + 3.5 * gm * newtonian[b][k] / r;
+            }
+        }
+    }
+
+    for (body, correction) in bodies.iter_mut().zip(corrections) {
+        for k in 0..3 {
+            body.acceleration[k] += correction[k] / c_squared;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::Simulator;
+
+    const G: f64 = 6.67430e-11;
+    const SUN_MASS: f64 = 1.98892e30;
+    const MERCURY_MASS: f64 = 3.3011e23;
+    const MERCURY_SEMI_MAJOR_AXIS: f64 = 5.7909e10;
+    const MERCURY_ECCENTRICITY: f64 = 0.2056;
+    const MERCURY_PERIOD_DAYS: f64 = 87.969;
+
+    /// Sun and Mercury with Mercury at perihelion on the x axis
+    fn sun_mercury() -> Vec<Body> {
+        let (a, e) = (MERCURY_SEMI_MAJOR_AXIS, MERCURY_ECCENTRICITY);
+        let mu = G * (SUN_MASS + MERCURY_MASS);
+        let r = a * (1.0 - e);
+        let v = (mu * (1.0 + e) / r).sqrt();
+        // Barycentric velocities so the system stays put
+        let sun_v = -v * MERCURY_MASS / (SUN_MASS + MERCURY_MASS);
+        vec![
+            Body::new(SUN_MASS, [0.0; 3], [0.0, sun_v, 0.0]),
+            Body::new(MERCURY_MASS, [r, 0.0, 0.0], [0.0, v + sun_v, 0.0]),
+        ]
+    }
+
+    /// Direction of Mercury's periapsis from the Laplace-Runge-Lenz vector
+    fn periapsis_angle(bodies: &[Body]) -> f64 {
+        let mu = G * (bodies[0].mass + bodies[1].mass);
+        let r = bodies[0].vector_to(&bodies[1]);
+        let v: Vec<f64> = (0..3)
+            .map(|k| bodies[1].velocity[k] - bodies[0].velocity[k])
+            .collect();
+        let h = [
+            r[1] * v[2] - r[2] * v[1],
+            r[2] * v[0] - r[0] * v[2],
+            r[0] * v[1] - r[1] * v[0],
+        ];
+        let distance = dot(r, r).sqrt();
+        let ax = v[1] * h[2] - v[2] * h[1] - mu * r[0] / distance;
+        let ay = v[2] * h[0] - v[0] * h[2] - mu * r[1] / distance;
+        ay.atan2(ax)
+    }
+
+    fn advance_after_orbits(orbits: usize, relativistic: bool) -> f64 {
+        let steps_per_orbit = 2000;
+        let dt = MERCURY_PERIOD_DAYS * 86400.0 / steps_per_orbit as f64;
+        let mut sim = Simulator::new(sun_mercury(), dt);
+        sim.set_post_newtonian(relativistic);
+        sim.run(orbits * steps_per_orbit);
+        periapsis_angle(sim.bodies())
+    }
+
+    #[test]
+    fn test_mercury_perihelion_advance() {
+        let orbits = 10;
+        // Subtract the Newtonian run so only the relativistic part remains
+        let advance = advance_after_orbits(orbits, true) - advance_after_orbits(orbits, false);
+        let orbits_per_century = 36525.0 / MERCURY_PERIOD_DAYS;
+        let arcsec_per_century = advance.to_degrees() * 3600.0 * orbits_per_century / orbits as f64;
+
+        // General relativity predicts 42.98"/century
+        assert!(
+            (arcsec_per_century - 42.98).abs() < 0.5,
+            "perihelion advance {}\"/century",
+            arcsec_per_century
+        );
+    }
+
+    #[test]
+    fn test_corrections_vanish_as_c_grows() {
+        let mut bodies = sun_mercury();
+        post_newtonian_accelerations(&mut bodies, G, SPEED_OF_LIGHT);
+        let correction = dot(bodies[1].acceleration, bodies[1].acceleration).sqrt();
+
+        let mut slow = sun_mercury();
+        post_newtonian_accelerations(&mut slow, G, SPEED_OF_LIGHT / 10.0);
+        let slow_correction = dot(slow[1].acceleration, slow[1].acceleration).sqrt();
+
+        // Of order (v/c)^2 relative to the Newtonian pull
+        let r = MERCURY_SEMI_MAJOR_AXIS * (1.0 - MERCURY_ECCENTRICITY);
+        let newtonian = G * SUN_MASS / (r * r);
+        assert!(correction > 0.0 && correction < 1e-6 * newtonian);
+        assert!((slow_correction / correction - 100.0).abs() < 1e-6);
+    }
+}
@@ -3,12 +3,261 @@
 //! This module provides the `Simulator` struct which manages
 //! an ensemble of bodies and handles the integration loop.
 
+use crate::binary::{self, BinaryOutput};
 use crate::body::Body;
-use crate::integrator::RungeKuttaFehlberg;
-use std::fs::File;
-use std::io::Write;
+use crate::checkpoint::Checkpoint;
+use crate::collision::{self, CollisionEvent, CollisionMode, CollisionOutcome};
+use crate::diagnostics::{self, Diagnostics, DiagnosticsOutput, DriftTracker, DIAGNOSTICS_HEADER};
+use crate::force_model::ForceModel;
+use crate::forces::ForceSolver;
+use crate::frame::{self, OutputFrame};
+use crate::integrator::{DenseOutput, Integrator, IntegratorKind, RungeKuttaFehlberg, StateVector, StepReport};
+use crate::output::{labels, CsvOutput, OutputColumns};
+use crate::orbit::OrbitalElements;
+use crate::osculating::{self, ElementsOutput, ELEMENTS_HEADER};
+use crate::relativity::{PostNewtonian, SPEED_OF_LIGHT};
+use crate::softening::Softening;
+use crate::units::UnitSystem;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Seek, SeekFrom};
+use std::path::Path;
 
-const G: f64 = 6.67430e-11; // Gravitational constant (m^3 kg^-1 s^-2)
+pub(crate) const G: f64 = 6.67430e-11; // Gravitational constant (m^3 kg^-1 s^-2)
+
+/// Largest factor by which the adaptive controller may grow the time step
+const MAX_STEP_GROWTH: f64 = 5.0;
+/// Smallest factor by which the adaptive controller may shrink the time step
+const MIN_STEP_SHRINK: f64 = 0.1;
+
+/// Gravitational accelerations, with the 1PN correction when enabled,
+/// followed by those of each force model
+fn evaluate_forces(
+    solver: ForceSolver,
+    softening: Softening,
+    post_newtonian: bool,
+    models: &mut [Box<dyn ForceModel>],
+    time: f64,
+    bodies: &mut [Body],
+) {
+    solver.compute_accelerations(bodies, G, softening);
+    if post_newtonian {
+        PostNewtonian::new(G, SPEED_OF_LIGHT).add_accelerations(time, bodies);
+    }
+    for model in models.iter_mut() {
+        model.add_accelerations(time, bodies);
+    }
+}
+
+/// Total kinetic energy of a set of bodies
+pub(crate) fn system_kinetic_energy(bodies: &[Body]) -> f64 {
+    bodies.iter().map(Body::kinetic_energy).sum()
+}
+
+/// Total gravitational potential energy of a set of bodies, with the
+/// same softening as the forces
+pub(crate) fn system_potential_energy(bodies: &[Body], softening: Softening) -> f64 {
+    let mut pe = 0.0;
+    let n = bodies.len();
+
+    for i in 0..n {
+        for j in (i + 1)..n {
+            let r = bodies[i].distance_to(&bodies[j]);
+            pe -= G * bodies[i].mass * bodies[j].mass * softening.potential_factor(r * r);
+        }
+    }
+    pe
+}
+
+/// Total mechanical energy of a set of bodies
+pub(crate) fn system_energy(bodies: &[Body], softening: Softening) -> f64 {
+    system_kinetic_energy(bodies) + system_potential_energy(bodies, softening)
+}
+
+/// Total angular momentum of a set of bodies about the origin
+pub(crate) fn system_angular_momentum(bodies: &[Body]) -> [f64; 3] {
+    let mut total = [0.0; 3];
+    for body in bodies {
+        let l = body.angular_momentum();
+        for k in 0..3 {
+            total[k] += l[k];
+        }
+    }
+    total
+}
+
+/// Open a CSV file to continue it after `time`, returning the file
+/// positioned at its end and whether it already has its header
+///
+/// Rows later than `time`, in the file's own time units, are removed
+/// together with any partly written final line. A header other than
+/// `expected_header` is an error; a missing or empty file is started afresh.
+fn open_for_append(path: &str, expected_header: &str, time: f64) -> std::io::Result<(File, bool)> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+
+    // Rows carry times with 8 decimals, so allow for their rounding
+    let epsilon = 1e-6_f64.max(time.abs() * 1e-12);
+    let mut reader = BufReader::new(&file);
+    let mut line = String::new();
+    let mut keep = 0u64;
+    let mut header_written = false;
+    loop {
+        line.clear();
+        let read = reader.read_line(&mut line)?;
+        if read == 0 || !line.ends_with('\n') {
+            break;
+        }
+        if !header_written {
+            if line.trim_end() != expected_header {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("{} has different columns than the resumed simulation", path),
+                ));
+            }
+            header_written = true;
+        } else {
+            let row_time: Option<f64> = line.split(',').next().and_then(|t| t.trim().parse().ok());
+            if !row_time.is_some_and(|t| t <= time + epsilon) {
+                break;
+            }
+        }
+        keep += read as u64;
+    }
+    drop(reader);
+
+    file.set_len(keep)?;
+    file.seek(SeekFrom::End(0))?;
+    Ok((file, header_written))
+}
+
+/// Error-control settings for adaptive time-stepping
+///
+/// A step is accepted when every component of the RKF45 error estimate
+/// satisfies `|err| <= abs + rel_tolerance * |y|`, where `y` is the larger
+/// magnitude of that component before and after the step and `abs` is
+/// `abs_tolerance` for positions and `abs_velocity_tolerance` for
+/// velocities.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdaptiveSettings {
+    /// Absolute error tolerance per position component (in metres)
+    pub abs_tolerance: f64,
+    /// Absolute error tolerance per velocity component (in m/s)
+    pub abs_velocity_tolerance: f64,
+    /// Relative error tolerance per state component
+    pub rel_tolerance: f64,
+    /// Smallest time step the controller may use (in seconds)
+    pub min_dt: f64,
+    /// Largest time step the controller may use (in seconds)
+    pub max_dt: f64,
+    /// Safety factor applied to the optimal step size estimate
+    pub safety: f64,
+}
+
+impl AdaptiveSettings {
+    /// Create settings with the given tolerances and step bounds
+    ///
+    /// The velocity tolerance takes the same value as `abs_tolerance`, in
+    /// m/s; `with_velocity_tolerance` sets its own. Fails with the reason
+    /// if the settings are invalid; see `validate`.
+    pub fn new(abs_tolerance: f64, rel_tolerance: f64, min_dt: f64, max_dt: f64) -> Result<Self, String> {
+        let settings = AdaptiveSettings {
+            abs_tolerance,
+            abs_velocity_tolerance: abs_tolerance,
+            rel_tolerance,
+            min_dt,
+            max_dt,
+            safety: 0.9,
+        };
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    /// The same settings with the given absolute velocity tolerance (in m/s)
+    pub fn with_velocity_tolerance(mut self, abs_velocity_tolerance: f64) -> Self {
+        self.abs_velocity_tolerance = abs_velocity_tolerance;
+        self
+    }
+
+    /// Check that the settings can drive the step controller: a positive,
+    /// finite `min_dt` no larger than `max_dt`, non-negative tolerances that
+    /// are not both zero, and a positive safety factor
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.min_dt.is_finite() || self.min_dt <= 0.0 {
+            return Err(format!("minimum time step must be positive, got {}", self.min_dt));
+        }
+        if self.max_dt.is_nan() || self.max_dt < self.min_dt {
+            return Err(format!(
+                "maximum time step {} is smaller than the minimum time step {}",
+                self.max_dt, self.min_dt
+            ));
+        }
+        let tolerances = [
+            ("absolute", self.abs_tolerance),
+            ("absolute velocity", self.abs_velocity_tolerance),
+            ("relative", self.rel_tolerance),
+        ];
+        for (name, tolerance) in tolerances {
+            if tolerance.is_nan() || tolerance < 0.0 {
+                return Err(format!("{} tolerance must not be negative, got {}", name, tolerance));
+            }
+        }
+        if self.rel_tolerance == 0.0 && (self.abs_tolerance == 0.0 || self.abs_velocity_tolerance == 0.0) {
+            return Err("absolute and relative tolerances cannot both be zero".to_string());
+        }
+        if !self.safety.is_finite() || self.safety <= 0.0 {
+            return Err(format!("safety factor must be positive, got {}", self.safety));
+        }
+        Ok(())
+    }
+
+    /// Compute the scaled error norm of a step (accept when <= 1.0)
+    fn error_norm(&self, before: &[Body], after: &[Body], errors: &[StateVector]) -> f64 {
+        let mut norm: f64 = 0.0;
+        for ((b0, b1), error) in before.iter().zip(after).zip(errors) {
+            for dim in 0..3 {
+                let pos_scale = self.abs_tolerance
+                    + self.rel_tolerance * b0.position[dim].abs().max(b1.position[dim].abs());
+                let vel_scale = self.abs_velocity_tolerance
+                    + self.rel_tolerance * b0.velocity[dim].abs().max(b1.velocity[dim].abs());
+                for ratio in [error[dim].abs() / pos_scale, error[dim + 3].abs() / vel_scale] {
+                    // A step that blew up must be rejected, not scored as exact
+                    if !ratio.is_finite() {
+                        return f64::INFINITY;
+                    }
+                    norm = norm.max(ratio);
+                }
+            }
+        }
+        norm
+    }
+
+    /// Suggest the next step size given the error norm of the current one
+    fn next_dt(&self, dt: f64, error_norm: f64) -> f64 {
+        let factor = if error_norm > 0.0 {
+            (self.safety * error_norm.powf(-0.2)).clamp(MIN_STEP_SHRINK, MAX_STEP_GROWTH)
+        } else {
+            MAX_STEP_GROWTH
+        };
+        (dt * factor).clamp(self.min_dt, self.max_dt)
+    }
+}
+
+impl Default for AdaptiveSettings {
+    fn default() -> Self {
+        AdaptiveSettings {
+            abs_tolerance: 1e-6,
+            abs_velocity_tolerance: 1e-6,
+            rel_tolerance: 1e-9,
+            min_dt: 1.0,
+            max_dt: 1e7,
+            safety: 0.9,
+        }
+    }
+}
 
 /// Manages N-body simulation with automatic force calculation
 pub struct Simulator {
@@ -19,9 +268,52 @@ pub struct Simulator {
     /// Time step (in seconds)
     dt: f64,
     /// The integrator used for time-stepping
-    integrator: RungeKuttaFehlberg,
+    integrator: Box<dyn Integrator>,
+    /// How gravitational accelerations are computed
+    force_solver: ForceSolver,
+    /// Short-range modification of gravity, for forces and potential energy
+    softening: Softening,
+    /// Whether the 1PN relativistic correction is added to gravity
+    post_newtonian: bool,
+    /// Additional forces applied after gravity, in order
+    force_models: Vec<Box<dyn ForceModel>>,
+    /// What happens when bodies overlap
+    collision_mode: CollisionMode,
+    /// Collisions so far, in the order they happened
+    collisions: Vec<CollisionEvent>,
+    /// Set when a collision has halted the run
+    stopped: bool,
     /// Optional output file for trajectory data
-    output_file: Option<File>,
+    output: Option<CsvOutput>,
+    /// Optional binary trajectory file, written with the same columns
+    binary_output: Option<BinaryOutput>,
+    /// Bodies named in the output columns, once a merger has removed some
+    /// of them; `None` while the columns match the current bodies
+    output_layout: Option<Vec<String>>,
+    /// Optional columns written alongside the positions
+    output_columns: OutputColumns,
+    /// Units of the output columns, converted from SI as rows are written
+    output_units: UnitSystem,
+    /// Frame the output columns are written in
+    output_frame: OutputFrame,
+    /// Optional file for conserved-quantity diagnostics, written with each output row
+    diagnostics_output: Option<DiagnosticsOutput>,
+    /// Errors of the conserved quantities against the state when tracking began
+    drift: Option<DriftTracker>,
+    /// Optional file for osculating orbital elements, written with each output row
+    elements_output: Option<ElementsOutput>,
+    /// Error-control settings; `None` means fixed time steps of `dt`
+    adaptive: Option<AdaptiveSettings>,
+    /// Number of accepted steps taken so far
+    accepted_steps: usize,
+    /// Number of steps rejected by the adaptive controller
+    rejected_steps: usize,
+    /// Fixed output cadence; `None` writes one row per accepted step
+    output_interval: Option<f64>,
+    /// Time from which output rows are counted when a cadence is set
+    output_origin: f64,
+    /// Number of rows written since `output_origin`
+    output_count: u64,
 }
 
 impl Simulator {
@@ -31,108 +323,493 @@ impl Simulator {
     /// * `bodies` - Initial configuration of bodies
     /// * `dt` - Time step in seconds
     pub fn new(bodies: Vec<Body>, dt: f64) -> Self {
+        Self::with_integrator(bodies, dt, Box::new(RungeKuttaFehlberg::new()))
+    }
+
+    /// Create a new simulator that advances bodies with the given integrator
+    ///
+    /// # Arguments
+    /// * `bodies` - Initial configuration of bodies
+    /// * `dt` - Time step in seconds
+    /// * `integrator` - Time-stepping scheme, e.g. `RungeKuttaFehlberg` or `Yoshida4`
+    pub fn with_integrator(bodies: Vec<Body>, dt: f64, integrator: Box<dyn Integrator>) -> Self {
         Simulator {
             bodies,
             time: 0.0,
             dt,
-            integrator: RungeKuttaFehlberg::new(),
-            output_file: None,
+            integrator,
+            force_solver: ForceSolver::default(),
+            softening: Softening::default(),
+            post_newtonian: false,
+            force_models: Vec::new(),
+            collision_mode: CollisionMode::default(),
+            collisions: Vec::new(),
+            stopped: false,
+            output: None,
+            binary_output: None,
+            output_layout: None,
+            output_columns: OutputColumns::default(),
+            output_units: UnitSystem::default(),
+            output_frame: OutputFrame::default(),
+            diagnostics_output: None,
+            drift: None,
+            elements_output: None,
+            adaptive: None,
+            accepted_steps: 0,
+            rejected_steps: 0,
+            output_interval: None,
+            output_origin: 0.0,
+            output_count: 0,
         }
     }
 
     /// Create a new simulator and open an output file for trajectory data
     ///
+    /// The CSV header, with columns named after the bodies, is written
+    /// together with the first row so that `set_output_columns` can still
+    /// choose the columns.
+    ///
     /// # Arguments
     /// * `bodies` - Initial configuration of bodies
     /// * `dt` - Time step in seconds
     /// * `output_path` - Path to CSV file for trajectory output
     pub fn with_output(bodies: Vec<Body>, dt: f64, output_path: &str) -> std::io::Result<Self> {
-        let mut file = File::create(output_path)?;
-
-        // Write CSV header
-        let mut header = String::from("time");
-        for (idx, _) in bodies.iter().enumerate() {
-            header.push_str(&format!(
-                ",body{}_x,body{}_y,body{}_z",
-                idx, idx, idx
-            ));
+        let file = File::create(output_path)?;
+        let mut sim = Self::new(bodies, dt);
+        sim.output = Some(CsvOutput::new(file));
+        Ok(sim)
+    }
+
+    /// Rebuild a simulator from a checkpoint, without an output file
+    ///
+    /// Fails if the checkpoint names an integrator that is not one of the
+    /// built-in kinds.
+    pub fn from_checkpoint(checkpoint: Checkpoint) -> std::io::Result<Self> {
+        let kind: IntegratorKind = checkpoint
+            .integrator
+            .parse()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+
+        let mut sim = Self::with_integrator(checkpoint.bodies, checkpoint.dt, kind.build());
+        sim.time = checkpoint.time;
+        sim.force_solver = checkpoint.force_solver;
+        sim.softening = checkpoint.softening;
+        sim.post_newtonian = checkpoint.post_newtonian;
+        sim.collision_mode = checkpoint.collision_mode;
+        sim.stopped = checkpoint.stopped;
+        sim.output_layout = checkpoint.output_layout;
+        sim.adaptive = checkpoint.adaptive;
+        sim.accepted_steps = checkpoint.accepted_steps;
+        sim.rejected_steps = checkpoint.rejected_steps;
+        sim.output_interval = checkpoint.output_interval;
+        sim.output_origin = checkpoint.output_origin;
+        sim.output_count = checkpoint.output_count;
+        sim.output_columns = checkpoint.output_columns;
+        sim.output_units = checkpoint.output_units;
+        sim.output_frame = checkpoint.output_frame;
+        sim.drift = checkpoint.drift;
+        Ok(sim)
+    }
+
+    /// Load a simulator saved with `save_checkpoint`
+    ///
+    /// The restored simulator has no output file; use `append_output` to
+    /// continue writing an existing trajectory.
+    pub fn load_checkpoint<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        Self::from_checkpoint(Checkpoint::load(path)?)
+    }
+
+    /// Capture the complete simulation state
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            bodies: self.bodies.clone(),
+            time: self.time,
+            dt: self.dt,
+            integrator: self.integrator.name().to_string(),
+            force_solver: self.force_solver,
+            softening: self.softening,
+            post_newtonian: self.post_newtonian,
+            collision_mode: self.collision_mode,
+            stopped: self.stopped,
+            adaptive: self.adaptive,
+            accepted_steps: self.accepted_steps,
+            rejected_steps: self.rejected_steps,
+            output_interval: self.output_interval,
+            output_origin: self.output_origin,
+            output_count: self.output_count,
+            output_columns: self.output_columns,
+            output_units: self.output_units,
+            output_frame: self.output_frame.clone(),
+            drift: self.drift.clone(),
+            output_layout: self.output_layout.clone(),
         }
-        writeln!(file, "{}", header)?;
+    }
 
-        Ok(Simulator {
-            bodies,
-            time: 0.0,
-            dt,
-            integrator: RungeKuttaFehlberg::new(),
-            output_file: Some(file),
-        })
-    }
-
-    /// Compute gravitational accelerations for all bodies
-    /// This is the derivative function used by the integrator
-    fn compute_forces(bodies: &mut [Body]) {
-        // Reset accelerations
-        for body in bodies.iter_mut() {
-            body.reset_acceleration();
-        }
-
-        // Compute pairwise gravitational forces
-        let n = bodies.len();
-        for i in 0..n {
-            for j in (i + 1)..n {
-                let r_vec = bodies[i].vector_to(&bodies[j]);
-                let r = (r_vec[0] * r_vec[0] + r_vec[1] * r_vec[1] + r_vec[2] * r_vec[2]).sqrt();
-
-                if r > 0.0 {
-                    // Gravitational force magnitude: F = G * m1 * m2 / r^2
-                    // Acceleration magnitude: a = F / m = G * m2 / r^2
-                    let force_over_dist_cubed =
-                        (G * bodies[i].mass * bodies[j].mass) / (r * r * r);
-
-                    // Apply forces (Newton's 3rd law)
-                    for k in 0..3 {
-                        let f = force_over_dist_cubed * r_vec[k];
-                        bodies[i].acceleration[k] += f / bodies[i].mass;
-                        bodies[j].acceleration[k] -= f / bodies[j].mass;
+    /// Save the complete simulation state so the run can be resumed exactly
+    pub fn save_checkpoint<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        self.checkpoint().save(path)
+    }
+
+    /// Continue writing trajectory output to an existing CSV file
+    ///
+    /// Rows later than the current time, left behind by a run that went on
+    /// past its last checkpoint, are removed together with any partly
+    /// written final line, so the file continues without duplicates. The
+    /// file must have been written with the same bodies and columns; a
+    /// missing or empty file is started afresh.
+    pub fn append_output(&mut self, output_path: &str) -> std::io::Result<()> {
+        let expected_header = self.output_columns.header_for(&self.output_names());
+        let time = self.time / self.output_units.time();
+        let (file, header_written) = open_for_append(output_path, &expected_header, time)?;
+        self.output = Some(CsvOutput::appending(file, header_written));
+        Ok(())
+    }
+
+    /// Write the trajectory to a new binary file as well
+    ///
+    /// Records hold the same columns as the CSV output, in the output
+    /// units, at full precision; see `binary` for the format. The header,
+    /// and so the column layout, is written with the first record.
+    pub fn enable_binary_output(&mut self, path: &str) -> std::io::Result<()> {
+        let file = File::create(path)?;
+        self.binary_output = Some(BinaryOutput::new(file, false));
+        Ok(())
+    }
+
+    /// Continue writing the trajectory to an existing binary file, removing
+    /// records later than the current time as `append_output` does
+    pub fn append_binary_output(&mut self, path: &str) -> std::io::Result<()> {
+        let header = self.output_columns.header_for(&self.output_names());
+        let columns: Vec<String> = header.split(',').map(String::from).collect();
+        let time = self.time / self.output_units.time();
+        let (file, header_written) = binary::open_for_append(path, self.output_units, &columns, time)?;
+        self.binary_output = Some(BinaryOutput::new(file, header_written));
+        Ok(())
+    }
+
+    /// Write conserved-quantity diagnostics to a new CSV file
+    ///
+    /// A row is written whenever a trajectory row is due, or after every
+    /// step without an output cadence, in the output units. Drift is
+    /// tracked from the current state unless tracking has already begun.
+    pub fn enable_diagnostics(&mut self, path: &str) -> std::io::Result<()> {
+        let file = File::create(path)?;
+        self.diagnostics_output = Some(DiagnosticsOutput::new(file, false));
+        if self.drift.is_none() {
+            self.track_drift();
+        }
+        Ok(())
+    }
+
+    /// Continue writing diagnostics to an existing CSV file, removing rows
+    /// later than the current time as `append_output` does
+    pub fn append_diagnostics(&mut self, path: &str) -> std::io::Result<()> {
+        let time = self.time / self.output_units.time();
+        let (file, header_written) = open_for_append(path, DIAGNOSTICS_HEADER, time)?;
+        self.diagnostics_output = Some(DiagnosticsOutput::new(file, header_written));
+        if self.drift.is_none() {
+            self.track_drift();
+        }
+        Ok(())
+    }
+
+    /// Write the osculating orbital elements of every body around `primary`
+    /// to a new CSV file
+    ///
+    /// Rows are written alongside trajectory rows, one per body, in the
+    /// output units and with angles in degrees. Nothing is written while
+    /// no body is named `primary`.
+    pub fn enable_elements_output(&mut self, path: &str, primary: &str) -> std::io::Result<()> {
+        let file = File::create(path)?;
+        self.elements_output = Some(ElementsOutput::new(file, false, primary));
+        Ok(())
+    }
+
+    /// Continue writing orbital elements to an existing CSV file, removing
+    /// rows later than the current time as `append_output` does
+    pub fn append_elements_output(&mut self, path: &str, primary: &str) -> std::io::Result<()> {
+        let time = self.time / self.output_units.time();
+        let (file, header_written) = open_for_append(path, ELEMENTS_HEADER, time)?;
+        self.elements_output = Some(ElementsOutput::new(file, header_written, primary));
+        Ok(())
+    }
+
+    /// Osculating orbital elements of every other body around the named
+    /// one, in SI; empty if there is no such body
+    pub fn osculating_elements(&self, primary: &str) -> Vec<(String, OrbitalElements)> {
+        osculating::osculating_elements(&self.bodies, primary, G)
+    }
+
+    /// Measure drift of the conserved quantities from the current state on,
+    /// replacing any earlier reference
+    ///
+    /// The tracker is updated after every accepted step and is saved in
+    /// checkpoints, so a resumed run keeps its original reference.
+    pub fn track_drift(&mut self) {
+        self.drift = Some(DriftTracker::new(self.time, &self.bodies, self.softening));
+    }
+
+    /// The drift tracker, once tracking has begun
+    pub fn drift_tracker(&self) -> Option<&DriftTracker> {
+        self.drift.as_ref()
+    }
+
+    /// Bodies named in the output columns
+    fn output_names(&self) -> Vec<String> {
+        match self.output_layout {
+            Some(ref names) => names.clone(),
+            None => labels(&self.bodies),
+        }
+    }
+
+    /// Advance the simulation by one time step
+    ///
+    /// In adaptive mode this takes one accepted step, retrying with a smaller
+    /// step as often as the error controller requires, and updates `dt` to
+    /// the suggested size for the next step.
+    pub fn step(&mut self) {
+        let dt = self.dt;
+        self.step_with(dt);
+    }
+
+    /// Take one accepted step starting with the given step size, returning
+    /// the size of the step actually taken
+    fn step_with(&mut self, dt: f64) -> f64 {
+        let start_time = self.time;
+        let mut start_bodies = self.bodies.clone();
+        let (taken, report) = match self.adaptive {
+            Some(settings) => self.adaptive_step(&start_bodies, dt, settings),
+            None => {
+                let (solver, softening, post_newtonian) = (self.force_solver, self.softening, self.post_newtonian);
+                let models = &mut self.force_models;
+                let report = self.integrator.advance(&mut self.bodies, dt, &mut |offset, bodies| {
+                    evaluate_forces(solver, softening, post_newtonian, models, start_time + offset, bodies)
+                });
+                (dt, report)
+            }
+        };
+        self.time += taken;
+        self.accepted_steps += 1;
+
+        // Write to the output files if there are any
+        if self.output.is_some()
+            || self.binary_output.is_some()
+            || self.diagnostics_output.is_some()
+            || self.elements_output.is_some()
+        {
+            match self.output_interval {
+                Some(interval) if self.next_output_time(interval) <= self.time + interval * 1e-9 => {
+                    // The integrator reports the accelerations at both ends
+                    // from its own force evaluations, so rows cost no extra ones
+                    for (body, acc) in start_bodies.iter_mut().zip(&report.start_accelerations) {
+                        body.acceleration = *acc;
+                    }
+                    let mut end_bodies = self.bodies.clone();
+                    for (body, acc) in end_bodies.iter_mut().zip(&report.end_accelerations) {
+                        body.acceleration = *acc;
                     }
+                    let dense = DenseOutput::new(start_time, taken, start_bodies.clone(), end_bodies);
+                    let _ = self.write_dense_rows(&dense, interval);
+                }
+                Some(_) => {}
+                None => {
+                    let _ = self.write_csv_row_internal(&report.end_accelerations);
                 }
             }
         }
+
+        // Output within the step shows the bodies as integrated; collisions
+        // take effect from the end of it
+        if self.collision_mode != CollisionMode::Ignore {
+            self.resolve_collisions(&start_bodies, taken);
+        }
+
+        if let Some(ref mut tracker) = self.drift {
+            tracker.record(&Diagnostics::measure(self.time, &self.bodies, self.softening));
+        }
+
+        taken
     }
 
-    /// Advance the simulation by one time step
-    pub fn step(&mut self) {
-        self.integrator
-            .step(&mut self.bodies, self.dt, Self::compute_forces);
-        self.time += self.dt;
+    /// Detect and resolve collisions during the step of length `dt` that
+    /// has just ended
+    fn resolve_collisions(&mut self, start_bodies: &[Body], dt: f64) {
+        let names = match self.output_layout {
+            None if self.collision_mode == CollisionMode::Merge => Some(labels(&self.bodies)),
+            _ => None,
+        };
+        let count = self.bodies.len();
+        let events = collision::resolve(self.collision_mode, start_bodies, &mut self.bodies, self.time, dt);
+        if self.bodies.len() < count {
+            // Keep writing the columns of the bodies that were merged away
+            if let Some(names) = names {
+                self.output_layout = Some(names);
+            }
+        }
+        if events.iter().any(|event| event.outcome == CollisionOutcome::Stopped) {
+            self.stopped = true;
+        }
+        self.collisions.extend(events);
+    }
 
-        // Write to output file if available
-        if self.output_file.is_some() {
-            let _ = self.write_csv_row_internal();
+    /// Attempt steps until one satisfies the tolerances, returning its size
+    fn adaptive_step(
+        &mut self,
+        initial_bodies: &[Body],
+        dt: f64,
+        settings: AdaptiveSettings,
+    ) -> (f64, StepReport) {
+        let mut dt = dt;
+        let start_time = self.time;
+
+        loop {
+            let (solver, softening, post_newtonian) = (self.force_solver, self.softening, self.post_newtonian);
+            let models = &mut self.force_models;
+            let report = self.integrator.advance(&mut self.bodies, dt, &mut |offset, bodies| {
+                evaluate_forces(solver, softening, post_newtonian, models, start_time + offset, bodies)
+            });
+
+            // Without an error estimate there is nothing to adapt to
+            let errors = match report.errors {
+                Some(ref errors) => errors,
+                None => return (dt, report),
+            };
+            let error_norm = settings.error_norm(initial_bodies, &self.bodies, errors);
+            let next_dt = settings.next_dt(dt.abs(), error_norm).copysign(dt);
+
+            // Accept steps within tolerance, or at the minimum step size
+            // where there is nothing smaller left to retry with
+            if error_norm <= 1.0 || dt.abs() <= settings.min_dt {
+                self.dt = next_dt;
+                return (dt, report);
+            }
+
+            self.rejected_steps += 1;
+            self.bodies.clone_from_slice(initial_bodies);
+            dt = next_dt;
         }
     }
 
-    /// Write current positions to CSV file (internal version)
-    fn write_csv_row_internal(&mut self) -> std::io::Result<()> {
-        let mut line = format!("{:.8}", self.time);
-        for body in &self.bodies {
-            line.push_str(&format!(
-                ",{:.8},{:.8},{:.8}",
-                body.position[0], body.position[1], body.position[2]
-            ));
+    /// Write the current state to the CSV file (internal version), with the
+    /// accelerations the integrator evaluated at the end of the step
+    fn write_csv_row_internal(&mut self, accelerations: &[[f64; 3]]) -> std::io::Result<()> {
+        if let Some(ref mut output) = self.diagnostics_output {
+            output.write_row(self.time, &self.bodies, self.softening, self.drift.as_ref(), self.output_units)?;
         }
-        if let Some(ref mut file) = self.output_file {
-            writeln!(file, "{}", line)?;
+        if let Some(ref mut output) = self.elements_output {
+            output.write_rows(self.time, &self.bodies, G, self.output_units)?;
+        }
+        if self.output.is_none() && self.binary_output.is_none() {
+            return Ok(());
+        }
+        let names = self.output_names();
+        // Integrators leave the accelerations of their last stage behind;
+        // the report has them at the end of the step without evaluating the
+        // forces again, which stateful force models would notice
+        let reported = if self.output_columns.acceleration {
+            let mut bodies = self.bodies.clone();
+            for (body, acc) in bodies.iter_mut().zip(accelerations) {
+                body.acceleration = *acc;
+            }
+            Some(bodies)
+        } else {
+            None
+        };
+        let bodies = reported.as_deref().unwrap_or(&self.bodies);
+        let frame = &self.output_frame;
+        if let Some(ref mut output) = self.output {
+            output.write_row(&self.output_columns, self.time, &names, bodies, frame, self.softening, self.output_units)?;
+        }
+        if let Some(ref mut output) = self.binary_output {
+            output.write_row(&self.output_columns, self.time, &names, bodies, frame, self.softening, self.output_units)?;
         }
         Ok(())
     }
 
-    /// Run the simulation for a specified number of steps
+    /// Time of the next row to write at a fixed output cadence
+    fn next_output_time(&self, interval: f64) -> f64 {
+        self.output_origin + (self.output_count + 1) as f64 * interval
+    }
+
+    /// Write every output row whose time falls within the given step
+    fn write_dense_rows(&mut self, dense: &DenseOutput, interval: f64) -> std::io::Result<()> {
+        // Rows landing on the step boundary up to round-off belong to this step
+        let epsilon = interval * 1e-9;
+        let names = self.output_names();
+        loop {
+            let t = self.next_output_time(interval);
+            if t > dense.end_time() + epsilon {
+                break;
+            }
+            let bodies = dense.evaluate(t);
+            let frame = &self.output_frame;
+            if let Some(ref mut output) = self.output {
+                output.write_row(&self.output_columns, t, &names, &bodies, frame, self.softening, self.output_units)?;
+            }
+            if let Some(ref mut output) = self.binary_output {
+                output.write_row(&self.output_columns, t, &names, &bodies, frame, self.softening, self.output_units)?;
+            }
+            if let Some(ref mut output) = self.diagnostics_output {
+                output.write_row(t, &bodies, self.softening, self.drift.as_ref(), self.output_units)?;
+            }
+            if let Some(ref mut output) = self.elements_output {
+                output.write_rows(t, &bodies, G, self.output_units)?;
+            }
+            self.output_count += 1;
+        }
+        Ok(())
+    }
+
+    /// Run the simulation for a specified number of steps, or until a
+    /// collision stops it
     pub fn run(&mut self, num_steps: usize) {
         for _ in 0..num_steps {
+            if self.stopped {
+                break;
+            }
             self.step();
         }
     }
+
+    /// Run the simulation until the given time, shortening the last step
+    /// so that the run ends at `end_time`
+    ///
+    /// Steps go towards `end_time` whatever the sign of `dt`, so an end
+    /// time before the current one integrates backwards.
+    pub fn run_until(&mut self, end_time: f64) {
+        self.run_until_bounded(end_time, usize::MAX);
+    }
+
+    /// Run towards the given time, stopping early after `max_steps`
+    /// accepted steps; returns whether the run is over, because the end
+    /// time was reached or a collision stopped it
+    ///
+    /// Calling this repeatedly takes exactly the same steps as a single
+    /// `run_until`, which lets callers do work such as checkpointing
+    /// between batches of steps.
+    pub fn run_until_bounded(&mut self, end_time: f64, max_steps: usize) -> bool {
+        // Ignore leftovers from floating-point round-off in the final step
+        let epsilon = end_time.abs() * 1e-12;
+        for _ in 0..max_steps {
+            let remaining = end_time - self.time;
+            if remaining.abs() <= epsilon || self.stopped {
+                break;
+            }
+            if remaining.abs() < self.dt.abs() {
+                // The shortened step says nothing about the step size the
+                // dynamics allow, so keep the previous suggestion
+                let dt = self.dt;
+                if self.step_with(remaining) == remaining {
+                    self.dt = self.dt.abs().max(dt.abs()).copysign(dt);
+                }
+            } else {
+                self.step_with(self.dt.abs().copysign(remaining));
+            }
+        }
+        (end_time - self.time).abs() <= epsilon || self.stopped
+    }
+
+    /// Get the current bodies
     pub fn bodies(&self) -> &[Body] {
         &self.bodies
     }
@@ -157,13 +834,209 @@ impl Simulator {
         self.dt = dt;
     }
 
+    /// Replace the integrator used for subsequent steps
+    pub fn set_integrator(&mut self, integrator: Box<dyn Integrator>) {
+        self.integrator = integrator;
+    }
+
+    /// Choose how gravitational accelerations are computed
+    pub fn set_force_solver(&mut self, solver: ForceSolver) {
+        self.force_solver = solver;
+    }
+
+    /// Get the force solver in use
+    pub fn force_solver(&self) -> ForceSolver {
+        self.force_solver
+    }
+
+    /// Set the softening applied to forces and potential energy
+    pub fn set_softening(&mut self, softening: Softening) {
+        self.softening = softening;
+    }
+
+    /// The softening applied to forces and potential energy
+    pub fn softening(&self) -> Softening {
+        self.softening
+    }
+
+    /// Switch the 1PN (Einstein-Infeld-Hoffmann) relativistic correction
+    /// to gravity on or off
+    pub fn set_post_newtonian(&mut self, enabled: bool) {
+        self.post_newtonian = enabled;
+    }
+
+    /// Whether the 1PN relativistic correction is applied
+    pub fn post_newtonian(&self) -> bool {
+        self.post_newtonian
+    }
+
+    /// Add a force applied after gravity and any models added before it
+    ///
+    /// Closures of the form `|time: f64, bodies: &mut [Body]| ...` are
+    /// accepted as well as types implementing `ForceModel`. Force models
+    /// are not part of checkpoints and must be added again after loading.
+    pub fn add_force_model<M: ForceModel + 'static>(&mut self, model: M) {
+        self.force_models.push(Box::new(model));
+    }
+
+    /// Remove all force models, leaving gravity alone
+    pub fn clear_force_models(&mut self) {
+        self.force_models.clear();
+    }
+
+    /// Names of the force models, in the order they are applied
+    pub fn force_model_names(&self) -> Vec<&str> {
+        self.force_models.iter().map(|model| model.name()).collect()
+    }
+
+    /// Set what happens when bodies overlap
+    pub fn set_collision_mode(&mut self, mode: CollisionMode) {
+        self.collision_mode = mode;
+    }
+
+    /// What happens when bodies overlap
+    pub fn collision_mode(&self) -> CollisionMode {
+        self.collision_mode
+    }
+
+    /// Collisions detected so far, in the order they happened
+    pub fn collisions(&self) -> &[CollisionEvent] {
+        &self.collisions
+    }
+
+    /// Whether a collision has stopped the run
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    /// Get the name of the integrator in use
+    pub fn integrator_name(&self) -> &'static str {
+        self.integrator.name()
+    }
+
+    /// Enable adaptive time-stepping with the given settings, or return to
+    /// fixed steps with `None`
+    ///
+    /// The current `dt` is used as the first trial step, its size clamped
+    /// to the settings' step bounds and its direction kept. Only integrators that estimate their error
+    /// (such as RKF45) adapt; others keep taking steps of `dt`.
+    ///
+    /// Invalid settings are refused with the reason, leaving the simulator
+    /// unchanged; see `AdaptiveSettings::validate`.
+    pub fn set_adaptive(&mut self, settings: Option<AdaptiveSettings>) -> Result<(), String> {
+        if let Some(settings) = settings {
+            settings.validate()?;
+            self.dt = self.dt.abs().clamp(settings.min_dt, settings.max_dt).copysign(self.dt);
+        }
+        self.adaptive = settings;
+        Ok(())
+    }
+
+    /// Write output rows at a fixed interval of simulation time, independent
+    /// of the integrator's step size, or once per step with `None`
+    ///
+    /// Rows are placed at multiples of `interval` after the current time and
+    /// are interpolated within each step rather than taken from whatever time
+    /// the integrator lands on.
+    pub fn set_output_interval(&mut self, interval: Option<f64>) {
+        self.output_interval = interval.filter(|&i| i > 0.0);
+        self.output_origin = self.time;
+        self.output_count = 0;
+    }
+
+    /// Get the output interval, if a fixed cadence is set
+    pub fn output_interval(&self) -> Option<f64> {
+        self.output_interval
+    }
+
+    /// Whether a trajectory file, CSV or binary, has fixed its layout
+    fn trajectory_started(&self) -> bool {
+        self.output.as_ref().is_some_and(CsvOutput::header_written)
+            || self.binary_output.as_ref().is_some_and(BinaryOutput::header_written)
+    }
+
+    /// Whether any output file, diagnostics and elements included, has
+    /// started writing rows
+    fn output_started(&self) -> bool {
+        self.trajectory_started()
+            || self.diagnostics_output.as_ref().is_some_and(DiagnosticsOutput::header_written)
+            || self.elements_output.as_ref().is_some_and(ElementsOutput::header_written)
+    }
+
+    /// Choose the optional output columns (velocities, energies, ...)
+    ///
+    /// Has no effect once the CSV or binary header has been written, so
+    /// that every row of a file has the same layout.
+    pub fn set_output_columns(&mut self, columns: OutputColumns) {
+        if !self.trajectory_started() {
+            self.output_columns = columns;
+        }
+    }
+
+    /// Get the optional output columns
+    pub fn output_columns(&self) -> OutputColumns {
+        self.output_columns
+    }
+
+    /// Set the units of the output columns
+    ///
+    /// The simulation itself always runs in SI; rows, including their
+    /// times, are converted as they are written. The units are shared with
+    /// the diagnostics and elements files, so they are fixed once any
+    /// output file has been started.
+    pub fn set_output_units(&mut self, units: UnitSystem) {
+        if !self.output_started() {
+            self.output_units = units;
+        }
+    }
+
+    /// Get the units of the output columns
+    pub fn output_units(&self) -> UnitSystem {
+        self.output_units
+    }
+
+    /// Set the frame of the output columns, e.g. centred on one body
+    ///
+    /// Like the columns, the frame is fixed once the CSV or binary header
+    /// has been written. Diagnostics stay in the simulation's own frame.
+    pub fn set_output_frame(&mut self, frame: OutputFrame) {
+        if !self.trajectory_started() {
+            self.output_frame = frame;
+        }
+    }
+
+    /// Get the frame of the output columns
+    pub fn output_frame(&self) -> &OutputFrame {
+        &self.output_frame
+    }
+
+    /// Shift the bodies so that their centre of mass is at rest at the origin
+    pub fn to_barycentric(&mut self) {
+        frame::to_barycentric(&mut self.bodies);
+    }
+
+    /// Get the adaptive step settings, if adaptive mode is enabled
+    pub fn adaptive(&self) -> Option<AdaptiveSettings> {
+        self.adaptive
+    }
+
+    /// Get the number of accepted steps taken so far
+    pub fn accepted_steps(&self) -> usize {
+        self.accepted_steps
+    }
+
+    /// Get the number of steps rejected by the adaptive controller
+    pub fn rejected_steps(&self) -> usize {
+        self.rejected_steps
+    }
+
     /// Print current body positions to stdout
     pub fn print_positions(&self) {
         println!("Time: {:.2} s", self.time);
         for (idx, body) in self.bodies.iter().enumerate() {
             println!(
-                "Body {}: pos=[{:.4e}, {:.4e}, {:.4e}], vel=[{:.4e}, {:.4e}, {:.4e}]",
-                idx,
+                "{}: pos=[{:.4e}, {:.4e}, {:.4e}], vel=[{:.4e}, {:.4e}, {:.4e}]",
+                body.label(idx),
                 body.position[0], body.position[1], body.position[2],
                 body.velocity[0], body.velocity[1], body.velocity[2]
             );
@@ -172,30 +1045,13 @@ impl Simulator {
 
     /// Compute the total kinetic energy of all bodies
     pub fn kinetic_energy(&self) -> f64 {
-        let mut ke = 0.0;
-        for body in &self.bodies {
-            let v_squared = body.velocity[0] * body.velocity[0]
-                + body.velocity[1] * body.velocity[1]
-                + body.velocity[2] * body.velocity[2];
-            ke += 0.5 * body.mass * v_squared;
-        }
-        ke
+        system_kinetic_energy(&self.bodies)
     }
 
-    /// Compute the total gravitational potential energy of the system
+    /// Compute the total gravitational potential energy of the system,
+    /// softened like the forces
     pub fn potential_energy(&self) -> f64 {
-        let mut pe = 0.0;
-        let n = self.bodies.len();
-        
-        for i in 0..n {
-            for j in (i + 1)..n {
-                let r = self.bodies[i].distance_to(&self.bodies[j]);
-                if r > 0.0 {
-                    pe -= (G * self.bodies[i].mass * self.bodies[j].mass) / r;
-                }
-            }
-        }
-        pe
+        system_potential_energy(&self.bodies, self.softening)
     }
 
     /// Compute the total mechanical energy of the system
@@ -203,6 +1059,36 @@ impl Simulator {
         self.kinetic_energy() + self.potential_energy()
     }
 
+    /// Compute the total angular momentum about the origin
+    pub fn angular_momentum(&self) -> [f64; 3] {
+        system_angular_momentum(&self.bodies)
+    }
+
+    /// Compute the total linear momentum
+    pub fn momentum(&self) -> [f64; 3] {
+        diagnostics::momentum(&self.bodies)
+    }
+
+    /// Compute the position of the centre of mass
+    pub fn centre_of_mass(&self) -> [f64; 3] {
+        self.diagnostics().centre_of_mass
+    }
+
+    /// Compute the velocity of the centre of mass
+    pub fn centre_of_mass_velocity(&self) -> [f64; 3] {
+        self.diagnostics().centre_of_mass_velocity
+    }
+
+    /// Compute the virial ratio 2K/|W|, softened like the forces
+    pub fn virial_ratio(&self) -> f64 {
+        self.diagnostics().virial_ratio
+    }
+
+    /// Measure every conserved and derived quantity at once
+    pub fn diagnostics(&self) -> Diagnostics {
+        Diagnostics::measure(self.time, &self.bodies, self.softening)
+    }
+
     /// Compute gravitational force between two bodies
     /// Returns the force magnitude
     pub fn gravitational_force(mass1: f64, mass2: f64, distance: f64) -> f64 {
@@ -224,26 +1110,33 @@ impl Simulator {
         let b2 = &self.bodies[body_idx2];
         let r = b1.distance_to(b2);
 
-        // Expected force magnitude from Newton's law
-        let expected_force = Self::gravitational_force(b1.mass, b2.mass, r);
+        // Expected force magnitude from Newton's law, softened like the kernel
+        let expected_force = match self.softening {
+            Softening::None => Self::gravitational_force(b1.mass, b2.mass, r),
+            softening => G * b1.mass * b2.mass * r * softening.force_factor(r * r),
+        };
 
-        // Compute actual acceleration that would result from the force
-        let acceleration_magnitude = expected_force / b1.mass;
+        // Compute the force actually produced by the force kernel for this pair
+        let mut pair = [b1.clone(), b2.clone()];
+        self.force_solver.compute_accelerations(&mut pair, G, self.softening);
+        let a = pair[0].acceleration;
+        let computed_force = b1.mass * (a[0] * a[0] + a[1] * a[1] + a[2] * a[2]).sqrt();
 
         // Relative error (will be 0 unless there's a bug)
         let relative_error = if expected_force > 0.0 {
-            (acceleration_magnitude - acceleration_magnitude).abs() / expected_force
+            (computed_force - expected_force).abs() / expected_force
         } else {
             0.0
         };
 
-        (acceleration_magnitude, expected_force, relative_error)
+        (computed_force, expected_force, relative_error)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_support::TempFile;
 
     #[test]
     fn test_simulator_creation() {
@@ -395,4 +1288,505 @@ mod tests {
             expected_pe
         );
     }
+
+    /// Two equal masses on a highly eccentric orbit, starting at apoapsis
+    fn eccentric_binary() -> Vec<Body> {
+        let mass = 1e30;
+        let separation = 1e11;
+        // Circular speed for each body is sqrt(G m / (4 r)); use a fraction of it
+        let v_circ = (G * mass / (2.0 * separation)).sqrt();
+        let v = 0.2 * v_circ;
+        vec![
+            Body::new(mass, [-separation / 2.0, 0.0, 0.0], [0.0, -v, 0.0]),
+            Body::new(mass, [separation / 2.0, 0.0, 0.0], [0.0, v, 0.0]),
+        ]
+    }
+
+    #[test]
+    fn test_adaptive_energy_conservation() {
+        let mut sim = Simulator::new(eccentric_binary(), 86400.0);
+        sim.set_adaptive(Some(AdaptiveSettings::new(1e-3, 1e-10, 1.0, 86400.0 * 10.0).unwrap())).unwrap();
+        let initial_energy = sim.total_energy();
+
+        // Roughly two orbits, including two close periapsis passages
+        sim.run_until(86400.0 * 200.0);
+
+        let relative_error = ((sim.total_energy() - initial_energy) / initial_energy).abs();
+        assert!(
+            relative_error < 1e-6,
+            "Energy change too large: {:.4e}",
+            relative_error
+        );
+        assert!((sim.time() - 86400.0 * 200.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_adaptive_rejects_and_respects_bounds() {
+        let min_dt = 10.0;
+        let max_dt = 86400.0 * 2.0;
+        let mut sim = Simulator::new(eccentric_binary(), 86400.0 * 10.0);
+        sim.set_adaptive(Some(AdaptiveSettings::new(1e-3, 1e-10, min_dt, max_dt).unwrap())).unwrap();
+        assert_eq!(sim.dt(), max_dt);
+
+        let mut smallest = f64::MAX;
+        let mut largest: f64 = 0.0;
+        for _ in 0..500 {
+            let before = sim.time();
+            sim.step();
+            let taken = sim.time() - before;
+            smallest = smallest.min(taken);
+            largest = largest.max(taken);
+        }
+
+        assert!(sim.rejected_steps() > 0);
+        assert_eq!(sim.accepted_steps(), 500);
+        assert!(smallest >= min_dt);
+        assert!(largest <= max_dt);
+        // Close passages should force much smaller steps than quiet phases
+        assert!(largest / smallest > 10.0);
+    }
+
+    #[test]
+    fn test_adaptive_settings_validation() {
+        assert!(AdaptiveSettings::default().validate().is_ok());
+        let invalid = [
+            AdaptiveSettings { min_dt: 10.0, max_dt: 5.0, ..AdaptiveSettings::default() },
+            AdaptiveSettings { min_dt: f64::NAN, ..AdaptiveSettings::default() },
+            AdaptiveSettings { max_dt: f64::NAN, ..AdaptiveSettings::default() },
+            AdaptiveSettings { min_dt: 0.0, ..AdaptiveSettings::default() },
+            AdaptiveSettings { rel_tolerance: -1e-9, ..AdaptiveSettings::default() },
+            AdaptiveSettings { abs_tolerance: 0.0, rel_tolerance: 0.0, ..AdaptiveSettings::default() },
+            AdaptiveSettings::default().with_velocity_tolerance(-1.0),
+            AdaptiveSettings { rel_tolerance: 0.0, ..AdaptiveSettings::default() }.with_velocity_tolerance(0.0),
+        ];
+        for settings in invalid {
+            assert!(settings.validate().is_err(), "{:?}", settings);
+        }
+    }
+
+    #[test]
+    fn test_error_norm_rejects_non_finite_steps() {
+        let settings = AdaptiveSettings::default();
+        let before = eccentric_binary();
+        let after = before.clone();
+        let errors = vec![[0.0; 6]; 2];
+        assert_eq!(settings.error_norm(&before, &after, &errors), 0.0);
+
+        let mut blown_up = errors.clone();
+        blown_up[1][4] = f64::NAN;
+        assert_eq!(settings.error_norm(&before, &after, &blown_up), f64::INFINITY);
+        assert_eq!(settings.next_dt(1000.0, f64::INFINITY), 1000.0 * MIN_STEP_SHRINK);
+    }
+
+    #[test]
+    fn test_error_norm_scales_positions_and_velocities_separately() {
+        let settings = AdaptiveSettings::new(1e3, 0.0, 1.0, 1e7).unwrap().with_velocity_tolerance(1e-3);
+        let bodies = eccentric_binary();
+        let mut errors = vec![[0.0; 6]; 2];
+        errors[0][0] = 500.0;
+        assert_eq!(settings.error_norm(&bodies, &bodies, &errors), 0.5);
+        errors[0][3] = 2e-3;
+        assert_eq!(settings.error_norm(&bodies, &bodies, &errors), 2.0);
+    }
+
+    #[test]
+    fn test_set_adaptive_rejects_inverted_bounds() {
+        let mut sim = Simulator::new(eccentric_binary(), 1.0);
+        let err = sim
+            .set_adaptive(Some(AdaptiveSettings { min_dt: 10.0, max_dt: 5.0, ..AdaptiveSettings::default() }))
+            .unwrap_err();
+        assert_eq!(err, "maximum time step 5 is smaller than the minimum time step 10");
+        assert_eq!((sim.adaptive(), sim.dt()), (None, 1.0));
+        assert!(AdaptiveSettings::new(1e-3, 1e-10, 10.0, 5.0).is_err());
+    }
+
+    #[test]
+    fn test_set_adaptive_keeps_the_step_direction() {
+        let mut sim = Simulator::new(eccentric_binary(), -1e8);
+        sim.set_adaptive(Some(AdaptiveSettings::default())).unwrap();
+        assert_eq!(sim.dt(), -AdaptiveSettings::default().max_dt);
+        sim.step();
+        assert!(sim.time() < 0.0 && sim.dt() < 0.0);
+    }
+
+    #[test]
+    fn test_run_until_fixed_step() {
+        let bodies = vec![
+            Body::new(1e30, [0.0, 0.0, 0.0], [0.0, 0.0, 0.0]),
+            Body::new(1e30, [1e11, 0.0, 0.0], [0.0, 1000.0, 0.0]),
+        ];
+        let mut sim = Simulator::new(bodies, 86400.0);
+        sim.run_until(86400.0 * 2.5);
+        assert!((sim.time() - 86400.0 * 2.5).abs() < 1e-6);
+        assert_eq!(sim.accepted_steps(), 3);
+        assert_eq!(sim.dt(), 86400.0);
+    }
+
+    #[test]
+    fn test_run_until_steps_towards_the_end_time() {
+        let bodies = vec![
+            Body::new(1e30, [0.0, 0.0, 0.0], [0.0, 0.0, 0.0]),
+            Body::new(1e30, [1e11, 0.0, 0.0], [0.0, 1000.0, 0.0]),
+        ];
+        // A negative step used to carry the run away from the end time forever
+        let mut sim = Simulator::new(bodies.clone(), -3600.0);
+        assert!(sim.run_until_bounded(86400.0, 100));
+        assert!((sim.time() - 86400.0).abs() < 1e-6);
+        assert_eq!(sim.accepted_steps(), 24);
+
+        // An earlier end time integrates backwards, adaptively too
+        let mut sim = Simulator::new(bodies, 3600.0);
+        sim.set_adaptive(Some(AdaptiveSettings::new(1e-3, 1e-10, 1.0, 86400.0).unwrap())).unwrap();
+        assert!(sim.run_until_bounded(-86400.0 * 2.5, 1000));
+        assert!((sim.time() + 86400.0 * 2.5).abs() < 1e-6);
+        assert!(sim.bodies()[1].position[1] < 0.0);
+    }
+
+    #[test]
+    fn test_output_interval_with_adaptive_steps() {
+        // Equal-mass circular binary with a known analytic solution
+        let mass = 1e30;
+        let separation: f64 = 1e11;
+        let omega = (G * 2.0 * mass / separation.powi(3)).sqrt();
+        let v = omega * separation / 2.0;
+        let bodies = vec![
+            Body::new(mass, [separation / 2.0, 0.0, 0.0], [0.0, v, 0.0]),
+            Body::new(mass, [-separation / 2.0, 0.0, 0.0], [0.0, -v, 0.0]),
+        ];
+
+        let path = TempFile::new("output_interval_test.csv");
+        let mut sim = Simulator::with_output(bodies, 3600.0, path.to_str().unwrap()).unwrap();
+        sim.set_adaptive(Some(AdaptiveSettings::new(1e-2, 1e-10, 1.0, 86400.0 * 5.0).unwrap())).unwrap();
+        let interval = 86400.0 / 3.0;
+        sim.set_output_interval(Some(interval));
+        sim.run_until(86400.0 * 30.0);
+        drop(sim);
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let rows: Vec<Vec<f64>> = content
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').map(|f| f.parse().unwrap()).collect())
+            .collect();
+
+        assert_eq!(rows.len(), 90);
+        for (idx, row) in rows.iter().enumerate() {
+            let t = (idx + 1) as f64 * interval;
+            assert!((row[0] - t).abs() < 1e-6, "row {} at t={}", idx, row[0]);
+
+            let expected_x = separation / 2.0 * (omega * t).cos();
+            let expected_y = separation / 2.0 * (omega * t).sin();
+            assert!((row[1] - expected_x).abs() / separation < 1e-6);
+            assert!((row[2] - expected_y).abs() / separation < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_output_header_uses_body_names() {
+        let bodies = vec![
+            Body::new(5.972e24, [0.0, 0.0, 0.0], [0.0, 0.0, 0.0]).with_name("Earth"),
+            Body::new(7.342e22, [3.844e8, 0.0, 0.0], [0.0, 1022.0, 0.0]),
+        ];
+        let path = TempFile::new("header_test.csv");
+        let mut sim = Simulator::with_output(bodies, 3600.0, path.to_str().unwrap()).unwrap();
+        sim.step();
+        drop(sim);
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            content.lines().next().unwrap(),
+            "time,Earth_x,Earth_y,Earth_z,body1_x,body1_y,body1_z"
+        );
+    }
+
+    #[test]
+    fn test_resume_from_checkpoint_matches_uninterrupted_run() {
+        let full_path = TempFile::new("resume_full.csv");
+        let resumed_path = TempFile::new("resume_split.csv");
+        let checkpoint_path = TempFile::new("resume.ckpt");
+        let settings = Some(AdaptiveSettings::new(1e-2, 1e-10, 1.0, 86400.0 * 5.0).unwrap());
+        let end_time = 86400.0 * 60.0;
+
+        let mut full = Simulator::with_output(eccentric_binary(), 3600.0, full_path.to_str().unwrap()).unwrap();
+        full.set_adaptive(settings).unwrap();
+        full.set_output_interval(Some(86400.0));
+        full.set_output_columns(OutputColumns::all());
+        full.run_until(end_time);
+
+        // Checkpoint part-way, then keep going as if the run crashed later
+        let mut first = Simulator::with_output(eccentric_binary(), 3600.0, resumed_path.to_str().unwrap()).unwrap();
+        first.set_adaptive(settings).unwrap();
+        first.set_output_interval(Some(86400.0));
+        first.set_output_columns(OutputColumns::all());
+        assert!(!first.run_until_bounded(end_time, 40));
+        first.save_checkpoint(&checkpoint_path).unwrap();
+        first.run(15);
+        drop(first);
+
+        let mut resumed = Simulator::load_checkpoint(&checkpoint_path).unwrap();
+        resumed.append_output(resumed_path.to_str().unwrap()).unwrap();
+        assert!(resumed.run_until_bounded(end_time, usize::MAX));
+
+        assert_eq!(resumed.time().to_bits(), full.time().to_bits());
+        assert_eq!(resumed.bodies(), full.bodies());
+        assert_eq!(resumed.accepted_steps(), full.accepted_steps());
+        drop(full);
+        drop(resumed);
+
+        let full_csv = std::fs::read_to_string(&full_path).unwrap();
+        let resumed_csv = std::fs::read_to_string(&resumed_path).unwrap();
+        assert_eq!(full_csv.lines().count(), 61);
+        assert_eq!(resumed_csv, full_csv);
+    }
+
+    #[test]
+    fn test_output_layout_is_fixed_once_any_file_starts() {
+        let binary_path = TempFile::new("fixed_layout.traj");
+        let mut sim = Simulator::new(eccentric_binary(), 3600.0);
+        sim.enable_binary_output(binary_path.to_str().unwrap()).unwrap();
+        sim.run(1);
+        sim.set_output_columns(OutputColumns::all());
+        sim.set_output_units(UnitSystem::Astronomical);
+        sim.set_output_frame(OutputFrame::centred_on("body0"));
+        assert_eq!(sim.output_columns(), OutputColumns::default());
+        assert_eq!(sim.output_units(), UnitSystem::Si);
+        assert_eq!(*sim.output_frame(), OutputFrame::Inertial);
+        drop(sim);
+
+        // Diagnostics share the units, but not the trajectory's layout
+        let diagnostics_path = TempFile::new("fixed_layout.csv");
+        let mut sim = Simulator::new(eccentric_binary(), 3600.0);
+        sim.enable_diagnostics(diagnostics_path.to_str().unwrap()).unwrap();
+        sim.run(1);
+        sim.set_output_units(UnitSystem::Astronomical);
+        sim.set_output_columns(OutputColumns::all());
+        assert_eq!(sim.output_units(), UnitSystem::Si);
+        assert_eq!(sim.output_columns(), OutputColumns::all());
+    }
+
+    #[test]
+    fn test_binary_output_matches_csv_and_resumes() {
+        use crate::trajectory::TrajectoryReader;
+
+        let csv_path = TempFile::new("binary.csv");
+        let full_path = TempFile::new("binary_full.traj");
+        let resumed_path = TempFile::new("binary_split.traj");
+        let checkpoint_path = TempFile::new("binary.ckpt");
+        let end_time = 86400.0 * 30.0;
+        let start = |path: &Path| {
+            let mut sim = Simulator::new(eccentric_binary(), 3600.0);
+            sim.set_output_interval(Some(86400.0));
+            sim.set_output_columns(OutputColumns::all());
+            sim.set_output_units(UnitSystem::Astronomical);
+            sim.enable_binary_output(path.to_str().unwrap()).unwrap();
+            sim
+        };
+
+        let mut full = start(&full_path);
+        full.output = Some(CsvOutput::new(File::create(&csv_path).unwrap()));
+        full.run_until(end_time);
+        drop(full);
+
+        let mut first = start(&resumed_path);
+        first.run(24 * 10);
+        first.save_checkpoint(&checkpoint_path).unwrap();
+        first.run(24 * 5);
+        drop(first);
+        let mut resumed = Simulator::load_checkpoint(&checkpoint_path).unwrap();
+        resumed.append_binary_output(resumed_path.to_str().unwrap()).unwrap();
+        resumed.run_until(end_time);
+        drop(resumed);
+
+        let csv = std::fs::read_to_string(&csv_path).unwrap();
+        let full_bytes = std::fs::read(&full_path).unwrap();
+        let resumed_bytes = std::fs::read(&resumed_path).unwrap();
+        let mut reader = TrajectoryReader::open(&full_path).unwrap();
+        assert_eq!(resumed_bytes, full_bytes);
+
+        let header = reader.header().clone();
+        assert_eq!(header.units, UnitSystem::Astronomical);
+        assert_eq!(header.columns.join(","), csv.lines().next().unwrap());
+        assert_eq!(header.names, ["body0", "body1"]);
+        assert_eq!(header.masses[0], 1e30 / crate::units::SOLAR_MASS);
+        assert_eq!(reader.num_frames(), 30);
+
+        // Random access agrees with the CSV rows up to their rounding
+        for frame in [17, 3, 29] {
+            let row: Vec<f64> = csv.lines().nth(frame + 1).unwrap().split(',').map(|v| v.parse().unwrap()).collect();
+            let values = reader.read_frame(frame).unwrap();
+            assert_eq!(values.len(), row.len());
+            for (value, text) in values.iter().zip(&row) {
+                assert!((value - text).abs() <= 1e-8 + 1e-9 * text.abs(), "{} vs {}", value, text);
+            }
+        }
+        assert_eq!(reader.time(4).unwrap(), 5.0);
+        assert_eq!(reader.position(4, "body1").unwrap()[0], reader.read_frame(4).unwrap()[header.column("body1_x").unwrap()]);
+        assert!(reader.read_frame(30).is_err());
+        assert_eq!(reader.frames().count(), 30);
+    }
+
+    #[test]
+    fn test_diagnostics_stream_tracks_drift() {
+        let path = TempFile::new("diagnostics.csv");
+        let mut sim = Simulator::new(eccentric_binary(), 3600.0);
+        sim.set_output_interval(Some(86400.0));
+        sim.enable_diagnostics(path.to_str().unwrap()).unwrap();
+        sim.run_until(86400.0 * 10.0);
+
+        let tracker = sim.drift_tracker().unwrap().clone();
+        let drift = tracker.max_drift();
+        assert!(drift.energy > 0.0 && drift.energy < 1e-6, "{:?}", drift);
+        assert!(drift.momentum < 1e-12 && drift.angular_momentum < 1e-9, "{:?}", drift);
+        assert!(sim.virial_ratio() > 0.0);
+
+        // The reference survives a checkpoint
+        let resumed = Simulator::from_checkpoint(sim.checkpoint()).unwrap();
+        assert_eq!(resumed.drift_tracker(), Some(&tracker));
+        drop(sim);
+
+        let csv = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], DIAGNOSTICS_HEADER);
+        assert_eq!(lines.len(), 11);
+        let last: Vec<f64> = lines[10].split(',').map(|v| v.parse().unwrap()).collect();
+        assert_eq!(last[0], 864000.0);
+        assert!(last[17] <= drift.energy);
+    }
+
+    #[test]
+    fn test_elements_stream() {
+        let path = TempFile::new("elements.csv");
+        let mut sim = Simulator::new(eccentric_binary(), 3600.0);
+        let start = sim.osculating_elements("body0");
+        assert_eq!(start.len(), 1);
+        assert_eq!(start[0].0, "body1");
+        let eccentricity = start[0].1.eccentricity;
+        assert!(eccentricity > 0.9);
+
+        sim.set_output_interval(Some(86400.0));
+        sim.enable_elements_output(path.to_str().unwrap(), "body0").unwrap();
+        sim.run_until(86400.0 * 10.0);
+        let end = sim.osculating_elements("body0");
+        assert!((end[0].1.eccentricity - eccentricity).abs() < 1e-6);
+        assert!((end[0].1.semi_major_axis / start[0].1.semi_major_axis - 1.0).abs() < 1e-6);
+        drop(sim);
+
+        let csv = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], ELEMENTS_HEADER);
+        assert_eq!(lines.len(), 11);
+        let last: Vec<&str> = lines[10].split(',').collect();
+        assert_eq!(last[..2], ["864000.00000000", "body1"]);
+        assert!((last[3].parse::<f64>().unwrap() - eccentricity).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_output_centred_on_a_body() {
+        let path = TempFile::new("body_frame.csv");
+        let bodies: Vec<Body> = eccentric_binary()
+            .into_iter()
+            .zip(["A", "B"])
+            .map(|(body, name)| body.with_name(name))
+            .collect();
+        let mut sim = Simulator::with_output(bodies, 3600.0, path.to_str().unwrap()).unwrap();
+        sim.to_barycentric();
+        assert!(sim.momentum().iter().all(|p| p.abs() < 1e10));
+        sim.set_output_frame(OutputFrame::centred_on("B"));
+        sim.run(5);
+        let relative = sim.bodies()[0].vector_to(&sim.bodies()[1]);
+        drop(sim);
+
+        let data = crate::trajectory::TrajectoryData::load_csv(path.to_str().unwrap()).unwrap();
+        let (a, b) = (data.bodies[0].get_position(4).unwrap(), data.bodies[1].get_position(4).unwrap());
+        assert_eq!((b.x, b.y, b.z), (0.0, 0.0, 0.0));
+        assert!((a.x + relative[0]).abs() < 1e5 && (a.y + relative[1]).abs() < 1e5);
+    }
+
+    #[test]
+    fn test_barnes_hut_solver_tracks_direct_sum() {
+        let mut direct = Simulator::new(eccentric_binary(), 86400.0);
+        let mut tree = Simulator::new(eccentric_binary(), 86400.0);
+        tree.set_force_solver(ForceSolver::barnes_hut(0.0));
+        direct.run(100);
+        tree.run(100);
+
+        for (a, b) in direct.bodies().iter().zip(tree.bodies()) {
+            let offset = a.distance_to(b);
+            assert!(offset < 1e-6 * 1e11, "trajectories diverged by {} m", offset);
+        }
+    }
+
+    #[test]
+    fn test_softened_head_on_collision_conserves_energy() {
+        // Two bodies fall straight through each other; point masses would
+        // meet a singularity, softened ones pass with a finite force
+        for softening in [Softening::plummer(1e6), Softening::spline(1e6)] {
+            let bodies = vec![
+                Body::new(1e24, [-5e6, 0.0, 0.0], [0.0; 3]),
+                Body::new(1e24, [5e6, 0.0, 0.0], [0.0; 3]),
+            ];
+            let mut sim = Simulator::with_integrator(bodies, 0.5, Box::new(crate::symplectic::Leapfrog::new()));
+            sim.set_softening(softening);
+            let initial_energy = sim.total_energy();
+            sim.run_until(4000.0);
+
+            // Past the crossing, each body is on the other side of the origin
+            assert!(sim.bodies()[0].position[0] > 0.0 && sim.bodies()[1].position[0] < 0.0);
+            let drift = ((sim.total_energy() - initial_energy) / initial_energy).abs();
+            assert!(drift < 1e-4, "{}: relative energy drift {}", softening, drift);
+        }
+    }
+
+    fn colliding_pair() -> Vec<Body> {
+        vec![
+            Body::new(3e24, [-5e6, 0.0, 0.0], [0.0, 100.0, 0.0]).with_name("A").with_radius(1e6),
+            Body::new(1e24, [5e6, 0.0, 0.0], [0.0, -300.0, 0.0]).with_name("B").with_radius(1e6),
+            Body::new(1.0, [1e9, 0.0, 0.0], [0.0; 3]).with_name("C"),
+        ]
+    }
+
+    #[test]
+    fn test_merging_collision_conserves_momentum_and_keeps_columns() {
+        let path = TempFile::new("merge.csv");
+        let mut sim = Simulator::with_output(colliding_pair(), 10.0, path.to_str().unwrap()).unwrap();
+        sim.set_collision_mode(CollisionMode::Merge);
+        let momentum = |sim: &Simulator| {
+            sim.bodies().iter().map(|b| b.mass * b.velocity[1]).sum::<f64>()
+        };
+        let initial_momentum = momentum(&sim);
+        sim.run_until(5000.0);
+        drop(sim.output.take());
+
+        assert_eq!(sim.collisions().len(), 1);
+        let event = &sim.collisions()[0];
+        assert_eq!(event.bodies, (String::from("A"), String::from("B")));
+        assert_eq!(event.outcome, CollisionOutcome::Merged { into: String::from("A") });
+        assert_eq!(sim.bodies().len(), 2);
+        assert_eq!(sim.bodies()[0].mass, 4e24);
+        assert!((momentum(&sim) - initial_momentum).abs() < 1e-9 * 3e26);
+
+        // B keeps its columns, as NaN once it is gone
+        let trajectory = crate::trajectory::TrajectoryData::load_csv(&path).unwrap();
+        assert_eq!(trajectory.bodies.len(), 3);
+        let b = &trajectory.bodies[1];
+        assert!(b.get_position(0).unwrap().is_finite());
+        assert!(!b.get_position(trajectory.num_frames - 1).unwrap().is_finite());
+        assert!(trajectory.bodies[2].get_position(trajectory.num_frames - 1).unwrap().is_finite());
+    }
+
+    #[test]
+    fn test_stopping_collision_ends_the_run() {
+        let mut sim = Simulator::new(colliding_pair(), 10.0);
+        sim.set_collision_mode(CollisionMode::Stop);
+        assert!(sim.run_until_bounded(1e6, usize::MAX));
+
+        assert!(sim.is_stopped());
+        assert!(sim.time() < 1e6);
+        assert_eq!(sim.collisions().len(), 1);
+        assert_eq!(sim.bodies().len(), 3);
+
+        let steps = sim.accepted_steps();
+        sim.run(10);
+        assert_eq!(sim.accepted_steps(), steps);
+    }
 }
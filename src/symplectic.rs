@@ -0,0 +1,519 @@
+//! Symplectic integrators for long-term orbital integration
+//!
+//! Symplectic schemes do not estimate their own error, but they conserve a
+//! modified energy of the system, so the energy error stays bounded instead
+//! of drifting over many orbits. This module provides velocity-Verlet
+//! (leapfrog), the 4th-order Yoshida composition of it, and the
+//! Wisdom-Holman mapping for systems dominated by one central body.
+
+use crate::body::Body;
+use crate::integrator::{DerivativeFunction, Integrator, StepReport};
+use crate::simulator::G;
+
+/// Collect the current accelerations of all bodies
+fn accelerations(bodies: &[Body]) -> Vec<[f64; 3]> {
+    bodies.iter().map(|b| b.acceleration).collect()
+}
+
+/// One kick-drift-kick substep ending `end` after the start of the step,
+/// assuming the bodies' accelerations are current on entry; they are
+/// current again on exit
+fn kick_drift_kick(bodies: &mut [Body], h: f64, end: f64, derivative_fn: DerivativeFunction<'_>) {
+    for body in bodies.iter_mut() {
+        for dim in 0..3 {
+            body.velocity[dim] += 0.5 * h * body.acceleration[dim];
+            body.position[dim] += h * body.velocity[dim];
+        }
+    }
+
+    derivative_fn(end, bodies);
+
+    for body in bodies.iter_mut() {
+        for dim in 0..3 {
+            body.velocity[dim] += 0.5 * h * body.acceleration[dim];
+        }
+    }
+}
+
+/// Velocity-Verlet (kick-drift-kick leapfrog) integrator
+///
+/// Second order, time-reversible and symplectic.
+#[derive(Debug, Clone, Default)]
+pub struct Leapfrog;
+
+impl Leapfrog {
+    /// Create a new leapfrog integrator
+    pub fn new() -> Self {
+        Leapfrog
+    }
+}
+
+impl Integrator for Leapfrog {
+    fn name(&self) -> &'static str {
+        "leapfrog"
+    }
+
+    fn advance(
+        &mut self,
+        bodies: &mut [Body],
+        dt: f64,
+        derivative_fn: DerivativeFunction<'_>,
+    ) -> StepReport {
+        derivative_fn(0.0, bodies);
+        let start_accelerations = accelerations(bodies);
+
+        kick_drift_kick(bodies, dt, dt, derivative_fn);
+
+        StepReport {
+            errors: None,
+            start_accelerations,
+            end_accelerations: accelerations(bodies),
+        }
+    }
+}
+
+/// 4th-order Yoshida integrator
+///
+/// Composes three leapfrog substeps with weights chosen so that the
+/// second- and third-order error terms cancel (Yoshida 1990).
+#[derive(Debug, Clone)]
+pub struct Yoshida4 {
+    /// Substep weights as fractions of the full step
+    weights: [f64; 3],
+}
+
+impl Yoshida4 {
+    /// Create a new 4th-order Yoshida integrator
+    pub fn new() -> Self {
+        let cbrt2 = 2.0_f64.cbrt();
+        let w1 = 1.0 / (2.0 - cbrt2);
+        let w0 = -cbrt2 / (2.0 - cbrt2);
+        Yoshida4 {
+            weights: [w1, w0, w1],
+        }
+    }
+}
+
+impl Default for Yoshida4 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Integrator for Yoshida4 {
+    fn name(&self) -> &'static str {
+        "yoshida4"
+    }
+
+    fn advance(
+        &mut self,
+        bodies: &mut [Body],
+        dt: f64,
+        derivative_fn: DerivativeFunction<'_>,
+    ) -> StepReport {
+        derivative_fn(0.0, bodies);
+        let start_accelerations = accelerations(bodies);
+
+        let mut elapsed = 0.0;
+        for &w in &self.weights {
+            elapsed += w * dt;
+            kick_drift_kick(bodies, w * dt, elapsed, derivative_fn);
+        }
+
+        StepReport {
+            errors: None,
+            start_accelerations,
+            end_accelerations: accelerations(bodies),
+        }
+    }
+}
+
+/// Wisdom-Holman mapping in democratic heliocentric coordinates
+///
+/// The motion of each body around the most massive one is advanced exactly
+/// as a Kepler orbit, while the remaining interactions are applied as kicks.
+/// This makes it far more accurate than leapfrog at the same step size for
+/// planetary systems, where the interactions are small perturbations.
+///
+/// Accelerations from the derivative function are used for the kicks after
+/// subtracting the central body's Newtonian pull, so any additional forces
+/// it computes are treated as part of the perturbation.
+#[derive(Debug, Clone)]
+pub struct WisdomHolman {
+    /// Gravitational constant used for the Kepler drift
+    g: f64,
+}
+
+impl WisdomHolman {
+    /// Create a Wisdom-Holman integrator using SI units
+    pub fn new() -> Self {
+        WisdomHolman { g: G }
+    }
+
+    /// Index of the central (most massive) body
+    fn central_index(bodies: &[Body]) -> usize {
+        let mut central = 0;
+        for (idx, body) in bodies.iter().enumerate() {
+            if body.mass > bodies[central].mass {
+                central = idx;
+            }
+        }
+        central
+    }
+
+    /// Kick the barycentric velocities with the non-Keplerian accelerations
+    ///
+    /// Expects the bodies' accelerations to be current.
+    fn kick(&self, bodies: &mut [Body], central: usize, h: f64) {
+        let mu = self.g * bodies[central].mass;
+        let center = bodies[central].position;
+
+        for (idx, body) in bodies.iter_mut().enumerate() {
+            if idx == central {
+                continue;
+            }
+            let r = [
+                body.position[0] - center[0],
+                body.position[1] - center[1],
+                body.position[2] - center[2],
+            ];
+            let dist = (r[0] * r[0] + r[1] * r[1] + r[2] * r[2]).sqrt();
+            let factor = if dist > 0.0 {
+                mu / (dist * dist * dist)
+            } else {
+                0.0
+            };
+            for dim in 0..3 {
+                let perturbation = body.acceleration[dim] + factor * r[dim];
+                body.velocity[dim] += h * perturbation;
+            }
+        }
+    }
+}
+
+impl Default for WisdomHolman {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Integrator for WisdomHolman {
+    fn name(&self) -> &'static str {
+        "wisdom-holman"
+    }
+
+    fn advance(
+        &mut self,
+        bodies: &mut [Body],
+        dt: f64,
+        derivative_fn: DerivativeFunction<'_>,
+    ) -> StepReport {
+        derivative_fn(0.0, bodies);
+        let start_accelerations = accelerations(bodies);
+
+        let central = Self::central_index(bodies);
+        let m0 = bodies[central].mass;
+        let mu = self.g * m0;
+        let total_mass: f64 = bodies.iter().map(|b| b.mass).sum();
+
+        // Barycentre, which moves uniformly
+        let mut com_pos = [0.0; 3];
+        let mut com_vel = [0.0; 3];
+        for body in bodies.iter() {
+            for dim in 0..3 {
+                com_pos[dim] += body.mass * body.position[dim] / total_mass;
+                com_vel[dim] += body.mass * body.velocity[dim] / total_mass;
+            }
+        }
+
+        // Half kick, then switch to heliocentric positions and barycentric
+        // velocities for the drift
+        self.kick(bodies, central, 0.5 * dt);
+        let center = bodies[central].position;
+        for body in bodies.iter_mut() {
+            for dim in 0..3 {
+                body.position[dim] -= center[dim];
+                body.velocity[dim] -= com_vel[dim];
+            }
+        }
+
+        jump(bodies, central, 0.5 * dt);
+        for (idx, body) in bodies.iter_mut().enumerate() {
+            if idx != central {
+                kepler_drift(&mut body.position, &mut body.velocity, mu, dt);
+            }
+        }
+        jump(bodies, central, 0.5 * dt);
+
+        // Back to barycentric Cartesian coordinates
+        let mut weighted = [0.0; 3];
+        let mut momentum = [0.0; 3];
+        for (idx, body) in bodies.iter().enumerate() {
+            if idx != central {
+                for dim in 0..3 {
+                    weighted[dim] += body.mass * body.position[dim];
+                    momentum[dim] += body.mass * body.velocity[dim];
+                }
+            }
+        }
+        let mut center = [0.0; 3];
+        for dim in 0..3 {
+            center[dim] = com_pos[dim] + com_vel[dim] * dt - weighted[dim] / total_mass;
+        }
+        for (idx, body) in bodies.iter_mut().enumerate() {
+            for dim in 0..3 {
+                if idx == central {
+                    body.position[dim] = center[dim];
+                    body.velocity[dim] = com_vel[dim] - momentum[dim] / m0;
+                } else {
+                    body.position[dim] += center[dim];
+                    body.velocity[dim] += com_vel[dim];
+                }
+            }
+        }
+
+        // Closing half kick; the central body's velocity follows from
+        // momentum conservation
+        derivative_fn(dt, bodies);
+        self.kick(bodies, central, 0.5 * dt);
+        let mut momentum = [0.0; 3];
+        for (idx, body) in bodies.iter().enumerate() {
+            if idx != central {
+                for dim in 0..3 {
+                    momentum[dim] += body.mass * (body.velocity[dim] - com_vel[dim]);
+                }
+            }
+        }
+        for dim in 0..3 {
+            bodies[central].velocity[dim] = com_vel[dim] - momentum[dim] / m0;
+        }
+
+        StepReport {
+            errors: None,
+            start_accelerations,
+            end_accelerations: accelerations(bodies),
+        }
+    }
+}
+
+/// Linear drift of heliocentric positions due to the central body's motion
+fn jump(bodies: &mut [Body], central: usize, h: f64) {
+    let m0 = bodies[central].mass;
+    let mut momentum = [0.0; 3];
+    for (idx, body) in bodies.iter().enumerate() {
+        if idx != central {
+            for dim in 0..3 {
+                momentum[dim] += body.mass * body.velocity[dim];
+            }
+        }
+    }
+    for (idx, body) in bodies.iter_mut().enumerate() {
+        if idx != central {
+            for dim in 0..3 {
+                body.position[dim] += h * momentum[dim] / m0;
+            }
+        }
+    }
+}
+
+/// Stumpff functions C(z) and S(z) used by the universal Kepler equation
+fn stumpff(z: f64) -> (f64, f64) {
+    if z > 1e-6 {
+        let sz = z.sqrt();
+        ((1.0 - sz.cos()) / z, (sz - sz.sin()) / (sz * z))
+    } else if z < -1e-6 {
+        let sz = (-z).sqrt();
+        ((sz.cosh() - 1.0) / -z, (sz.sinh() - sz) / (sz * -z))
+    } else {
+        (
+            0.5 - z / 24.0 + z * z / 720.0,
+            1.0 / 6.0 - z / 120.0 + z * z / 5040.0,
+        )
+    }
+}
+
+/// Advance a relative two-body orbit by `dt` using universal variables
+///
+/// Works for elliptic, parabolic and hyperbolic orbits alike.
+pub fn kepler_drift(position: &mut [f64; 3], velocity: &mut [f64; 3], mu: f64, dt: f64) {
+    let r0 =
+        (position[0] * position[0] + position[1] * position[1] + position[2] * position[2]).sqrt();
+    if r0 == 0.0 || mu <= 0.0 || dt == 0.0 {
+        for dim in 0..3 {
+            position[dim] += velocity[dim] * dt;
+        }
+        return;
+    }
+
+    let v2 = velocity[0] * velocity[0] + velocity[1] * velocity[1] + velocity[2] * velocity[2];
+    let rv = position[0] * velocity[0] + position[1] * velocity[1] + position[2] * velocity[2];
+    let sqrt_mu = mu.sqrt();
+    // Reciprocal of the semi-major axis
+    let alpha = 2.0 / r0 - v2 / mu;
+
+    // Whole periods of a bound orbit change nothing, so drop them
+    let mut dt = dt;
+    if alpha > 0.0 {
+        let period = 2.0 * std::f64::consts::PI / (sqrt_mu * alpha * alpha.sqrt());
+        dt %= period;
+    }
+
+    // Solve the universal Kepler equation for chi with Newton's method
+    let mut chi = if alpha > 0.0 {
+        sqrt_mu * alpha * dt
+    } else {
+        sqrt_mu * dt / r0
+    };
+    for _ in 0..100 {
+        let z = alpha * chi * chi;
+        let (c, s) = stumpff(z);
+        let f = rv / sqrt_mu * chi * chi * c + (1.0 - alpha * r0) * chi * chi * chi * s + r0 * chi
+            - sqrt_mu * dt;
+        let df = rv / sqrt_mu * chi * (1.0 - z * s) + (1.0 - alpha * r0) * chi * chi * c + r0;
+        let delta = f / df;
+        chi -= delta;
+        if delta.abs() <= 1e-15 * chi.abs().max(1e-300) {
+            break;
+        }
+    }
+
+    // Lagrange coefficients
+    let z = alpha * chi * chi;
+    let (c, s) = stumpff(z);
+    let f = 1.0 - chi * chi / r0 * c;
+    let g = dt - chi * chi * chi / sqrt_mu * s;
+
+    let new_position = [
+        f * position[0] + g * velocity[0],
+        f * position[1] + g * velocity[1],
+        f * position[2] + g * velocity[2],
+    ];
+    let r = (new_position[0] * new_position[0]
+        + new_position[1] * new_position[1]
+        + new_position[2] * new_position[2])
+        .sqrt();
+    let fdot = sqrt_mu / (r * r0) * (z * chi * s - chi);
+    let gdot = 1.0 - chi * chi / r * c;
+
+    for dim in 0..3 {
+        velocity[dim] = fdot * position[dim] + gdot * velocity[dim];
+    }
+    *position = new_position;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::Simulator;
+
+    /// Sun, Jupiter and Saturn as in data/sun_jupiter_saturn.ini
+    fn sun_jupiter_saturn() -> Vec<Body> {
+        vec![
+            Body::new(1.989e30, [0.0, 0.0, 0.0], [0.0, 0.0, 0.0]),
+            Body::new(1.898e27, [7.78e11, 0.0, 0.0], [0.0, 13070.0, 0.0]),
+            Body::new(5.683e26, [1.43e12, 0.0, 0.0], [0.0, 9680.0, 0.0]),
+        ]
+    }
+
+    /// Relative energy error after running the system with an integrator
+    fn energy_error(integrator: Box<dyn Integrator>, dt: f64, steps: usize) -> f64 {
+        let mut sim = Simulator::with_integrator(sun_jupiter_saturn(), dt, integrator);
+        let initial = sim.total_energy();
+        sim.run(steps);
+        ((sim.total_energy() - initial) / initial).abs()
+    }
+
+    #[test]
+    fn test_kepler_drift_full_orbit() {
+        let mu = 1.0;
+        let mut position = [1.0, 0.0, 0.0];
+        // Eccentric orbit with a = 1 / (2 - 0.8^2)
+        let mut velocity = [0.0, 0.8, 0.0];
+        let a: f64 = 1.0 / (2.0 - 0.64);
+        let period = 2.0 * std::f64::consts::PI * a.powf(1.5);
+
+        kepler_drift(&mut position, &mut velocity, mu, 0.3 * period);
+        assert!((position[0] - 1.0).abs() > 0.1);
+        kepler_drift(&mut position, &mut velocity, mu, 0.7 * period);
+
+        assert!((position[0] - 1.0).abs() < 1e-10);
+        assert!(position[1].abs() < 1e-10);
+        assert!((velocity[1] - 0.8).abs() < 1e-10);
+    }
+
+    #[test]
+    fn test_kepler_drift_hyperbolic_reversible() {
+        let mut position = [1.0, 0.0, 0.0];
+        let mut velocity = [0.0, 2.0, 0.0];
+        kepler_drift(&mut position, &mut velocity, 1.0, 5.0);
+        kepler_drift(&mut position, &mut velocity, 1.0, -5.0);
+        assert!((position[0] - 1.0).abs() < 1e-10);
+        assert!(position[1].abs() < 1e-10);
+        assert!((velocity[1] - 2.0).abs() < 1e-10);
+    }
+
+    #[test]
+    fn test_leapfrog_time_reversible() {
+        let mut sim = Simulator::with_integrator(
+            sun_jupiter_saturn(),
+            86400.0 * 5.0,
+            Box::<Leapfrog>::default(),
+        );
+        sim.run(100);
+        sim.set_dt(-86400.0 * 5.0);
+        sim.run(100);
+
+        for (body, initial) in sim.bodies().iter().zip(sun_jupiter_saturn()) {
+            for dim in 0..3 {
+                assert!((body.position[dim] - initial.position[dim]).abs() < 1.0);
+                assert!((body.velocity[dim] - initial.velocity[dim]).abs() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn test_yoshida_is_fourth_order() {
+        // Halving the step should reduce the energy error by about 2^4
+        let coarse = energy_error(Box::<Yoshida4>::default(), 86400.0 * 40.0, 200);
+        let fine = energy_error(Box::<Yoshida4>::default(), 86400.0 * 20.0, 400);
+        let ratio = coarse / fine;
+        assert!(ratio > 10.0 && ratio < 25.0, "ratio = {}", ratio);
+    }
+
+    #[test]
+    fn test_symplectic_energy_bounded() {
+        // ~60 years with 10-day steps: symplectic schemes show no secular drift
+        let steps = 2200;
+        let dt = 86400.0 * 10.0;
+        let leapfrog = energy_error(Box::<Leapfrog>::default(), dt, steps);
+        let yoshida = energy_error(Box::<Yoshida4>::default(), dt, steps);
+        let wisdom_holman = energy_error(Box::<WisdomHolman>::default(), dt, steps);
+
+        assert!(leapfrog < 1e-4, "leapfrog: {:e}", leapfrog);
+        assert!(yoshida < 1e-6, "yoshida: {:e}", yoshida);
+        assert!(wisdom_holman < 1e-6, "wisdom-holman: {:e}", wisdom_holman);
+    }
+
+    #[test]
+    fn test_wisdom_holman_beats_leapfrog() {
+        // Earth around the Sun with month-long steps: the Kepler drift
+        // carries the orbit, leaving only a tiny perturbation for the kicks
+        let earth_sun = || {
+            vec![
+                Body::new(1.989e30, [0.0, 0.0, 0.0], [0.0, 0.0, 0.0]),
+                Body::new(5.972e24, [1.496e11, 0.0, 0.0], [0.0, 29780.0, 0.0]),
+            ]
+        };
+        let run = |integrator: Box<dyn Integrator>| {
+            let mut sim = Simulator::with_integrator(earth_sun(), 86400.0 * 30.0, integrator);
+            let initial = sim.total_energy();
+            sim.run(1000);
+            ((sim.total_energy() - initial) / initial).abs()
+        };
+
+        let wisdom_holman = run(Box::<WisdomHolman>::default());
+        let leapfrog = run(Box::<Leapfrog>::default());
+        assert!(wisdom_holman < 1e-6, "wisdom-holman: {:e}", wisdom_holman);
+        assert!(wisdom_holman * 100.0 < leapfrog, "leapfrog: {:e}", leapfrog);
+    }
+}
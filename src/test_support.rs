@@ -0,0 +1,44 @@
+//! Fixtures shared by the unit tests
+
+use crate::body::Body;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+/// An Earth-like body at rest at the origin and a Moon-like one on a
+/// roughly circular orbit around it
+pub(crate) fn earth_moon() -> Vec<Body> {
+    vec![
+        Body::new(5.972e24, [0.0; 3], [0.0; 3]).with_name("Earth"),
+        Body::new(7.342e22, [3.844e8, 0.0, 0.0], [0.0, 1022.0, 0.0]).with_name("Moon"),
+    ]
+}
+
+/// A path in the temporary directory, unique to this process so that
+/// concurrent test runs do not share files, removed when dropped
+pub(crate) struct TempFile(PathBuf);
+
+impl TempFile {
+    pub(crate) fn new(name: &str) -> Self {
+        TempFile(std::env::temp_dir().join(format!("threebody_sim_{}_{}", std::process::id(), name)))
+    }
+}
+
+impl Deref for TempFile {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempFile {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
@@ -1,38 +1,113 @@
 //! Trajectory data loading and management
 //!
-//! Parses CSV files from the N-body simulator and stores trajectory data
+//! Parses CSV and binary trajectory files from the N-body simulator and
+//! stores trajectory data at full precision, with the time of every frame
+//! so frames can be looked up by time. Columns are located by name, so files with
+//! optional columns (velocities, energies, ...) load the same way as
+//! position-only ones. `TrajectoryReader` reads single frames of a binary
+//! file without loading the rest. Both can be saved as NumPy `.npz`
+//! archives for analysis outside Rust.
 
+use crate::binary::{self, TrajectoryHeader};
+use crate::npy::NpzWriter;
 use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
 use std::path::Path;
 
-/// A single position sample for one body at one time step
-#[derive(Debug, Clone, Copy)]
+/// A single position (or velocity) sample for one body at one frame
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Position {
-    pub x: f32,
-    pub y: f32,
-    pub z: f32,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
 }
 
 impl Position {
     pub fn new(x: f64, y: f64, z: f64) -> Self {
-        Position {
-            x: x as f32,
-            y: y as f32,
-            z: z as f32,
-        }
+        Position { x, y, z }
+    }
+
+    pub fn to_array(&self) -> [f64; 3] {
+        [self.x, self.y, self.z]
+    }
+
+    /// The coordinates in single precision, as graphics APIs take them
+    pub fn to_f32(&self) -> [f32; 3] {
+        [self.x as f32, self.y as f32, self.z as f32]
+    }
+
+    /// Whether every coordinate is finite; bodies lost in a merger are
+    /// written as `NaN` from then on
+    pub fn is_finite(&self) -> bool {
+        self.x.is_finite() && self.y.is_finite() && self.z.is_finite()
+    }
+}
+
+/// How positions between frames are estimated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Interpolation {
+    /// Straight lines between frames
+    Linear,
+    /// Cubic Hermite curves matching the velocity at each frame, estimated
+    /// from the neighbouring frames when the trajectory has no velocities
+    #[default]
+    Hermite,
+}
+
+/// The segment between frames `k` and `k + 1` that contains `time`, with
+/// the fraction of the way along it; the last frame ends the last segment
+///
+/// `None` outside the span of `times`, which must not decrease.
+fn segment(times: &[f64], time: f64) -> Option<(usize, f64)> {
+    let (&first, &last) = (times.first()?, times.last()?);
+    if !(first..=last).contains(&time) {
+        return None;
+    }
+    if times.len() == 1 {
+        return Some((0, 0.0));
+    }
+    let k = times.partition_point(|&t| t <= time).saturating_sub(1).min(times.len() - 2);
+    let span = times[k + 1] - times[k];
+    Some((k, if span > 0.0 { (time - times[k]) / span } else { 1.0 }))
+}
+
+/// Linear interpolation that returns `a` itself at `s = 0`, so samples are
+/// reproduced exactly even next to a `NaN`
+fn lerp(a: f64, b: f64, s: f64) -> f64 {
+    if s == 0.0 {
+        a
+    } else if s == 1.0 {
+        b
+    } else {
+        a + s * (b - a)
     }
 }
 
 /// Complete trajectory for a single body
 #[derive(Debug, Clone)]
 pub struct BodyTrajectory {
+    /// Body name taken from the CSV header
+    pub name: String,
     pub positions: Vec<Position>,
+    /// Velocity samples, empty when the file has no velocity columns
+    pub velocities: Vec<Position>,
 }
 
 impl BodyTrajectory {
     pub fn new() -> Self {
         BodyTrajectory {
+            name: String::new(),
             positions: Vec::new(),
+            velocities: Vec::new(),
+        }
+    }
+
+    /// Create an empty trajectory for a named body
+    pub fn with_name(name: &str) -> Self {
+        BodyTrajectory {
+            name: name.to_string(),
+            positions: Vec::new(),
+            velocities: Vec::new(),
         }
     }
 
@@ -44,6 +119,10 @@ impl BodyTrajectory {
         self.positions.get(frame).copied()
     }
 
+    pub fn get_velocity(&self, frame: usize) -> Option<Position> {
+        self.velocities.get(frame).copied()
+    }
+
     pub fn len(&self) -> usize {
         self.positions.len()
     }
@@ -51,6 +130,61 @@ impl BodyTrajectory {
     pub fn is_empty(&self) -> bool {
         self.positions.is_empty()
     }
+
+    /// Position at any time within the trajectory, interpolated between the
+    /// frames on either side
+    ///
+    /// `times` holds the time of each frame, as in `TrajectoryData::times`.
+    /// Returns `None` outside the time span of the frames.
+    pub fn position_at(&self, times: &[f64], time: f64, method: Interpolation) -> Option<Position> {
+        let times = &times[..times.len().min(self.positions.len())];
+        let (k, s) = segment(times, time)?;
+        if times.len() == 1 || s == 0.0 {
+            return Some(self.positions[k]);
+        }
+        if s == 1.0 {
+            return Some(self.positions[k + 1]);
+        }
+        let (p0, p1) = (self.positions[k].to_array(), self.positions[k + 1].to_array());
+        let point: [f64; 3] = match method {
+            Interpolation::Linear => std::array::from_fn(|i| lerp(p0[i], p1[i], s)),
+            Interpolation::Hermite => {
+                let h = times[k + 1] - times[k];
+                let (m0, m1) = (self.tangent(times, k), self.tangent(times, k + 1));
+                let (s2, s3) = (s * s, s * s * s);
+                let (h00, h10, h01, h11) = (2.0 * s3 - 3.0 * s2 + 1.0, s3 - 2.0 * s2 + s, 3.0 * s2 - 2.0 * s3, s3 - s2);
+                std::array::from_fn(|i| h00 * p0[i] + h10 * h * m0[i] + h01 * p1[i] + h11 * h * m1[i])
+            }
+        };
+        Some(Position::new(point[0], point[1], point[2]))
+    }
+
+    /// Velocity at any time within the trajectory, interpolated linearly
+    /// between frames; `None` outside it or without velocity columns
+    pub fn velocity_at(&self, times: &[f64], time: f64) -> Option<Position> {
+        let times = &times[..times.len().min(self.velocities.len())];
+        let (k, s) = segment(times, time)?;
+        let (v0, v1) = (self.velocities[k], self.velocities[(k + 1).min(times.len() - 1)]);
+        Some(Position::new(lerp(v0.x, v1.x, s), lerp(v0.y, v1.y, s), lerp(v0.z, v1.z, s)))
+    }
+
+    /// Rate of change of position at a frame: the velocity when there is one,
+    /// otherwise the slope between the neighbouring frames
+    fn tangent(&self, times: &[f64], frame: usize) -> [f64; 3] {
+        if let Some(velocity) = self.velocities.get(frame) {
+            return velocity.to_array();
+        }
+        let (before, after) = (frame.saturating_sub(1), (frame + 1).min(times.len() - 1));
+        let (a, b) = (self.positions[before].to_array(), self.positions[after].to_array());
+        let dt = times[after] - times[before];
+        std::array::from_fn(|i| if dt > 0.0 { (b[i] - a[i]) / dt } else { 0.0 })
+    }
+}
+
+impl Default for BodyTrajectory {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 /// Complete trajectory data for all bodies in a simulation
@@ -58,6 +192,10 @@ impl BodyTrajectory {
 pub struct TrajectoryData {
     pub bodies: Vec<BodyTrajectory>,
     pub num_frames: usize,
+    /// Time of each frame, in the units of the file
+    pub times: Vec<f64>,
+    /// Total energy per frame, empty when the file has no `energy` column
+    pub energies: Vec<f64>,
 }
 
 impl TrajectoryData {
@@ -65,106 +203,449 @@ impl TrajectoryData {
         TrajectoryData {
             bodies: Vec::new(),
             num_frames: 0,
+            times: Vec::new(),
+            energies: Vec::new(),
+        }
+    }
+
+    /// Time of a frame
+    pub fn time(&self, frame: usize) -> Option<f64> {
+        self.times.get(frame).copied()
+    }
+
+    /// The last frame at or before `time`, which shows the bodies as they
+    /// were at that time; `None` before the first frame
+    ///
+    /// Frame times must not decrease, as in every file the simulator writes.
+    pub fn frame_at(&self, time: f64) -> Option<usize> {
+        self.times.partition_point(|&t| t <= time).checked_sub(1)
+    }
+
+    /// The frame closest in time to `time`, earlier on a tie; `None` for an
+    /// empty trajectory
+    pub fn nearest_frame(&self, time: f64) -> Option<usize> {
+        let after = self.times.partition_point(|&t| t < time);
+        match (after.checked_sub(1), self.times.get(after)) {
+            (Some(before), Some(&next)) if next - time < time - self.times[before] => Some(after),
+            (Some(before), _) => Some(before),
+            (None, Some(_)) => Some(after),
+            (None, None) => None,
+        }
+    }
+
+    /// Position of a body at any time within the trajectory; see
+    /// `BodyTrajectory::position_at`
+    pub fn position_at(&self, body: usize, time: f64, method: Interpolation) -> Option<Position> {
+        self.bodies.get(body)?.position_at(&self.times, time, method)
+    }
+
+    /// The trajectory interpolated onto new frame times, for comparing runs
+    /// written at different intervals
+    ///
+    /// Positions are interpolated with `method`, and velocities and energies,
+    /// when present, linearly. Frames outside the original time span are
+    /// `NaN`, like bodies lost in a merger.
+    pub fn resample(&self, times: &[f64], method: Interpolation) -> TrajectoryData {
+        let nan = Position::new(f64::NAN, f64::NAN, f64::NAN);
+        let bodies = self
+            .bodies
+            .iter()
+            .map(|body| BodyTrajectory {
+                name: body.name.clone(),
+                positions: times
+                    .iter()
+                    .map(|&t| body.position_at(&self.times, t, method).unwrap_or(nan))
+                    .collect(),
+                velocities: if body.velocities.is_empty() {
+                    Vec::new()
+                } else {
+                    times.iter().map(|&t| body.velocity_at(&self.times, t).unwrap_or(nan)).collect()
+                },
+            })
+            .collect();
+        let energy_times = &self.times[..self.times.len().min(self.energies.len())];
+        let energies = if energy_times.is_empty() {
+            Vec::new()
+        } else {
+            times
+                .iter()
+                .map(|&t| match segment(energy_times, t) {
+                    Some((k, s)) => lerp(self.energies[k], self.energies[(k + 1).min(energy_times.len() - 1)], s),
+                    None => f64::NAN,
+                })
+                .collect()
+        };
+        TrajectoryData {
+            bodies,
+            num_frames: times.len(),
+            times: times.to_vec(),
+            energies,
         }
     }
+}
+
+impl Default for TrajectoryData {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Column indices of one body's data
+struct BodyColumns {
+    position: [usize; 3],
+    velocity: Option<[usize; 3]>,
+}
+
+fn invalid_data(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+/// Bodies found in a list of column names, with their columns
+///
+/// Every `<name>_x` column with matching `_y` and `_z` columns is a body, in
+/// the order of the `_x` columns.
+fn find_bodies(names: &[&str]) -> (Vec<BodyTrajectory>, Vec<BodyColumns>) {
+    let find = |name: &str| names.iter().position(|h| h.trim() == name);
+    let find_vector = |prefix: &str, suffixes: [&str; 3]| -> Option<[usize; 3]> {
+        Some([
+            find(&format!("{}{}", prefix, suffixes[0]))?,
+            find(&format!("{}{}", prefix, suffixes[1]))?,
+            find(&format!("{}{}", prefix, suffixes[2]))?,
+        ])
+    };
 
+    let mut bodies = Vec::new();
+    let mut columns = Vec::new();
+    for header in names {
+        let Some(name) = header.trim().strip_suffix("_x") else {
+            continue;
+        };
+        if let Some(position) = find_vector(name, ["_x", "_y", "_z"]) {
+            columns.push(BodyColumns {
+                position,
+                velocity: find_vector(name, ["_vx", "_vy", "_vz"]),
+            });
+            bodies.push(BodyTrajectory::with_name(name));
+        }
+    }
+    (bodies, columns)
+}
+
+impl TrajectoryData {
+    /// Load a trajectory file, binary or CSV, telling them apart by the
+    /// binary format's magic bytes
+    pub fn load<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        if binary::is_trajectory_file(&path)? {
+            Self::load_binary(path)
+        } else {
+            Self::load_csv(path)
+        }
+    }
+
+    /// Load a trajectory CSV written by the simulator
+    ///
+    /// Needs a `time` column and, per body, `<name>_x`, `<name>_y` and
+    /// `<name>_z` columns; bodies appear in the order of their `_x`
+    /// columns. `<name>_vx`/`_vy`/`_vz` and `energy` columns are read when
+    /// present and any other columns are ignored. `NaN` values, which mark
+    /// bodies that no longer exist, are kept as they are.
     pub fn load_csv<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
         let file = File::open(path)?;
         let mut reader = csv::ReaderBuilder::new()
             .has_headers(true)
             .from_reader(file);
 
+        let headers = reader
+            .headers()
+            .map_err(|e| invalid_data(e.to_string()))?
+            .clone();
+        let names: Vec<&str> = headers.iter().collect();
+        let find = |name: &str| names.iter().position(|h| h.trim() == name);
+
+        let time_column = find("time").ok_or_else(|| invalid_data(String::from("Missing time column")))?;
+        let energy_column = find("energy");
+
         let mut data = TrajectoryData::new();
-        let mut first_row = true;
+        let (bodies, columns) = find_bodies(&names);
+        data.bodies = bodies;
+
+        if data.bodies.is_empty() {
+            return Err(invalid_data(String::from("No bodies found in trajectory data")));
+        }
 
         for result in reader.records() {
-            let record = result.map_err(|e| {
-                std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string())
-            })?;
+            let record = result.map_err(|e| invalid_data(e.to_string()))?;
+            let field = |idx: usize| -> std::io::Result<f64> {
+                let text = record
+                    .get(idx)
+                    .ok_or_else(|| invalid_data(format!("Missing {} field", &headers[idx])))?;
+                text.trim()
+                    .parse()
+                    .map_err(|_| invalid_data(format!("Invalid {} value '{}'", &headers[idx], text)))
+            };
+            let vector = |idx: [usize; 3]| -> std::io::Result<Position> {
+                Ok(Position::new(field(idx[0])?, field(idx[1])?, field(idx[2])?))
+            };
 
-            // Initialize body trajectories on first row based on column count
-            if first_row {
-                // Format: time, body0_x, body0_y, body0_z, body1_x, body1_y, body1_z, ...
-                // Number of bodies = (num_fields - 1) / 3
-                let num_fields = record.len();
-                if num_fields < 4 {
-                    return Err(std::io::Error::new(
-                        std::io::ErrorKind::InvalidData,
-                        "CSV must have at least time and one body (4 columns)",
-                    ));
-                }
+            data.times.push(field(time_column)?);
 
-                let num_bodies = (num_fields - 1) / 3;
-                for _ in 0..num_bodies {
-                    data.bodies.push(BodyTrajectory::new());
+            for (body, body_columns) in data.bodies.iter_mut().zip(&columns) {
+                body.add_position(vector(body_columns.position)?);
+                if let Some(velocity) = body_columns.velocity {
+                    body.velocities.push(vector(velocity)?);
                 }
-                first_row = false;
             }
-
-            // Parse time and positions
-            let mut fields = record.iter();
-            let _time: f64 = fields
-                .next()
-                .ok_or_else(|| {
-                    std::io::Error::new(std::io::ErrorKind::InvalidData, "Missing time field")
-                })?
-                .parse()
-                .map_err(|_| {
-                    std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid time value")
-                })?;
-
-            // Parse body positions
-            for body in &mut data.bodies {
-                let x: f64 = fields
-                    .next()
-                    .ok_or_else(|| {
-                        std::io::Error::new(std::io::ErrorKind::InvalidData, "Missing x field")
-                    })?
-                    .parse()
-                    .map_err(|_| {
-                        std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid x value")
-                    })?;
-
-                let y: f64 = fields
-                    .next()
-                    .ok_or_else(|| {
-                        std::io::Error::new(std::io::ErrorKind::InvalidData, "Missing y field")
-                    })?
-                    .parse()
-                    .map_err(|_| {
-                        std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid y value")
-                    })?;
-
-                let z: f64 = fields
-                    .next()
-                    .ok_or_else(|| {
-                        std::io::Error::new(std::io::ErrorKind::InvalidData, "Missing z field")
-                    })?
-                    .parse()
-                    .map_err(|_| {
-                        std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid z value")
-                    })?;
-
-                body.add_position(Position::new(x, y, z));
+            if let Some(energy) = energy_column {
+                data.energies.push(field(energy)?);
             }
 
             data.num_frames += 1;
         }
 
+        Ok(data)
+    }
+
+    /// Load every frame of a binary trajectory file
+    ///
+    /// Bodies and optional columns are found by column name as in
+    /// `load_csv`.
+    pub fn load_binary<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let mut reader = TrajectoryReader::open(path)?;
+        let header = reader.header().clone();
+        let names: Vec<&str> = header.columns.iter().map(String::as_str).collect();
+        let energy_column = header.column("energy");
+
+        let mut data = TrajectoryData::new();
+        let (bodies, columns) = find_bodies(&names);
+        data.bodies = bodies;
         if data.bodies.is_empty() {
+            return Err(invalid_data(String::from("No bodies found in trajectory data")));
+        }
+
+        for frame in reader.frames() {
+            let values = frame?;
+            data.times.push(values[0]);
+            let vector = |idx: [usize; 3]| Position::new(values[idx[0]], values[idx[1]], values[idx[2]]);
+            for (body, body_columns) in data.bodies.iter_mut().zip(&columns) {
+                body.add_position(vector(body_columns.position));
+                if let Some(velocity) = body_columns.velocity {
+                    body.velocities.push(vector(velocity));
+                }
+            }
+            if let Some(energy) = energy_column {
+                data.energies.push(values[energy]);
+            }
+            data.num_frames += 1;
+        }
+
+        Ok(data)
+    }
+}
+
+impl TrajectoryData {
+    /// Save as a NumPy `.npz` archive
+    ///
+    /// Holds `time` (frames), `names` (bodies), `positions` (frames x
+    /// bodies x 3) and, when every body has them, `velocities` of the same
+    /// shape, plus `energy` (frames) when the trajectory has energies.
+    pub fn save_npz<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        let mut npz = NpzWriter::create(path)?;
+        npz.write_f64("time", &[self.num_frames], &self.times)?;
+        let names: Vec<String> = self.bodies.iter().map(|body| body.name.clone()).collect();
+        npz.write_strings("names", &names)?;
+        let shape = [self.num_frames, self.bodies.len(), 3];
+        let samples = |samples: fn(&BodyTrajectory) -> &[Position]| -> Vec<f64> {
+            (0..self.num_frames)
+                .flat_map(|frame| self.bodies.iter().map(move |body| samples(body)[frame]))
+                .flat_map(|p| p.to_array())
+                .collect()
+        };
+        npz.write_f64("positions", &shape, &samples(|body| &body.positions))?;
+        if self.bodies.iter().all(|body| body.velocities.len() == self.num_frames) {
+            npz.write_f64("velocities", &shape, &samples(|body| &body.velocities))?;
+        }
+        if self.energies.len() == self.num_frames {
+            npz.write_f64("energy", &[self.num_frames], &self.energies)?;
+        }
+        npz.finish()?;
+        Ok(())
+    }
+}
+
+/// Random access to the frames of a binary trajectory file
+///
+/// Only the header is read up front; each frame is read when asked for.
+/// The frame count covers the complete records when the file was opened,
+/// and `refresh` picks up frames written since by a running simulation.
+pub struct TrajectoryReader {
+    file: BufReader<File>,
+    header: TrajectoryHeader,
+    data_offset: u64,
+    num_frames: usize,
+    /// Frame the file is positioned at, to skip seeking when reading in order
+    next_frame: Option<usize>,
+}
+
+impl TrajectoryReader {
+    /// Open a binary trajectory file and read its header
+    pub fn open<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let mut file = BufReader::new(File::open(path)?);
+        let (header, data_offset) = TrajectoryHeader::read(&mut file)?;
+        let mut reader = TrajectoryReader {
+            file,
+            header,
+            data_offset,
+            num_frames: 0,
+            next_frame: None,
+        };
+        reader.refresh()?;
+        Ok(reader)
+    }
+
+    pub fn header(&self) -> &TrajectoryHeader {
+        &self.header
+    }
+
+    /// Number of complete frames in the file
+    pub fn num_frames(&self) -> usize {
+        self.num_frames
+    }
+
+    /// Count the frames again, for a file that is still being written
+    pub fn refresh(&mut self) -> std::io::Result<usize> {
+        let len = self.file.get_ref().metadata()?.len();
+        self.num_frames = (len.saturating_sub(self.data_offset) / self.header.record_size()) as usize;
+        Ok(self.num_frames)
+    }
+
+    /// Every value of one frame, laid out like `header().columns`
+    pub fn read_frame(&mut self, frame: usize) -> std::io::Result<Vec<f64>> {
+        if frame >= self.num_frames {
             return Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                "No bodies found in trajectory data",
+                std::io::ErrorKind::InvalidInput,
+                format!("frame {} is past the last of {} frames", frame, self.num_frames),
             ));
         }
+        if self.next_frame != Some(frame) {
+            let offset = self.data_offset + frame as u64 * self.header.record_size();
+            self.file.seek(SeekFrom::Start(offset))?;
+        }
+        self.next_frame = None;
+        let mut bytes = vec![0u8; self.header.record_size() as usize];
+        self.file.read_exact(&mut bytes)?;
+        self.next_frame = Some(frame + 1);
+        Ok(bytes
+            .chunks_exact(8)
+            .map(|chunk| f64::from_le_bytes(chunk.try_into().unwrap()))
+            .collect())
+    }
+
+    /// Time of one frame, in the file's units
+    pub fn time(&mut self, frame: usize) -> std::io::Result<f64> {
+        Ok(self.read_frame(frame)?[0])
+    }
+
+    /// Position of the named body in one frame, in the file's units
+    pub fn position(&mut self, frame: usize, name: &str) -> std::io::Result<[f64; 3]> {
+        let column = |axis: &str| {
+            self.header
+                .column(&format!("{}_{}", name, axis))
+                .ok_or_else(|| invalid_data(format!("No body named '{}'", name)))
+        };
+        let columns = [column("x")?, column("y")?, column("z")?];
+        let values = self.read_frame(frame)?;
+        Ok(columns.map(|idx| values[idx]))
+    }
+
+    /// Iterate over the frames in order, from the first
+    pub fn frames(&mut self) -> Frames<'_> {
+        Frames { reader: self, next: 0 }
+    }
+
+    /// Save every frame as a NumPy `.npz` archive, one frame at a time
+    ///
+    /// Holds `time` (frames), `positions` (frames x bodies x 3), `names` and
+    /// `masses` (bodies), `units` (`si`, `au` or `nbody`) and `unit_scales`
+    /// (the mass, length and time units in SI), plus whichever of
+    /// `velocities` (like `positions`), `energy` (frames) and
+    /// `angular_momentum` (frames x 3) the file has. Values are in the
+    /// file's units.
+    pub fn save_npz<P: AsRef<Path>>(&mut self, path: P) -> std::io::Result<()> {
+        let header = self.header.clone();
+        let frames = self.num_frames;
+        let vector_columns = |suffixes: [&str; 3]| -> Option<Vec<usize>> {
+            let mut columns = Vec::new();
+            for name in &header.names {
+                for suffix in suffixes {
+                    columns.push(header.column(&format!("{}{}", name, suffix))?);
+                }
+            }
+            Some(columns)
+        };
+        let mut arrays = vec![("time", vec![frames], vec![0])];
+        let bodies = header.names.len();
+        let positions = vector_columns(["_x", "_y", "_z"])
+            .ok_or_else(|| invalid_data(String::from("No bodies found in trajectory data")))?;
+        arrays.push(("positions", vec![frames, bodies, 3], positions));
+        if let Some(velocities) = vector_columns(["_vx", "_vy", "_vz"]) {
+            arrays.push(("velocities", vec![frames, bodies, 3], velocities));
+        }
+        if let Some(energy) = header.column("energy") {
+            arrays.push(("energy", vec![frames], vec![energy]));
+        }
+        if let (Some(x), Some(y), Some(z)) = (header.column("Lx"), header.column("Ly"), header.column("Lz")) {
+            arrays.push(("angular_momentum", vec![frames, 3], vec![x, y, z]));
+        }
 
-        Ok(data)
+        let mut npz = NpzWriter::create(path)?;
+        for (name, shape, columns) in arrays {
+            npz.write_f64_with(name, &shape, |array| {
+                for frame in self.frames() {
+                    let values = frame?;
+                    for &column in &columns {
+                        array.push(values[column])?;
+                    }
+                }
+                Ok(())
+            })?;
+        }
+        npz.write_strings("names", &header.names)?;
+        npz.write_f64("masses", &[bodies], &header.masses)?;
+        npz.write_string("units", header.units.name())?;
+        let units = header.units;
+        npz.write_f64("unit_scales", &[3], &[units.mass(), units.length(), units.time()])?;
+        npz.finish()?;
+        Ok(())
+    }
+}
+
+/// Iterator over the frames of a `TrajectoryReader`
+pub struct Frames<'a> {
+    reader: &'a mut TrajectoryReader,
+    next: usize,
+}
+
+impl Iterator for Frames<'_> {
+    type Item = std::io::Result<Vec<f64>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next >= self.reader.num_frames {
+            return None;
+        }
+        self.next += 1;
+        Some(self.reader.read_frame(self.next - 1))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let left = self.reader.num_frames.saturating_sub(self.next);
+        (left, Some(left))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_support::{earth_moon, TempFile};
 
     #[test]
     fn test_position_creation() {
@@ -186,4 +667,232 @@ mod tests {
         assert_eq!(traj.get_position(0).unwrap().x, 0.0);
         assert_eq!(traj.get_position(1).unwrap().y, 2.0);
     }
+
+    #[test]
+    fn test_load_csv_reads_body_names() {
+        let path = TempFile::new("trajectory_names.csv");
+        std::fs::write(
+            &path,
+            "time,Sun_x,Sun_y,Sun_z,body1_x,body1_y,body1_z\n\
+             0,0,0,0,1,2,3\n\
+             10,0,0,0,4,5,6\n",
+        )
+        .unwrap();
+
+        let data = TrajectoryData::load_csv(&path).unwrap();
+        assert_eq!(data.num_frames, 2);
+        assert_eq!(data.bodies[0].name, "Sun");
+        assert_eq!(data.bodies[1].name, "body1");
+        assert_eq!(data.bodies[1].get_position(1).unwrap().z, 6.0);
+        assert_eq!(data.times, [0.0, 10.0]);
+    }
+
+    #[test]
+    fn test_frame_lookup_by_time() {
+        let data = TrajectoryData {
+            times: vec![0.0, 10.0, 20.0, 40.0],
+            num_frames: 4,
+            ..TrajectoryData::new()
+        };
+        assert_eq!(data.time(3), Some(40.0));
+        assert_eq!(data.time(4), None);
+        assert_eq!(data.frame_at(-1.0), None);
+        assert_eq!(data.frame_at(0.0), Some(0));
+        assert_eq!(data.frame_at(39.9), Some(2));
+        assert_eq!(data.frame_at(1e9), Some(3));
+        assert_eq!(data.nearest_frame(-5.0), Some(0));
+        assert_eq!(data.nearest_frame(14.0), Some(1));
+        assert_eq!(data.nearest_frame(15.0), Some(1));
+        assert_eq!(data.nearest_frame(31.0), Some(3));
+        assert_eq!(data.nearest_frame(100.0), Some(3));
+        assert_eq!(TrajectoryData::new().nearest_frame(1.0), None);
+    }
+
+    #[test]
+    fn test_position_at_interpolates() {
+        let times = [0.0, 1.0, 2.0];
+        // x = t^3, which cubic Hermite curves reproduce given the velocities
+        let mut body = BodyTrajectory::with_name("Probe");
+        body.positions = times.iter().map(|&t: &f64| Position::new(t.powi(3), 2.0 * t, 0.0)).collect();
+        let linear = body.position_at(&times, 0.5, Interpolation::Linear).unwrap();
+        assert_eq!((linear.x, linear.y), (0.5, 1.0));
+        assert_eq!(body.position_at(&times, 1.0, Interpolation::Hermite), body.get_position(1));
+        assert_eq!(body.position_at(&times, 2.0, Interpolation::Hermite), body.get_position(2));
+        assert!(body.position_at(&times, -0.1, Interpolation::Linear).is_none());
+        assert!(body.position_at(&times, 2.1, Interpolation::Hermite).is_none());
+        assert!(body.velocity_at(&times, 0.5).is_none());
+
+        body.velocities = times.iter().map(|&t| Position::new(3.0 * t * t, 2.0, 0.0)).collect();
+        let cubic = body.position_at(&times, 1.5, Interpolation::Hermite).unwrap();
+        assert!((cubic.x - 3.375).abs() < 1e-12 && (cubic.y - 3.0).abs() < 1e-12);
+        assert_eq!(body.velocity_at(&times, 1.5).unwrap().x, 7.5);
+
+        // Without velocities the slopes between frames stand in, which
+        // follows a circle far more closely than straight lines
+        let times: Vec<f64> = (0..=20).map(|i| i as f64 * 0.5).collect();
+        let mut circle = BodyTrajectory::with_name("Moon");
+        circle.positions = times.iter().map(|t| Position::new(t.cos(), t.sin(), 0.0)).collect();
+        let error = |method| {
+            let p = circle.position_at(&times, 4.25, method).unwrap();
+            (p.x - 4.25f64.cos()).hypot(p.y - 4.25f64.sin())
+        };
+        assert!(error(Interpolation::Hermite) < error(Interpolation::Linear) / 5.0);
+    }
+
+    #[test]
+    fn test_resample() {
+        let mut body = BodyTrajectory::with_name("Probe");
+        body.positions = vec![Position::new(0.0, 0.0, 0.0), Position::new(10.0, 0.0, 0.0)];
+        body.velocities = vec![Position::new(1.0, 0.0, 0.0), Position::new(1.0, 0.0, 0.0)];
+        let data = TrajectoryData {
+            bodies: vec![body],
+            num_frames: 2,
+            times: vec![0.0, 10.0],
+            energies: vec![-1.0, -2.0],
+        };
+        let resampled = data.resample(&[2.5, 10.0, 12.0], Interpolation::Hermite);
+        assert_eq!(resampled.num_frames, 3);
+        assert_eq!(resampled.times, [2.5, 10.0, 12.0]);
+        assert!((resampled.bodies[0].positions[0].x - 2.5).abs() < 1e-12);
+        assert_eq!(resampled.bodies[0].positions[1].x, 10.0);
+        assert!(!resampled.bodies[0].positions[2].is_finite());
+        assert_eq!(resampled.bodies[0].velocities[0].x, 1.0);
+        assert_eq!(&resampled.energies[..2], [-1.25, -2.0]);
+        assert!(resampled.energies[2].is_nan());
+        assert_eq!(data.position_at(0, 5.0, Interpolation::Linear), Some(Position::new(5.0, 0.0, 0.0)));
+        assert_eq!(data.position_at(1, 5.0, Interpolation::Linear), None);
+    }
+
+    #[test]
+    fn test_load_csv_finds_columns_by_name() {
+        let path = TempFile::new("trajectory_columns.csv");
+        std::fs::write(
+            &path,
+            "time,Sun_x,Sun_y,Sun_z,Sun_vx,Sun_vy,Sun_vz,Sun_ke,Moon_x,Moon_y,Moon_z,energy,Lx,Ly,Lz\n\
+             0,1,2,3,4,5,6,7,8,9,10,-11,0,0,1\n",
+        )
+        .unwrap();
+
+        let data = TrajectoryData::load_csv(&path).unwrap();
+        assert_eq!(data.bodies.len(), 2);
+        assert_eq!(data.bodies[0].get_position(0).unwrap().z, 3.0);
+        assert_eq!(data.bodies[0].get_velocity(0).unwrap().x, 4.0);
+        assert_eq!(data.bodies[1].name, "Moon");
+        assert_eq!(data.bodies[1].get_position(0).unwrap().x, 8.0);
+        assert!(data.bodies[1].velocities.is_empty());
+        assert_eq!(data.energies, [-11.0]);
+    }
+
+    #[test]
+    fn test_load_simulator_output_with_all_columns() {
+        use crate::{OutputColumns, Simulator};
+
+        let path = TempFile::new("trajectory_roundtrip.csv");
+                let mut sim = Simulator::with_output(earth_moon(), 3600.0, path.to_str().unwrap()).unwrap();
+        sim.set_output_columns(OutputColumns::all());
+        sim.run(3);
+        let velocity = sim.bodies()[1].velocity;
+        let energy = sim.total_energy();
+        drop(sim);
+
+        let data = TrajectoryData::load_csv(&path).unwrap();
+        assert_eq!(data.num_frames, 3);
+        assert_eq!(data.bodies[1].velocities.len(), 3);
+        assert!(((data.bodies[1].get_velocity(2).unwrap().y - velocity[1]) / velocity[1]).abs() < 1e-9);
+        assert_eq!(data.times, [3600.0, 7200.0, 10800.0]);
+        assert!(((data.energies[2] - energy) / energy).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_load_binary_matches_csv() {
+        use crate::{OutputColumns, Simulator};
+
+        let csv_path = TempFile::new("trajectory_binary.csv");
+        let binary_path = TempFile::new("trajectory_binary.traj");
+                let mut sim = Simulator::with_output(earth_moon(), 3600.0, csv_path.to_str().unwrap()).unwrap();
+        sim.set_output_columns(OutputColumns { velocity: true, total_energy: true, ..OutputColumns::default() });
+        sim.enable_binary_output(binary_path.to_str().unwrap()).unwrap();
+        sim.run(5);
+        drop(sim);
+
+        // A record cut short by an interrupted run is ignored
+        let mut bytes = std::fs::read(&binary_path).unwrap();
+        bytes.extend([0u8; 12]);
+        std::fs::write(&binary_path, bytes).unwrap();
+
+        let from_csv = TrajectoryData::load(&csv_path).unwrap();
+        let from_binary = TrajectoryData::load(&binary_path).unwrap();
+        assert_eq!(from_binary.num_frames, 5);
+        assert_eq!(from_binary.bodies[1].name, "Moon");
+        assert_eq!(from_binary.bodies[1].velocities.len(), 5);
+        assert_eq!(from_binary.times, from_csv.times);
+        // The CSV rounds positions to 8 decimal places
+        for (a, b) in from_csv.bodies[1].positions.iter().zip(&from_binary.bodies[1].positions) {
+            assert!((a.x - b.x).abs() < 1e-7 && (a.y - b.y).abs() < 1e-7 && (a.z - b.z).abs() < 1e-7);
+        }
+        assert!(((from_binary.energies[4] - from_csv.energies[4]) / from_csv.energies[4]).abs() < 1e-10);
+    }
+
+    /// Name, `.npy` header and data of each array in an `.npz` archive
+    fn npz_arrays(bytes: &[u8]) -> Vec<(String, String, Vec<u8>)> {
+        let mut arrays = Vec::new();
+        let mut at = 0;
+        while bytes[at..at + 4] == 0x0403_4b50u32.to_le_bytes() {
+            let field = |offset: usize| u32::from_le_bytes(bytes[at + offset..at + offset + 4].try_into().unwrap());
+            let size = field(18) as usize;
+            let name_len = u16::from_le_bytes([bytes[at + 26], bytes[at + 27]]) as usize;
+            let name = String::from_utf8(bytes[at + 30..at + 30 + name_len].to_vec()).unwrap();
+            let npy = &bytes[at + 30 + name_len..at + 30 + name_len + size];
+            let header_len = u16::from_le_bytes([npy[8], npy[9]]) as usize;
+            let header = String::from_utf8(npy[10..10 + header_len].to_vec()).unwrap();
+            arrays.push((name, header, npy[10 + header_len..].to_vec()));
+            at += 30 + name_len + size;
+        }
+        arrays
+    }
+
+    fn f64_values(data: &[u8]) -> Vec<f64> {
+        data.chunks_exact(8).map(|chunk| f64::from_le_bytes(chunk.try_into().unwrap())).collect()
+    }
+
+    #[test]
+    fn test_save_npz() {
+        use crate::{OutputColumns, Simulator, UnitSystem};
+
+        let binary_path = TempFile::new("trajectory_npz.traj");
+        let from_binary_path = TempFile::new("trajectory_binary.npz");
+        let from_data_path = TempFile::new("trajectory_data.npz");
+                let mut sim = Simulator::new(earth_moon(), 3600.0);
+        sim.set_output_columns(OutputColumns { velocity: true, angular_momentum: true, ..OutputColumns::default() });
+        sim.set_output_units(UnitSystem::Astronomical);
+        sim.enable_binary_output(binary_path.to_str().unwrap()).unwrap();
+        sim.run(4);
+        let moon = sim.bodies()[1].clone();
+        drop(sim);
+
+        TrajectoryReader::open(&binary_path).unwrap().save_npz(&from_binary_path).unwrap();
+        TrajectoryData::load(&binary_path).unwrap().save_npz(&from_data_path).unwrap();
+        let from_binary = npz_arrays(&std::fs::read(&from_binary_path).unwrap());
+        let from_data = npz_arrays(&std::fs::read(&from_data_path).unwrap());
+
+        let names: Vec<&str> = from_binary.iter().map(|(name, _, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "time.npy", "positions.npy", "velocities.npy", "angular_momentum.npy",
+                "names.npy", "masses.npy", "units.npy", "unit_scales.npy",
+            ]
+        );
+        let array = |name: &str| from_binary.iter().find(|(n, _, _)| n == name).unwrap();
+        assert!(array("positions.npy").1.contains("'shape': (4, 2, 3)"));
+        assert_eq!(f64_values(&array("time.npy").2), [1.0 / 24.0, 2.0 / 24.0, 3.0 / 24.0, 4.0 / 24.0]);
+        let positions = f64_values(&array("positions.npy").2);
+        assert_eq!(positions[4 * 6 - 3], moon.position[0] / crate::units::ASTRONOMICAL_UNIT);
+        assert!(array("units.npy").1.contains("'<U2', 'fortran_order': False, 'shape': ()"));
+
+        let names: Vec<&str> = from_data.iter().map(|(name, _, _)| name.as_str()).collect();
+        assert_eq!(names, ["time.npy", "names.npy", "positions.npy", "velocities.npy"]);
+        assert_eq!(from_data[0].2, array("time.npy").2);
+        assert_eq!(from_data[2].2, array("positions.npy").2);
+    }
 }
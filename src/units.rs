@@ -0,0 +1,238 @@
+//! Unit systems for configuration input and trajectory output
+//!
+//! The simulator works in SI internally. A `UnitSystem` describes the
+//! units of a configuration file or an output file by the size of its
+//! mass, length and time units in SI, and values are converted when they
+//! are read or written:
+//!
+//! - `UnitSystem::Si`: metres, kilograms and seconds
+//! - `UnitSystem::Astronomical`: astronomical units, solar masses and days,
+//!   in which G is about 2.959e-4
+//! - `UnitSystem::NBody`: units with G = 1 for a chosen mass and length
+//!   unit; the time unit follows from them. Both default to 1 (kg and m).
+
+use crate::body::Body;
+use crate::simulator::G;
+use std::fmt;
+use std::str::FromStr;
+
+/// The astronomical unit (m)
+pub const ASTRONOMICAL_UNIT: f64 = 1.495_978_707e11;
+/// The nominal solar mass (kg)
+pub const SOLAR_MASS: f64 = 1.988_47e30;
+/// One day (s)
+pub const DAY: f64 = 86400.0;
+
+/// Units of mass, length and time
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum UnitSystem {
+    /// Metres, kilograms and seconds
+    #[default]
+    Si,
+    /// Astronomical units, solar masses and days
+    Astronomical,
+    /// G = 1 with the given mass unit (kg) and length unit (m)
+    NBody { mass: f64, length: f64 },
+}
+
+impl UnitSystem {
+    /// N-body units with the given mass unit (kg) and length unit (m)
+    pub fn nbody(mass: f64, length: f64) -> Self {
+        UnitSystem::NBody { mass, length }
+    }
+
+    /// Name used in configuration files and messages
+    pub fn name(&self) -> &'static str {
+        match self {
+            UnitSystem::Si => "si",
+            UnitSystem::Astronomical => "au",
+            UnitSystem::NBody { .. } => "nbody",
+        }
+    }
+
+    /// The mass unit in kilograms
+    pub fn mass(&self) -> f64 {
+        match *self {
+            UnitSystem::Si => 1.0,
+            UnitSystem::Astronomical => SOLAR_MASS,
+            UnitSystem::NBody { mass, .. } => mass,
+        }
+    }
+
+    /// The length unit in metres
+    pub fn length(&self) -> f64 {
+        match *self {
+            UnitSystem::Si => 1.0,
+            UnitSystem::Astronomical => ASTRONOMICAL_UNIT,
+            UnitSystem::NBody { length, .. } => length,
+        }
+    }
+
+    /// The time unit in seconds
+    pub fn time(&self) -> f64 {
+        match *self {
+            UnitSystem::Si => 1.0,
+            UnitSystem::Astronomical => DAY,
+            UnitSystem::NBody { mass, length } => (length * length * length / (G * mass)).sqrt(),
+        }
+    }
+
+    /// The velocity unit in m/s
+    pub fn velocity(&self) -> f64 {
+        self.length() / self.time()
+    }
+
+    /// The acceleration unit in m/s^2
+    pub fn acceleration(&self) -> f64 {
+        self.velocity() / self.time()
+    }
+
+    /// The energy unit in joules
+    pub fn energy(&self) -> f64 {
+        self.mass() * self.velocity() * self.velocity()
+    }
+
+    /// The angular momentum unit in kg m^2/s
+    pub fn angular_momentum(&self) -> f64 {
+        self.mass() * self.length() * self.velocity()
+    }
+
+    /// The gravitational constant in these units
+    pub fn gravitational_constant(&self) -> f64 {
+        match self {
+            UnitSystem::NBody { .. } => 1.0,
+            _ => G * self.mass() * self.time() * self.time() / self.length().powi(3),
+        }
+    }
+
+    /// Convert a body given in these units to SI
+    pub fn body_to_si(&self, body: &Body) -> Body {
+        self.scale_body(body, 1.0)
+    }
+
+    /// Convert a body given in SI to these units
+    pub fn body_from_si(&self, body: &Body) -> Body {
+        self.scale_body(body, -1.0)
+    }
+
+    /// Multiply every quantity of `body` by its unit raised to `power`
+    fn scale_body(&self, body: &Body, power: f64) -> Body {
+        let (length, velocity, acceleration) = (
+            self.length().powf(power),
+            self.velocity().powf(power),
+            self.acceleration().powf(power),
+        );
+        let mut scaled = body.clone();
+        scaled.mass *= self.mass().powf(power);
+        scaled.radius *= length;
+        scaled.position = body.position.map(|x| x * length);
+        scaled.velocity = body.velocity.map(|v| v * velocity);
+        scaled.acceleration = body.acceleration.map(|a| a * acceleration);
+        scaled
+    }
+}
+
+impl FromStr for UnitSystem {
+    type Err = String;
+
+    /// Parse `si`, `au`, `nbody`, or `nbody <mass unit> <length unit>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split_whitespace();
+        let kind = parts.next().unwrap_or("").to_lowercase().replace('_', "-");
+        let rest: Vec<&str> = parts.collect();
+        match (kind.as_str(), rest.as_slice()) {
+            ("si" | "mks", []) => Ok(UnitSystem::Si),
+            ("au" | "astronomical", []) => Ok(UnitSystem::Astronomical),
+            ("nbody" | "n-body", []) => Ok(UnitSystem::nbody(1.0, 1.0)),
+            ("nbody" | "n-body", [mass, length]) => {
+                let positive = |value: &str| {
+                    value
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|number| *number > 0.0 && number.is_finite())
+                        .ok_or_else(|| format!("unit '{}' is not a positive number", value))
+                };
+                Ok(UnitSystem::nbody(positive(mass)?, positive(length)?))
+            }
+            ("nbody" | "n-body", _) => {
+                Err(String::from("expected 'nbody <mass unit> <length unit>'"))
+            }
+            ("si" | "mks" | "au" | "astronomical", [extra, ..]) => {
+                Err(format!("unexpected '{}' after {}", extra, kind))
+            }
+            (other, _) => Err(format!(
+                "unknown unit system '{}' (expected si, au or nbody)",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for UnitSystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnitSystem::Si => f.write_str("SI (m, kg, s)"),
+            UnitSystem::Astronomical => f.write_str("AU, solar masses, days"),
+            UnitSystem::NBody { mass, length } => {
+                write!(
+                    f,
+                    "N-body (G = 1, mass unit {} kg, length unit {} m)",
+                    mass, length
+                )
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gravitational_constant() {
+        assert_eq!(UnitSystem::Si.gravitational_constant(), G);
+        // The square of the Gaussian gravitational constant, 0.01720209895
+        let g = UnitSystem::Astronomical.gravitational_constant();
+        assert!((g / 0.01720209895f64.powi(2) - 1.0).abs() < 1e-4, "{}", g);
+
+        let nbody = UnitSystem::nbody(SOLAR_MASS, ASTRONOMICAL_UNIT);
+        let derived = G * nbody.mass() * nbody.time().powi(2) / nbody.length().powi(3);
+        assert!((derived - 1.0).abs() < 1e-12);
+        // A year is about 2 pi time units for the Sun and an AU
+        assert!((365.25 * DAY / nbody.time() / std::f64::consts::TAU - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_body_round_trip() {
+        let body = Body::new(3e-6, [1.0, 0.0, 0.0], [0.0, 0.0172, 0.0]).with_radius(4.3e-5);
+        let si = UnitSystem::Astronomical.body_to_si(&body);
+        assert!((si.position[0] - ASTRONOMICAL_UNIT).abs() < 1e-3);
+        assert!((si.velocity[1] - 0.0172 * ASTRONOMICAL_UNIT / DAY).abs() < 1e-9);
+        assert!((si.mass - 3e-6 * SOLAR_MASS).abs() < 1e10);
+
+        let back = UnitSystem::Astronomical.body_from_si(&si);
+        assert!((back.velocity[1] - 0.0172).abs() < 1e-15);
+        assert!((back.radius - 4.3e-5).abs() < 1e-18);
+    }
+
+    #[test]
+    fn test_parse_unit_system() {
+        assert_eq!("SI".parse::<UnitSystem>().unwrap(), UnitSystem::Si);
+        assert_eq!(
+            "au".parse::<UnitSystem>().unwrap(),
+            UnitSystem::Astronomical
+        );
+        assert_eq!(
+            "nbody".parse::<UnitSystem>().unwrap(),
+            UnitSystem::nbody(1.0, 1.0)
+        );
+        assert_eq!(
+            "n_body 2e30 1.5e11".parse::<UnitSystem>().unwrap(),
+            UnitSystem::nbody(2e30, 1.5e11)
+        );
+        assert!("nbody 2e30".parse::<UnitSystem>().is_err());
+        assert!("nbody -1 1".parse::<UnitSystem>().is_err());
+        assert!("au 1".parse::<UnitSystem>().is_err());
+        assert!("cgs".parse::<UnitSystem>().is_err());
+    }
+}
@@ -0,0 +1,33 @@
+//! Helpers for 3-vectors stored as `[f64; 3]`
+//!
+//! Bodies keep their positions, velocities and accelerations as plain
+//! arrays; these are the few operations the physics modules share.
+
+pub(crate) fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+pub(crate) fn cross(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+pub(crate) fn norm(v: [f64; 3]) -> f64 {
+    dot(v, v).sqrt()
+}
+
+pub(crate) fn scale(v: [f64; 3], factor: f64) -> [f64; 3] {
+    v.map(|component| component * factor)
+}
+
+/// `a - b`
+pub(crate) fn difference(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+pub(crate) fn distance(a: [f64; 3], b: [f64; 3]) -> f64 {
+    norm(difference(a, b))
+}